@@ -0,0 +1,159 @@
+use crate::outbox::{JobStatus, JobSummary};
+use crate::suppression::{Suppression, SuppressionReason};
+use crate::{config, templates, AppState};
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+// --- Suppression List ---
+
+#[derive(Serialize)]
+pub struct SuppressionEntry {
+    pub address_hash: String,
+    pub reason: SuppressionReason,
+    pub status: Option<String>,
+    pub diagnostic: Option<String>,
+    pub created_at: u64,
+}
+
+pub async fn list_suppressions(State(state): State<AppState>) -> Json<Vec<SuppressionEntry>> {
+    let entries = state
+        .suppressions
+        .list()
+        .into_iter()
+        .map(|(address_hash, s): (String, Suppression)| SuppressionEntry {
+            address_hash,
+            reason: s.reason,
+            status: s.status,
+            diagnostic: s.diagnostic,
+            created_at: s.created_at,
+        })
+        .collect();
+
+    Json(entries)
+}
+
+#[derive(Deserialize)]
+pub struct SuppressRequest {
+    pub email: String,
+}
+
+pub async fn add_suppression(
+    State(state): State<AppState>,
+    Json(payload): Json<SuppressRequest>,
+) -> Result<String, (StatusCode, String)> {
+    state
+        .suppressions
+        .add(&payload.email, SuppressionReason::Manual, None, None)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    tracing::info!("Suppression added by operator");
+    Ok("Suppressed".to_string())
+}
+
+/// `key` is either the address itself or the hash shown by the listing.
+pub async fn remove_suppression(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let removed = state
+        .suppressions
+        .remove(&key)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    if !removed {
+        return Err((StatusCode::NOT_FOUND, "Not suppressed".to_string()));
+    }
+
+    tracing::info!("Suppression removed by operator");
+    Ok("Removed".to_string())
+}
+
+// --- SMTP Upstreams ---
+
+#[derive(Serialize)]
+pub struct UpstreamStatus {
+    pub name: String,
+    pub host: String,
+    pub circuit: &'static str,
+    pub sent: u64,
+    pub failures: u64,
+    pub quota_skips: u64,
+}
+
+pub async fn list_upstreams(State(state): State<AppState>) -> Json<Vec<UpstreamStatus>> {
+    let upstreams = state
+        .upstreams
+        .upstreams
+        .iter()
+        .map(|u| UpstreamStatus {
+            name: u.name.clone(),
+            host: u.host.clone(),
+            circuit: u.circuit().as_str(),
+            sent: u.sent.load(Ordering::Relaxed),
+            failures: u.failures.load(Ordering::Relaxed),
+            quota_skips: u.quota_skips.load(Ordering::Relaxed),
+        })
+        .collect();
+
+    Json(upstreams)
+}
+
+pub async fn metrics(State(state): State<AppState>) -> String {
+    state.upstreams.render_metrics()
+}
+
+// --- Outbox ---
+
+#[derive(Deserialize)]
+pub struct JobFilter {
+    pub status: Option<JobStatus>,
+}
+
+pub async fn list_jobs(State(state): State<AppState>, Query(filter): Query<JobFilter>) -> Json<Vec<JobSummary>> {
+    Json(state.outbox.list(filter.status))
+}
+
+pub async fn retry_job(State(state): State<AppState>, Path(id): Path<u64>) -> Result<String, (StatusCode, String)> {
+    match state.outbox.retry(Some(id)) {
+        0 => Err((StatusCode::NOT_FOUND, "No retryable dead-letter job with that id".to_string())),
+        _ => Ok("Re-queued".to_string()),
+    }
+}
+
+pub async fn retry_all(State(state): State<AppState>) -> String {
+    format!("Re-queued {}", state.outbox.retry(None))
+}
+
+pub async fn purge_job(State(state): State<AppState>, Path(id): Path<u64>) -> Result<String, (StatusCode, String)> {
+    match state.outbox.purge(Some(id)) {
+        0 => Err((StatusCode::NOT_FOUND, "No purgeable job with that id".to_string())),
+        _ => Ok("Purged".to_string()),
+    }
+}
+
+pub async fn purge_dead(State(state): State<AppState>) -> String {
+    format!("Purged {}", state.outbox.purge(None))
+}
+
+#[derive(Deserialize)]
+pub struct TestEmailRequest {
+    pub to: String,
+}
+
+pub async fn send_test_email(State(state): State<AppState>, Json(payload): Json<TestEmailRequest>) -> String {
+    let content = templates::test_email();
+    let body = crate::alternative_body(content.text, content.html);
+    let id = state.outbox.enqueue("test", payload.to, content.subject, body, false);
+    format!("Queued job {}", id)
+}
+
+// --- Config ---
+
+pub async fn config() -> Json<BTreeMap<&'static str, String>> {
+    Json(config::effective())
+}
@@ -0,0 +1,48 @@
+use axum::{extract::Request, http::StatusCode, middleware::Next, response::Response};
+use std::env;
+
+/// Guards operator endpoints. The admin API is disabled unless `ADMIN_TOKEN` is set.
+pub async fn require_admin(req: Request, next: Next) -> Result<Response, StatusCode> {
+    check_bearer(&req, "ADMIN_TOKEN")?;
+    Ok(next.run(req).await)
+}
+
+/// Guards the webhook the mail provider posts bounce reports to (`INBOUND_TOKEN`).
+pub async fn require_inbound(req: Request, next: Next) -> Result<Response, StatusCode> {
+    check_bearer(&req, "INBOUND_TOKEN")?;
+    Ok(next.run(req).await)
+}
+
+/// Guards calendar invites (`INVITE_TOKEN`), so the relay can't be used to
+/// mail arbitrary text to arbitrary attendees.
+pub async fn require_invite(req: Request, next: Next) -> Result<Response, StatusCode> {
+    check_bearer(&req, "INVITE_TOKEN")?;
+    Ok(next.run(req).await)
+}
+
+fn check_bearer(req: &Request, var: &str) -> Result<(), StatusCode> {
+    let expected = env::var(var).map_err(|_| StatusCode::FORBIDDEN)?;
+    if expected.is_empty() {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let provided = req
+        .headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+
+    if constant_time_eq(provided.as_bytes(), expected.as_bytes()) {
+        Ok(())
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,151 @@
+use crate::suppression::SuppressionReason;
+use mail_parser::{MessageParser, MimeHeaders, PartType};
+
+/// One recipient the upstream told us to stop mailing.
+pub struct BounceEvent {
+    pub recipient: String,
+    pub reason: SuppressionReason,
+    pub status: Option<String>,
+    pub diagnostic: Option<String>,
+}
+
+/// Extract hard bounces (RFC 3464 DSNs) and complaints (RFC 5965 ARF reports) from a raw message.
+/// Soft bounces and delayed notices are ignored; the provider keeps retrying those itself.
+pub fn parse_report(raw: &[u8]) -> Vec<BounceEvent> {
+    let Some(message) = MessageParser::default().parse(raw) else {
+        return Vec::new();
+    };
+
+    let mut events = Vec::new();
+
+    for part in &message.parts {
+        let Some(ct) = part.content_type() else {
+            continue;
+        };
+        if !ct.ctype().eq_ignore_ascii_case("message") {
+            continue;
+        }
+
+        match ct.subtype().map(|s| s.to_lowercase()).as_deref() {
+            Some("delivery-status") | Some("global-delivery-status") => {
+                events.extend(parse_delivery_status(&String::from_utf8_lossy(part.contents())));
+            }
+            Some("feedback-report") => {
+                let fields = parse_fields(&String::from_utf8_lossy(part.contents()));
+                let recipient = field(&fields, "original-rcpt-to")
+                    .map(|value| strip_address_type(&value))
+                    .or_else(|| complained_message_recipient(&message));
+
+                if let Some(recipient) = recipient {
+                    events.push(BounceEvent {
+                        recipient,
+                        reason: SuppressionReason::Complaint,
+                        status: None,
+                        diagnostic: field(&fields, "feedback-type"),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    events
+}
+
+fn parse_delivery_status(body: &str) -> Vec<BounceEvent> {
+    // The first block describes the reporting MTA, the rest are one block per recipient
+    parse_fields(body)
+        .iter()
+        .skip(1)
+        .filter_map(|block| {
+            let lookup = |name: &str| {
+                block
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, v)| v.clone())
+            };
+
+            let action = lookup("action")?.to_lowercase();
+            let status = lookup("status")?;
+            if action != "failed" || !status.starts_with('5') {
+                return None;
+            }
+
+            let recipient = lookup("final-recipient").or_else(|| lookup("original-recipient"))?;
+            let recipient = strip_address_type(&recipient);
+
+            // Diagnostics usually echo the address back; we only keep hashes
+            let diagnostic = lookup("diagnostic-code").map(|d| d.replace(&recipient, "<redacted>"));
+
+            Some(BounceEvent {
+                recipient,
+                reason: SuppressionReason::HardBounce,
+                status: Some(status),
+                diagnostic,
+            })
+        })
+        .collect()
+}
+
+// ARF reports without Original-Rcpt-To still carry the original message we sent
+fn complained_message_recipient(message: &mail_parser::Message) -> Option<String> {
+    message.parts.iter().find_map(|part| match &part.body {
+        PartType::Message(original) => original
+            .to()
+            .and_then(|to| to.first())
+            .and_then(|addr| addr.address())
+            .map(|addr| addr.to_string()),
+        _ => None,
+    })
+}
+
+/// Split a header-style body into blank-line separated blocks of (lowercased name, value).
+fn parse_fields(body: &str) -> Vec<Vec<(String, String)>> {
+    let mut blocks = Vec::new();
+    let mut block: Vec<(String, String)> = Vec::new();
+
+    for line in body.lines() {
+        if line.trim().is_empty() {
+            if !block.is_empty() {
+                blocks.push(std::mem::take(&mut block));
+            }
+            continue;
+        }
+
+        // Folded continuation line
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let Some(last) = block.last_mut() {
+                last.1.push(' ');
+                last.1.push_str(line.trim());
+            }
+            continue;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            block.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+    blocks
+}
+
+fn field(blocks: &[Vec<(String, String)>], name: &str) -> Option<String> {
+    blocks
+        .iter()
+        .flatten()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.clone())
+}
+
+// "rfc822; user@example.com" -> "user@example.com"
+fn strip_address_type(value: &str) -> String {
+    let address = value.split_once(';').map_or(value, |(_, addr)| addr);
+    address
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .to_string()
+}
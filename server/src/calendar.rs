@@ -0,0 +1,287 @@
+use crate::auth::constant_time_eq;
+use crate::store::JsonStore;
+use chrono::{DateTime, FixedOffset, Utc};
+use lettre::Address;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InviteMethod {
+    Request,
+    Update,
+    Cancel,
+}
+
+#[derive(Deserialize)]
+pub struct Person {
+    pub email: String,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EventRequest {
+    /// Leave empty for a new event; required for updates and cancellations
+    pub uid: Option<String>,
+    /// Returned when the event was created; required for updates and cancellations
+    pub secret: Option<String>,
+    pub method: InviteMethod,
+    pub summary: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    /// RFC 3339 with the offset that applies in `timezone`, e.g. `2026-10-20T10:00:00+02:00`
+    pub start: String,
+    pub end: String,
+    /// IANA name, used for display and as `X-WR-TIMEZONE`
+    pub timezone: String,
+    pub organizer: Person,
+    pub attendees: Vec<Person>,
+}
+
+/// Parsed and validated event, ready to render.
+pub struct Event {
+    pub uid: String,
+    pub sequence: u32,
+    pub method: InviteMethod,
+    pub summary: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    pub timezone: String,
+    pub organizer: Person,
+    pub attendees: Vec<Person>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct EventRecord {
+    sequence: u32,
+    /// SHA-256 of the event's secret
+    #[serde(default)]
+    secret_hash: String,
+    cancelled: bool,
+}
+
+/// Remembers the last sequence number per UID so updates are accepted by calendar clients.
+pub struct EventLog {
+    records: JsonStore<EventRecord>,
+}
+
+impl EventLog {
+    pub fn open() -> Self {
+        Self {
+            records: JsonStore::open("events.json"),
+        }
+    }
+
+    /// Validate the request and assign its UID and SEQUENCE. New events also
+    /// get the secret that later updates and cancellations have to present.
+    pub fn prepare(&self, req: EventRequest) -> Result<(Event, Option<String>), String> {
+        let start = DateTime::parse_from_rfc3339(&req.start).map_err(|e| format!("Invalid start: {}", e))?;
+        let end = DateTime::parse_from_rfc3339(&req.end).map_err(|e| format!("Invalid end: {}", e))?;
+        if end <= start {
+            return Err("Event must end after it starts".to_string());
+        }
+        if req.summary.trim().is_empty() {
+            return Err("Event needs a summary".to_string());
+        }
+        if req.attendees.is_empty() {
+            return Err("Event needs at least one attendee".to_string());
+        }
+        // Addresses go into the calendar object unescaped, so they must be plain addresses
+        for person in std::iter::once(&req.organizer).chain(&req.attendees) {
+            person
+                .email
+                .parse::<Address>()
+                .map_err(|_| format!("Invalid address: {}", person.email.escape_debug()))?;
+        }
+
+        // Whoever created the event holds its secret; nobody else may change it
+        let (uid, sequence, secret) = match (req.method, req.uid) {
+            (InviteMethod::Request, None) => (new_uid(), 0, Some(new_secret())),
+            (method, Some(uid)) => match self.records.get(&uid) {
+                Some(r) if !req.secret.as_deref().is_some_and(|s| secret_matches(s, &r.secret_hash)) => {
+                    return Err("Wrong or missing event secret".to_string())
+                }
+                Some(r) if r.cancelled => return Err("Event was cancelled".to_string()),
+                // Re-sending an unchanged invite keeps its sequence
+                Some(r) if method == InviteMethod::Request => (uid, r.sequence, None),
+                Some(r) => (uid, r.sequence + 1, None),
+                None if method == InviteMethod::Request => (uid, 0, Some(new_secret())),
+                None => return Err("Unknown event UID".to_string()),
+            },
+            (_, None) => return Err("Updates and cancellations need the event UID".to_string()),
+        };
+
+        let event = Event {
+            uid,
+            sequence,
+            method: req.method,
+            summary: req.summary,
+            description: req.description,
+            location: req.location,
+            start,
+            end,
+            timezone: req.timezone,
+            organizer: req.organizer,
+            attendees: req.attendees,
+        };
+        Ok((event, secret))
+    }
+
+    /// Store the event's sequence, with the hash of `secret` for new events.
+    pub fn record(&self, event: &Event, secret: Option<&str>) -> Result<(), String> {
+        let secret_hash = match secret {
+            Some(secret) => hash_secret(secret),
+            None => self.records.get(&event.uid).map(|r| r.secret_hash).unwrap_or_default(),
+        };
+        self.records.insert(
+            event.uid.clone(),
+            EventRecord {
+                sequence: event.sequence,
+                secret_hash,
+                cancelled: event.method == InviteMethod::Cancel,
+            },
+        )
+    }
+}
+
+impl Event {
+    pub fn ics_method(&self) -> &'static str {
+        match self.method {
+            InviteMethod::Request | InviteMethod::Update => "REQUEST",
+            InviteMethod::Cancel => "CANCEL",
+        }
+    }
+
+    /// Human readable time range in the event's own offset, e.g. `Tue 20 Oct 2026, 10:00–11:00 (Europe/Berlin)`.
+    pub fn when(&self) -> String {
+        let end_format = if self.start.date_naive() == self.end.date_naive() {
+            "%H:%M"
+        } else {
+            "%a %d %b %Y, %H:%M"
+        };
+        format!(
+            "{}–{} ({})",
+            self.start.format("%a %d %b %Y, %H:%M"),
+            self.end.format(end_format),
+            self.timezone
+        )
+    }
+
+    /// Render the RFC 5545 calendar object (CRLF line endings, folded lines).
+    pub fn to_ics(&self) -> String {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "PRODID:-//Onyx//Relay//EN".to_string(),
+            "VERSION:2.0".to_string(),
+            "CALSCALE:GREGORIAN".to_string(),
+            format!("METHOD:{}", self.ics_method()),
+            format!("X-WR-TIMEZONE:{}", escape_text(&self.timezone)),
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}", escape_text(&self.uid)),
+            format!("SEQUENCE:{}", self.sequence),
+            format!("DTSTAMP:{}", utc_stamp(&Utc::now().fixed_offset())),
+            format!("DTSTART:{}", utc_stamp(&self.start)),
+            format!("DTEND:{}", utc_stamp(&self.end)),
+            format!("SUMMARY:{}", escape_text(&self.summary)),
+        ];
+
+        if let Some(description) = &self.description {
+            lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+        }
+        if let Some(location) = &self.location {
+            lines.push(format!("LOCATION:{}", escape_text(location)));
+        }
+
+        lines.push(format!("ORGANIZER{}:mailto:{}", cn_param(&self.organizer), self.organizer.email));
+        for attendee in &self.attendees {
+            lines.push(format!(
+                "ATTENDEE{};ROLE=REQ-PARTICIPANT;PARTSTAT=NEEDS-ACTION;RSVP=TRUE:mailto:{}",
+                cn_param(attendee),
+                attendee.email
+            ));
+        }
+
+        lines.push(format!(
+            "STATUS:{}",
+            if self.method == InviteMethod::Cancel { "CANCELLED" } else { "CONFIRMED" }
+        ));
+        lines.push("END:VEVENT".to_string());
+        lines.push("END:VCALENDAR".to_string());
+
+        lines.iter().map(|l| fold_line(l)).collect::<Vec<_>>().join("\r\n") + "\r\n"
+    }
+}
+
+fn new_uid() -> String {
+    let bytes: [u8; 16] = rand::thread_rng().gen();
+    format!("{}@onyx", hex::encode(bytes))
+}
+
+fn new_secret() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    hex::encode(bytes)
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+// Records from before secrets were issued have none and can't be changed
+fn secret_matches(secret: &str, hash: &str) -> bool {
+    !hash.is_empty() && constant_time_eq(hash_secret(secret).as_bytes(), hash.as_bytes())
+}
+
+fn utc_stamp(time: &DateTime<FixedOffset>) -> String {
+    time.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn cn_param(person: &Person) -> String {
+    match &person.name {
+        // DQUOTE is not allowed inside a quoted parameter value, and control
+        // characters would end the line
+        Some(name) if !name.trim().is_empty() => {
+            let name: String = name.chars().filter(|c| !c.is_control()).collect();
+            format!(";CN=\"{}\"", name.replace('"', "'"))
+        }
+        _ => String::new(),
+    }
+}
+
+// RFC 5545 3.3.11. A bare CR is a line break to lenient parsers, so it's
+// one here too; other control characters (but tab) would end the line.
+fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.replace("\r\n", "\n").replace('\r', "\n").chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// RFC 5545 3.1: lines longer than 75 octets continue on the next line after a space
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+
+    for c in line.chars() {
+        let len = c.len_utf8();
+        if width + len > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += len;
+    }
+
+    folded
+}
@@ -0,0 +1,82 @@
+use reqwest::{Method, RequestBuilder};
+use serde_json::{json, Value};
+use std::env;
+
+const USAGE: &str = "Usage: onyx-relay admin <command>
+
+Commands:
+  test-email <address>            Queue a test message
+  jobs [queued|sending|dead]      List outbox jobs
+  retry <id|all>                  Re-queue dead-letter jobs
+  purge <id|all>                  Drop a job, or every dead-letter job
+  suppressions                    List suppressed addresses
+  suppress <address>              Add an address to the suppression list
+  unsuppress <address|hash>       Remove an address from the suppression list
+  upstreams                       Show SMTP upstream health
+  config                          Print the relay's effective config (secrets redacted)
+
+Environment:
+  ADMIN_TOKEN       Bearer token configured on the relay
+  RELAY_ADMIN_URL   Relay base URL (default http://127.0.0.1:$PORT)";
+
+/// `onyx-relay admin ...`: a thin client for the admin API of a running relay.
+pub async fn run(args: &[String]) -> Result<(), String> {
+    let command = args.first().map(String::as_str).unwrap_or("help");
+    let arg = args.get(1).map(String::as_str);
+
+    let (method, path, body) = match (command, arg) {
+        ("test-email", Some(to)) => (Method::POST, "/test-email".to_string(), Some(json!({ "to": to }))),
+        ("jobs", None) => (Method::GET, "/jobs".to_string(), None),
+        ("jobs", Some(status)) => (Method::GET, format!("/jobs?status={}", status), None),
+        ("retry", Some("all")) => (Method::POST, "/jobs/retry".to_string(), None),
+        ("retry", Some(id)) => (Method::POST, format!("/jobs/{}/retry", job_id(id)?), None),
+        ("purge", Some("all")) => (Method::DELETE, "/jobs".to_string(), None),
+        ("purge", Some(id)) => (Method::DELETE, format!("/jobs/{}", job_id(id)?), None),
+        ("suppressions", None) => (Method::GET, "/suppressions".to_string(), None),
+        ("suppress", Some(email)) => (Method::POST, "/suppressions".to_string(), Some(json!({ "email": email }))),
+        ("unsuppress", Some(key)) => (Method::DELETE, format!("/suppressions/{}", key), None),
+        ("upstreams", None) => (Method::GET, "/upstreams".to_string(), None),
+        ("config", None) => (Method::GET, "/config".to_string(), None),
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let res = request(method, &path, body)?
+        .send()
+        .await
+        .map_err(|e| format!("Relay unreachable: {}", e))?;
+
+    let status = res.status();
+    let text = res.text().await.map_err(|e| e.to_string())?;
+    if !status.is_success() {
+        return Err(format!("{}: {}", status, text));
+    }
+
+    // Pretty-print JSON answers, pass plain text through
+    match serde_json::from_str::<Value>(&text) {
+        Ok(value) => println!("{}", serde_json::to_string_pretty(&value).unwrap_or(text)),
+        Err(_) => println!("{}", text),
+    }
+    Ok(())
+}
+
+fn request(method: Method, path: &str, body: Option<Value>) -> Result<RequestBuilder, String> {
+    let token = env::var("ADMIN_TOKEN").map_err(|_| "ADMIN_TOKEN not set")?;
+    let base = env::var("RELAY_ADMIN_URL").unwrap_or_else(|_| {
+        let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
+        format!("http://127.0.0.1:{}", port)
+    });
+    let url = format!("{}/api/admin{}", base.trim_end_matches('/'), path);
+
+    let mut req = reqwest::Client::new().request(method, url).bearer_auth(token);
+    if let Some(body) = body {
+        req = req.json(&body);
+    }
+    Ok(req)
+}
+
+fn job_id(raw: &str) -> Result<u64, String> {
+    raw.parse().map_err(|_| format!("Invalid job id: {}", raw))
+}
@@ -0,0 +1,61 @@
+use std::collections::BTreeMap;
+use std::env;
+use url::Url;
+
+// Every variable the relay reads, with the default it falls back to
+const SETTINGS: &[(&str, Option<&str>)] = &[
+    ("PORT", Some("3000")),
+    ("RUST_LOG", Some("info")),
+    ("DATA_DIR", Some("data")),
+    ("GPG_BIN", Some("gpg")),
+    ("SMTP_HOST", Some("smtp.purelymail.com")),
+    ("SMTP_USERNAME", None),
+    ("SMTP_PASSWORD", None),
+    ("SMTP_UPSTREAMS", None),
+    ("ADMIN_TOKEN", None),
+    ("INBOUND_TOKEN", None),
+    ("INVITE_TOKEN", None),
+];
+
+const SECRETS: &[&str] = &["SMTP_PASSWORD", "ADMIN_TOKEN", "INBOUND_TOKEN", "INVITE_TOKEN"];
+
+/// The configuration the relay is running with, safe to print.
+pub fn effective() -> BTreeMap<&'static str, String> {
+    SETTINGS
+        .iter()
+        .map(|(name, default)| {
+            let value = match env::var(name) {
+                Ok(v) if SECRETS.contains(name) => redact(&v),
+                Ok(v) if *name == "SMTP_UPSTREAMS" => redact_urls(&v),
+                Ok(v) => v,
+                Err(_) => default.map_or("(unset)".to_string(), |d| format!("{} (default)", d)),
+            };
+            (*name, value)
+        })
+        .collect()
+}
+
+fn redact(value: &str) -> String {
+    if value.is_empty() {
+        "(empty)".to_string()
+    } else {
+        "********".to_string()
+    }
+}
+
+// Upstream URLs carry passwords in the userinfo part
+fn redact_urls(list: &str) -> String {
+    list.split(',')
+        .map(str::trim)
+        .map(|raw| match Url::parse(raw) {
+            Ok(mut url) => {
+                if url.password().is_some() {
+                    let _ = url.set_password(Some("********"));
+                }
+                url.to_string()
+            }
+            Err(_) => "(invalid url)".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -0,0 +1,104 @@
+use crate::pgp::KeyInfo;
+use crate::store::{hash_address, unix_now, JsonStore};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// How long a key verification code stays valid (seconds)
+const VERIFY_TTL: u64 = 15 * 60;
+// Wrong guesses allowed before the pending upload is thrown away
+const MAX_VERIFY_ATTEMPTS: u32 = 5;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoredKey {
+    pub fingerprint: String,
+    pub armored: String,
+    pub expires_at: Option<u64>,
+}
+
+impl StoredKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|exp| exp <= unix_now())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingKey {
+    pub key: StoredKey,
+    pub code: String,
+    pub code_expires_at: u64,
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+/// A user's registered key plus an upload that still awaits verification.
+/// The active key stays in use until the new one has been proven.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub active: Option<StoredKey>,
+    pub pending: Option<PendingKey>,
+}
+
+pub struct KeyStore {
+    records: JsonStore<KeyRecord>,
+}
+
+impl KeyStore {
+    pub fn open() -> Self {
+        Self {
+            records: JsonStore::open("keys.json"),
+        }
+    }
+
+    pub fn active_key(&self, email: &str) -> Option<StoredKey> {
+        self.records.get(&hash_address(email)).and_then(|r| r.active)
+    }
+
+    /// Stage an uploaded key and return the code that must be echoed back to activate it.
+    pub fn stage(&self, email: &str, armored: String, info: &KeyInfo) -> Result<(StoredKey, String), String> {
+        let hash = hash_address(email);
+        let mut record = self.records.get(&hash).unwrap_or_default();
+
+        let key = StoredKey {
+            fingerprint: info.fingerprint.clone(),
+            armored,
+            expires_at: info.expires_at,
+        };
+        let code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+
+        record.pending = Some(PendingKey {
+            key: key.clone(),
+            code: code.clone(),
+            code_expires_at: unix_now() + VERIFY_TTL,
+            attempts: 0,
+        });
+        self.records.insert(hash, record)?;
+
+        Ok((key, code))
+    }
+
+    /// Promote the pending key if the code matches. Returns the activated fingerprint.
+    pub fn verify(&self, email: &str, code: &str) -> Result<String, String> {
+        let hash = hash_address(email);
+        let mut record = self.records.get(&hash).ok_or("No key upload pending")?;
+        let mut pending = record.pending.take().ok_or("No key upload pending")?;
+
+        if pending.code_expires_at <= unix_now() {
+            self.records.insert(hash, record)?;
+            return Err("Verification code expired".to_string());
+        }
+        if pending.code != code.trim() {
+            pending.attempts += 1;
+            if pending.attempts < MAX_VERIFY_ATTEMPTS {
+                record.pending = Some(pending);
+            }
+            self.records.insert(hash, record)?;
+            return Err("Invalid verification code".to_string());
+        }
+
+        let fingerprint = pending.key.fingerprint.clone();
+        record.active = Some(pending.key);
+        self.records.insert(hash, record)?;
+
+        Ok(fingerprint)
+    }
+}
@@ -1,24 +1,64 @@
 use axum::{
-    extract::Json,
-    routing::{get, post},
+    body::Bytes,
+    extract::{Json, State},
+    http::StatusCode,
+    middleware,
+    routing::{delete, get, post},
     Router,
 };
+use calendar::{EventLog, EventRequest, InviteMethod};
 use dotenv::dotenv;
-use lettre::message::Mailbox;
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
-use serde::Deserialize;
+use keys::KeyStore;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, MultiPart, SinglePart};
+use outbox::Outbox;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use suppression::{SuppressionList, SUPPRESSED_ERROR};
 use tower_http::cors::CorsLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use upstream::{UpstreamPool, RECIPIENT_REJECTED};
 
+mod admin;
+mod auth;
+mod bounce;
+mod calendar;
+mod cli;
+mod config;
+mod keys;
+mod outbox;
+mod pgp;
+mod store;
+mod suppression;
 mod templates; // Import the templates module
+mod upstream;
+
+#[derive(Clone)]
+struct AppState {
+    keys: Arc<KeyStore>,
+    suppressions: Arc<SuppressionList>,
+    upstreams: Arc<UpstreamPool>,
+    events: Arc<EventLog>,
+    outbox: Arc<Outbox>,
+}
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
-    
+
+    // `onyx-relay admin ...` talks to a running relay instead of starting one
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("admin") {
+        if let Err(e) = cli::run(&args[1..]).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Initialize logging (for server health, NOT for user data)
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
@@ -30,11 +70,47 @@ async fn main() {
     // CORS: Allow requests from anywhere (since Client is Tauri/App)
     let cors = CorsLayer::permissive();
 
+    let state = AppState {
+        keys: Arc::new(KeyStore::open()),
+        suppressions: Arc::new(SuppressionList::open()),
+        upstreams: Arc::new(UpstreamPool::from_env()),
+        events: Arc::new(EventLog::open()),
+        outbox: Arc::new(Outbox::default()),
+    };
+
+    tokio::spawn(run_outbox(state.clone()));
+
+    let admin_routes = Router::new()
+        .route("/suppressions", get(admin::list_suppressions).post(admin::add_suppression))
+        .route("/suppressions/:key", delete(admin::remove_suppression))
+        .route("/upstreams", get(admin::list_upstreams))
+        .route("/metrics", get(admin::metrics))
+        .route("/jobs", get(admin::list_jobs).delete(admin::purge_dead))
+        .route("/jobs/retry", post(admin::retry_all))
+        .route("/jobs/:id", delete(admin::purge_job))
+        .route("/jobs/:id/retry", post(admin::retry_job))
+        .route("/test-email", post(admin::send_test_email))
+        .route("/config", get(admin::config))
+        .route_layer(middleware::from_fn(auth::require_admin));
+
+    let inbound_routes = Router::new()
+        .route("/bounces", post(receive_bounce))
+        .route_layer(middleware::from_fn(auth::require_inbound));
+
     let app = Router::new()
         .route("/", get(health_check))
         .route("/api/email/otp", post(send_otp))
         .route("/api/email/link", post(send_magic_link))
-        .layer(cors);
+        .route(
+            "/api/email/invite",
+            post(send_invite).route_layer(middleware::from_fn(auth::require_invite)),
+        )
+        .route("/api/keys", post(upload_key))
+        .route("/api/keys/verify", post(verify_key))
+        .nest("/api/admin", admin_routes)
+        .nest("/api/inbound", inbound_routes)
+        .layer(cors)
+        .with_state(state);
 
     let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
     let addr = SocketAddr::from(([0, 0, 0, 0], port.parse().unwrap()));
@@ -57,19 +133,19 @@ struct OtpRequest {
     code: String,
 }
 
-async fn send_otp(Json(payload): Json<OtpRequest>) -> Result<String, String> {
+async fn send_otp(
+    State(state): State<AppState>,
+    Json(payload): Json<OtpRequest>,
+) -> Result<String, (StatusCode, String)> {
     tracing::info!("Received OTP request. Spawning background task...");
+    reject_suppressed(&state, &payload.email)?;
 
     // Generate Content via Templates
     let content = templates::otp_email(&payload.code);
 
-    // Spawn logging/sending in background so Client doesn't wait/timeout
-    tokio::spawn(async move {
-        match send_email(payload.email.clone(), &content.subject, content.html, content.text).await {
-            Ok(_) => tracing::info!("OTP sent successfully to {}", payload.email),
-            Err(e) => tracing::error!("Failed to send OTP to {}: {}", payload.email, e),
-        }
-    });
+    // Hand off to the outbox so Client doesn't wait/timeout
+    let body = alternative_body(content.text, content.html);
+    state.outbox.enqueue("otp", payload.email, content.subject, body, false);
 
     Ok("Queued".to_string())
 }
@@ -82,62 +158,263 @@ struct LinkRequest {
     link: String,
 }
 
-async fn send_magic_link(Json(payload): Json<LinkRequest>) -> Result<String, String> {
+async fn send_magic_link(
+    State(state): State<AppState>,
+    Json(payload): Json<LinkRequest>,
+) -> Result<String, (StatusCode, String)> {
     tracing::info!("Received Magic Link request. Spawning background task...");
+    reject_suppressed(&state, &payload.email)?;
 
     let content = templates::magic_link_email(&payload.link);
 
-    tokio::spawn(async move {
-        match send_email(payload.email.clone(), &content.subject, content.html, content.text).await {
-            Ok(_) => tracing::info!("Magic Link sent successfully to {}", payload.email),
-            Err(e) => tracing::error!("Failed to send Magic Link to {}: {}", payload.email, e),
-        }
-    });
+    let body = alternative_body(content.text, content.html);
+    state.outbox.enqueue("magic_link", payload.email, content.subject, body, false);
 
     Ok("Queued".to_string())
 }
 
-// --- SMTP Logic ---
+// --- Calendar Invite Handler ---
 
-// --- SMTP Logic ---
+#[derive(Serialize)]
+struct InviteResponse {
+    uid: String,
+    /// Only for new events: must accompany every later update or cancellation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    sequence: u32,
+    queued: usize,
+    suppressed: usize,
+}
 
-async fn send_email(to: String, subject: &str, html_body: String, text_body: String) -> Result<(), String> {
-    let host = env::var("SMTP_HOST").unwrap_or_else(|_| "smtp.purelymail.com".to_string());
-    let username = env::var("SMTP_USERNAME").map_err(|_| "SMTP_USERNAME not set")?;
-    let password = env::var("SMTP_PASSWORD").map_err(|_| "SMTP_PASSWORD not set")?;
+async fn send_invite(
+    State(state): State<AppState>,
+    Json(payload): Json<EventRequest>,
+) -> Result<Json<InviteResponse>, (StatusCode, String)> {
+    tracing::info!("Received invite request. Queueing...");
 
-    let from_header = format!("Onyx <{}>", username);
+    let (event, secret) = state
+        .events
+        .prepare(payload)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
 
-    // Build Multipart Email (Text + HTML)
-    // This is crucial for spam scores.
-    let email = Message::builder()
-        .from(from_header.parse::<Mailbox>().unwrap())
-        .to(to.parse::<Mailbox>().map_err(|e| e.to_string())?)
-        .subject(subject)
-        .multipart(
-            lettre::message::MultiPart::alternative() // "Alternative" means client chooses best view (HTML or Text)
-                .singlepart(
-                    lettre::message::SinglePart::builder()
-                        .header(lettre::message::header::ContentType::TEXT_PLAIN)
-                        .body(text_body)
-                )
-                .singlepart(
-                    lettre::message::SinglePart::builder()
-                        .header(lettre::message::header::ContentType::TEXT_HTML)
-                        .body(html_body)
-                )
-        )
-        .map_err(|e| e.to_string())?;
+    let (subject_prefix, title) = match event.method {
+        InviteMethod::Request => ("Invitation", "You're Invited"),
+        InviteMethod::Update => ("Updated invitation", "Event Updated"),
+        InviteMethod::Cancel => ("Cancelled", "Event Cancelled"),
+    };
+    let organizer = event.organizer.name.clone().unwrap_or_else(|| event.organizer.email.clone());
+    let content = templates::invite_email(
+        subject_prefix,
+        title,
+        &event.summary,
+        &event.when(),
+        event.location.as_deref(),
+        &organizer,
+    );
+    let ics = event.to_ics();
+
+    // Persist the sequence before sending so a retry never reuses it
+    state
+        .events
+        .record(&event, secret.as_deref())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    let mut queued = 0;
+    let mut suppressed = 0;
+    for attendee in &event.attendees {
+        if state.suppressions.is_suppressed(&attendee.email) {
+            suppressed += 1;
+            continue;
+        }
+
+        let body = invite_body(content.text.clone(), content.html.clone(), ics.clone(), event.ics_method());
+        state
+            .outbox
+            .enqueue("invite", attendee.email.clone(), content.subject.clone(), body, false);
+        queued += 1;
+    }
+
+    Ok(Json(InviteResponse {
+        uid: event.uid,
+        secret,
+        sequence: event.sequence,
+        queued,
+        suppressed,
+    }))
+}
+
+// --- OpenPGP Key Handlers ---
+
+#[derive(Deserialize)]
+struct KeyUploadRequest {
+    email: String,
+    public_key: String,
+}
+
+async fn upload_key(
+    State(state): State<AppState>,
+    Json(payload): Json<KeyUploadRequest>,
+) -> Result<String, (StatusCode, String)> {
+    reject_suppressed(&state, &payload.email)?;
+
+    let info = pgp::inspect_key(&payload.public_key)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    if !info.has_address(&payload.email) {
+        return Err((StatusCode::BAD_REQUEST, "Key has no user ID for this address".to_string()));
+    }
+    if !info.can_encrypt {
+        return Err((StatusCode::BAD_REQUEST, "Key has no usable encryption subkey".to_string()));
+    }
+    if info.is_expired() {
+        return Err((StatusCode::BAD_REQUEST, "Key has expired".to_string()));
+    }
+
+    let (key, code) = state
+        .keys
+        .stage(&payload.email, payload.public_key, &info)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+    // The code is only readable by whoever holds the private key, which proves ownership
+    let content = templates::key_verification_email(&code);
+    let body = alternative_body(content.text, content.html);
+    let encrypted = pgp::encrypt_mime(&key.armored, &key.fingerprint, &body)
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    state
+        .outbox
+        .enqueue("key_verification", payload.email, content.subject, encrypted, true);
+
+    Ok("Queued".to_string())
+}
 
-    let creds = Credentials::new(username, password);
+#[derive(Deserialize)]
+struct KeyVerifyRequest {
+    email: String,
+    code: String,
+}
+
+async fn verify_key(
+    State(state): State<AppState>,
+    Json(payload): Json<KeyVerifyRequest>,
+) -> Result<String, (StatusCode, String)> {
+    let fingerprint = state
+        .keys
+        .verify(&payload.email, &payload.code)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    tracing::info!("Activated OpenPGP key {}", fingerprint);
+    Ok(fingerprint)
+}
 
-    let mailer: AsyncSmtpTransport<Tokio1Executor> =
-        AsyncSmtpTransport::<Tokio1Executor>::relay(&host)
-            .map_err(|e| e.to_string())?
-            .credentials(creds)
-            .build();
+// --- Bounce Handler ---
 
-    mailer.send(email).await.map_err(|e| e.to_string())?;
+// The provider forwards raw DSN / ARF messages here (body is the RFC 822 message)
+async fn receive_bounce(State(state): State<AppState>, body: Bytes) -> Result<String, (StatusCode, String)> {
+    let events = bounce::parse_report(&body);
 
+    for event in &events {
+        tracing::info!("Suppressing recipient: {:?} {}", event.reason, event.status.as_deref().unwrap_or("-"));
+        state
+            .suppressions
+            .add(&event.recipient, event.reason, event.status.clone(), event.diagnostic.clone())
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    }
+
+    Ok(format!("Suppressed {}", events.len()))
+}
+
+// Refuse up front so the client learns about it instead of a silent background failure
+fn reject_suppressed(state: &AppState, email: &str) -> Result<(), (StatusCode, String)> {
+    if state.suppressions.is_suppressed(email) {
+        return Err((StatusCode::UNPROCESSABLE_ENTITY, SUPPRESSED_ERROR.to_string()));
+    }
     Ok(())
 }
+
+// --- Delivery Loop ---
+
+// Drains the outbox: every due job gets its own task so one slow upstream
+// doesn't hold up the rest.
+async fn run_outbox(state: AppState) {
+    loop {
+        for delivery in state.outbox.take_due() {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let id = delivery.id;
+                // Sent from its own task so a panic still settles the job
+                // instead of leaving it marked as sending
+                let send = tokio::spawn(deliver(state.clone(), delivery));
+                let result = send.await.unwrap_or_else(|e| Err(format!("Delivery task failed: {}", e)));
+                let permanent = matches!(&result, Err(e) if e == SUPPRESSED_ERROR || e.starts_with(RECIPIENT_REJECTED));
+                state.outbox.complete(id, result, permanent);
+                state.outbox.wake.notify_one();
+            });
+        }
+
+        let wait = state.outbox.next_due_in().unwrap_or(Duration::from_secs(60));
+        let _ = tokio::time::timeout(wait, state.outbox.wake.notified()).await;
+    }
+}
+
+// --- SMTP Logic ---
+
+async fn deliver(state: AppState, delivery: outbox::Delivery) -> Result<(), String> {
+    // Checked again at send time: the address may have bounced since it was queued
+    if state.suppressions.is_suppressed(&delivery.to) {
+        return Err(SUPPRESSED_ERROR.to_string());
+    }
+    if delivery.prepared {
+        state.upstreams.send(&delivery.to, &delivery.subject, delivery.body).await
+    } else {
+        send_body(&state, delivery.to, &delivery.subject, delivery.body).await
+    }
+}
+
+async fn send_body(state: &AppState, to: String, subject: &str, body: MultiPart) -> Result<(), String> {
+    // Users with a registered key only ever get PGP/MIME from us
+    match state.keys.active_key(&to) {
+        Some(key) if key.is_expired() => {
+            let notice = templates::key_expired_email();
+            let body = alternative_body(notice.text, notice.html);
+            state.upstreams.send(&to, &notice.subject, body).await
+        }
+        Some(key) => {
+            let encrypted = pgp::encrypt_mime(&key.armored, &key.fingerprint, &body).await?;
+            state.upstreams.send(&to, subject, encrypted).await
+        }
+        None => state.upstreams.send(&to, subject, body).await,
+    }
+}
+
+// Build Multipart Email (Text + HTML)
+// This is crucial for spam scores.
+pub(crate) fn alternative_body(text_body: String, html_body: String) -> MultiPart {
+    MultiPart::alternative() // "Alternative" means client chooses best view (HTML or Text)
+        .singlepart(
+            lettre::message::SinglePart::builder()
+                .header(lettre::message::header::ContentType::TEXT_PLAIN)
+                .body(text_body)
+        )
+        .singlepart(
+            lettre::message::SinglePart::builder()
+                .header(lettre::message::header::ContentType::TEXT_HTML)
+                .body(html_body)
+        )
+}
+
+// Calendar clients look for the inline text/calendar alternative; the .ics
+// attachment is for clients that only offer "open attachment".
+fn invite_body(text_body: String, html_body: String, ics: String, method: &str) -> MultiPart {
+    let calendar_type = ContentType::parse(&format!("text/calendar; charset=UTF-8; method={}", method)).unwrap();
+
+    MultiPart::mixed()
+        .multipart(
+            MultiPart::alternative()
+                .singlepart(SinglePart::builder().header(ContentType::TEXT_PLAIN).body(text_body))
+                .singlepart(SinglePart::builder().header(ContentType::TEXT_HTML).body(html_body))
+                .singlepart(SinglePart::builder().header(calendar_type).body(ics.clone())),
+        )
+        .singlepart(Attachment::new("invite.ics".to_string()).body(ics, ContentType::parse("application/ics").unwrap()))
+}
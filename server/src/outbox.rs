@@ -0,0 +1,232 @@
+use crate::store::{hash_address, unix_now};
+use lettre::message::MultiPart;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// Attempts before a job is parked in the dead-letter list
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_BACKOFF: Duration = Duration::from_secs(5);
+// Dead jobs are kept this long for inspection, and at most this many
+const DEAD_TTL_SECS: u64 = 24 * 60 * 60;
+const MAX_DEAD: usize = 500;
+// Codes and sign-in links are useless by the time an operator could retry
+// them, so their content is dropped as soon as they die
+const SINGLE_USE_KINDS: [&str; 2] = ["otp", "magic_link"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Sending,
+    Dead,
+}
+
+struct Job {
+    id: u64,
+    kind: &'static str,
+    to: String,
+    subject: String,
+    /// `None` once a single-use job has died
+    body: Option<MultiPart>,
+    /// Body is already encrypted for a specific key; skip the recipient key lookup
+    prepared: bool,
+    status: JobStatus,
+    attempts: u32,
+    last_error: Option<String>,
+    created_at: u64,
+    /// When it was parked in the dead-letter list
+    died_at: Option<u64>,
+    next_attempt: Instant,
+}
+
+/// A copy of a job handed to the delivery loop.
+pub struct Delivery {
+    pub id: u64,
+    pub to: String,
+    pub subject: String,
+    pub body: MultiPart,
+    pub prepared: bool,
+}
+
+/// What operators get to see. Never the address or the content.
+#[derive(Serialize)]
+pub struct JobSummary {
+    pub id: u64,
+    pub kind: &'static str,
+    pub address_hash: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: u64,
+    /// Whether `retry` can re-queue it once dead
+    pub retryable: bool,
+}
+
+/// Messages waiting to be handed to an upstream.
+///
+/// Held in memory only, so message content never touches disk. A restart
+/// drops whatever is still queued, which is fine for short-lived codes and links.
+#[derive(Default)]
+pub struct Outbox {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    next_id: AtomicU64,
+    pub wake: Notify,
+}
+
+impl Outbox {
+    pub fn enqueue(&self, kind: &'static str, to: String, subject: String, body: MultiPart, prepared: bool) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.jobs.lock().unwrap().insert(
+            id,
+            Job {
+                id,
+                kind,
+                to,
+                subject,
+                body: Some(body),
+                prepared,
+                status: JobStatus::Queued,
+                attempts: 0,
+                last_error: None,
+                created_at: unix_now(),
+                died_at: None,
+                next_attempt: Instant::now(),
+            },
+        );
+        self.wake.notify_one();
+        id
+    }
+
+    /// Mark due jobs as sending and hand out copies for delivery.
+    pub fn take_due(&self) -> Vec<Delivery> {
+        let now = Instant::now();
+        let mut jobs = self.jobs.lock().unwrap();
+        prune_dead(&mut jobs);
+        jobs.values_mut()
+            .filter(|j| j.status == JobStatus::Queued && j.next_attempt <= now)
+            .filter_map(|j| {
+                let body = j.body.clone()?;
+                j.status = JobStatus::Sending;
+                Some(Delivery {
+                    id: j.id,
+                    to: j.to.clone(),
+                    subject: j.subject.clone(),
+                    body,
+                    prepared: j.prepared,
+                })
+            })
+            .collect()
+    }
+
+    pub fn complete(&self, id: u64, result: Result<(), String>, permanent: bool) {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        let Err(error) = result else {
+            jobs.remove(&id);
+            return;
+        };
+        let Some(job) = jobs.get_mut(&id) else {
+            return;
+        };
+
+        job.attempts += 1;
+        tracing::error!("Job {} ({}) attempt {} failed: {}", job.id, job.kind, job.attempts, error);
+        job.last_error = Some(error);
+
+        if permanent || job.attempts >= MAX_ATTEMPTS {
+            job.status = JobStatus::Dead;
+            job.died_at = Some(unix_now());
+            if SINGLE_USE_KINDS.contains(&job.kind) {
+                job.body = None;
+            }
+            prune_dead(&mut jobs);
+        } else {
+            job.status = JobStatus::Queued;
+            job.next_attempt = Instant::now() + BASE_BACKOFF * 2u32.pow(job.attempts - 1);
+        }
+    }
+
+    /// Time until the next queued job becomes due, if any.
+    pub fn next_due_in(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|j| j.status == JobStatus::Queued)
+            .map(|j| j.next_attempt.saturating_duration_since(now))
+            .min()
+    }
+
+    pub fn list(&self, status: Option<JobStatus>) -> Vec<JobSummary> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|j| status.is_none_or(|s| j.status == s))
+            .map(|j| JobSummary {
+                id: j.id,
+                kind: j.kind,
+                address_hash: hash_address(&j.to),
+                status: j.status,
+                attempts: j.attempts,
+                last_error: j.last_error.clone(),
+                created_at: j.created_at,
+                retryable: j.body.is_some(),
+            })
+            .collect()
+    }
+
+    /// Re-queue dead jobs (one, or all when `id` is `None`). Single-use jobs
+    /// stay dead. Returns how many were moved.
+    pub fn retry(&self, id: Option<u64>) -> usize {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut count = 0;
+        for job in jobs.values_mut() {
+            if job.status == JobStatus::Dead && job.body.is_some() && id.is_none_or(|id| job.id == id) {
+                job.status = JobStatus::Queued;
+                job.died_at = None;
+                job.attempts = 0;
+                job.next_attempt = Instant::now();
+                count += 1;
+            }
+        }
+        drop(jobs);
+
+        if count > 0 {
+            self.wake.notify_one();
+        }
+        count
+    }
+
+    /// Drop dead jobs, or a single queued/dead job by id. Jobs mid-send are left alone.
+    pub fn purge(&self, id: Option<u64>) -> usize {
+        let mut jobs = self.jobs.lock().unwrap();
+        let before = jobs.len();
+        jobs.retain(|_, job| match id {
+            Some(id) => job.id != id || job.status == JobStatus::Sending,
+            None => job.status != JobStatus::Dead,
+        });
+        before - jobs.len()
+    }
+}
+
+// Drop dead jobs past their TTL, then the oldest ones beyond the cap
+fn prune_dead(jobs: &mut BTreeMap<u64, Job>) {
+    let cutoff = unix_now().saturating_sub(DEAD_TTL_SECS);
+    jobs.retain(|_, job| job.status != JobStatus::Dead || job.died_at.is_some_and(|t| t >= cutoff));
+
+    let mut dead: Vec<(Option<u64>, u64)> = jobs
+        .values()
+        .filter(|j| j.status == JobStatus::Dead)
+        .map(|j| (j.died_at, j.id))
+        .collect();
+    dead.sort_unstable();
+    for (_, id) in dead.iter().take(dead.len().saturating_sub(MAX_DEAD)) {
+        jobs.remove(id);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::store::unix_now;
+use lettre::message::header::{ContentDisposition, ContentType};
+use lettre::message::{MultiPart, SinglePart};
+use std::env;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// What the relay needs to know about an uploaded public key.
+pub struct KeyInfo {
+    pub fingerprint: String,
+    pub uids: Vec<String>,
+    pub expires_at: Option<u64>,
+    pub can_encrypt: bool,
+}
+
+impl KeyInfo {
+    /// A key is only accepted if one of its user IDs carries the address it is registered for.
+    pub fn has_address(&self, email: &str) -> bool {
+        let email = email.trim().to_lowercase();
+        self.uids.iter().any(|uid| {
+            let uid = uid.to_lowercase();
+            uid == email || uid.contains(&format!("<{}>", email))
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|exp| exp <= unix_now())
+    }
+}
+
+/// Parse an armored public key without trusting or keeping it.
+pub async fn inspect_key(armored: &str) -> Result<KeyInfo, String> {
+    let home = tempfile::tempdir().map_err(|e| e.to_string())?;
+    let output = run_gpg(
+        home.path(),
+        &["--with-colons", "--import-options", "show-only", "--import"],
+        armored.as_bytes(),
+    )
+    .await?;
+
+    let listing = String::from_utf8_lossy(&output);
+    let mut info = KeyInfo {
+        fingerprint: String::new(),
+        uids: Vec::new(),
+        expires_at: None,
+        can_encrypt: false,
+    };
+    let mut keys_seen = 0;
+
+    // Colon listing format: https://github.com/gpg/gnupg/blob/master/doc/DETAILS
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields.first() {
+            Some(&"pub") => {
+                keys_seen += 1;
+                info.expires_at = fields.get(6).and_then(|v| v.parse().ok());
+                // Uppercase 'E' means the key as a whole has a usable encryption subkey
+                info.can_encrypt = fields.get(11).is_some_and(|caps| caps.contains('E'));
+            }
+            Some(&"fpr") if info.fingerprint.is_empty() => {
+                info.fingerprint = fields.get(9).unwrap_or(&"").to_string();
+            }
+            Some(&"uid") => {
+                if let Some(uid) = fields.get(9) {
+                    info.uids.push(unescape_colons(uid));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if keys_seen != 1 || info.fingerprint.is_empty() {
+        return Err("Expected exactly one OpenPGP public key".to_string());
+    }
+
+    Ok(info)
+}
+
+/// Wrap an already-built MIME body as an RFC 3156 PGP/MIME message.
+pub async fn encrypt_mime(armored: &str, fingerprint: &str, inner: &MultiPart) -> Result<MultiPart, String> {
+    let ciphertext = encrypt(armored, fingerprint, &inner.formatted()).await?;
+
+    Ok(MultiPart::encrypted("application/pgp-encrypted".to_string())
+        .singlepart(
+            SinglePart::builder()
+                .header(ContentType::parse("application/pgp-encrypted").unwrap())
+                .body(String::from("Version: 1\r\n")),
+        )
+        .singlepart(
+            SinglePart::builder()
+                .header(ContentType::parse("application/octet-stream; name=\"encrypted.asc\"").unwrap())
+                .header(ContentDisposition::inline_with_name("encrypted.asc"))
+                .body(ciphertext),
+        ))
+}
+
+async fn encrypt(armored: &str, fingerprint: &str, plaintext: &[u8]) -> Result<String, String> {
+    // Fresh keyring per message: nothing about the recipient outlives the send
+    let home = tempfile::tempdir().map_err(|e| e.to_string())?;
+    run_gpg(home.path(), &["--import"], armored.as_bytes()).await?;
+
+    let ciphertext = run_gpg(
+        home.path(),
+        &[
+            "--trust-model",
+            "always",
+            "--armor",
+            "--encrypt",
+            "--recipient",
+            fingerprint,
+        ],
+        plaintext,
+    )
+    .await?;
+
+    String::from_utf8(ciphertext).map_err(|e| e.to_string())
+}
+
+async fn run_gpg(home: &std::path::Path, args: &[&str], input: &[u8]) -> Result<Vec<u8>, String> {
+    let gpg = env::var("GPG_BIN").unwrap_or_else(|_| "gpg".to_string());
+
+    let mut child = Command::new(gpg)
+        .arg("--homedir")
+        .arg(home)
+        .args(["--batch", "--no-tty", "--quiet"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to start gpg: {}", e))?;
+
+    let mut stdin = child.stdin.take().ok_or("gpg stdin unavailable")?;
+    stdin.write_all(input).await.map_err(|e| e.to_string())?;
+    drop(stdin);
+
+    let output = child.wait_with_output().await.map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(format!("gpg failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(output.stdout)
+}
+
+fn unescape_colons(value: &str) -> String {
+    value.replace("\\x3a", ":")
+}
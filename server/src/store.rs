@@ -0,0 +1,94 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Small JSON-file backed map used for the relay's persistent state.
+///
+/// The whole map is kept in memory and rewritten on every change. The relay
+/// only stores a handful of records per user, so this stays cheap and keeps
+/// the deployment free of an external database.
+pub struct JsonStore<V> {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, V>>,
+}
+
+impl<V: Serialize + DeserializeOwned + Clone> JsonStore<V> {
+    pub fn open(name: &str) -> Self {
+        let path = data_dir().join(name);
+
+        let entries = match fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+                tracing::error!("Ignoring corrupt store {}: {}", path.display(), e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            path,
+            entries: Mutex::new(entries),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<V> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn insert(&self, key: String, value: V) -> Result<(), String> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key, value);
+        self.persist(&entries)
+    }
+
+    pub fn remove(&self, key: &str) -> Result<Option<V>, String> {
+        let mut entries = self.entries.lock().unwrap();
+        let removed = entries.remove(key);
+        if removed.is_some() {
+            self.persist(&entries)?;
+        }
+        Ok(removed)
+    }
+
+    pub fn list(&self) -> Vec<(String, V)> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    // Write to a temp file first so a crash never leaves a truncated store behind
+    fn persist(&self, entries: &HashMap<String, V>) -> Result<(), String> {
+        let raw = serde_json::to_string_pretty(entries).map_err(|e| e.to_string())?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, raw).map_err(|e| e.to_string())?;
+        fs::rename(&tmp, &self.path).map_err(|e| e.to_string())
+    }
+}
+
+pub fn data_dir() -> PathBuf {
+    let dir = PathBuf::from(std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string()));
+    if !dir.exists() {
+        fs::create_dir_all(&dir).expect("failed to create data dir");
+    }
+    dir
+}
+
+/// Stores are keyed by a hash of the address so the relay never keeps a plain list of users.
+pub fn hash_address(email: &str) -> String {
+    let normalized = email.trim().to_lowercase();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,73 @@
+use crate::store::{hash_address, unix_now, JsonStore};
+use serde::{Deserialize, Serialize};
+
+/// Error code returned to clients when a send is refused because of the list.
+pub const SUPPRESSED_ERROR: &str = "recipient_suppressed";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SuppressionReason {
+    HardBounce,
+    Complaint,
+    Manual,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Suppression {
+    pub reason: SuppressionReason,
+    /// Enhanced status code from the DSN, e.g. `5.1.1`
+    pub status: Option<String>,
+    pub diagnostic: Option<String>,
+    pub created_at: u64,
+}
+
+/// Addresses we must stop mailing. Keyed by the hashed address like every other relay store.
+pub struct SuppressionList {
+    entries: JsonStore<Suppression>,
+}
+
+impl SuppressionList {
+    pub fn open() -> Self {
+        Self {
+            entries: JsonStore::open("suppressions.json"),
+        }
+    }
+
+    pub fn is_suppressed(&self, email: &str) -> bool {
+        self.entries.get(&hash_address(email)).is_some()
+    }
+
+    pub fn add(
+        &self,
+        email: &str,
+        reason: SuppressionReason,
+        status: Option<String>,
+        diagnostic: Option<String>,
+    ) -> Result<(), String> {
+        self.entries.insert(
+            hash_address(email),
+            Suppression {
+                reason,
+                status,
+                diagnostic,
+                created_at: unix_now(),
+            },
+        )
+    }
+
+    /// Accepts either the plain address or its hash, since listings only show hashes.
+    pub fn remove(&self, key: &str) -> Result<bool, String> {
+        let hash = if key.contains('@') {
+            hash_address(key)
+        } else {
+            key.to_lowercase()
+        };
+        Ok(self.entries.remove(&hash)?.is_some())
+    }
+
+    pub fn list(&self) -> Vec<(String, Suppression)> {
+        let mut entries = self.entries.list();
+        entries.sort_by_key(|(_, s)| std::cmp::Reverse(s.created_at));
+        entries
+    }
+}
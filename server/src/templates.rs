@@ -98,6 +98,161 @@ pub fn magic_link_email(link: &str) -> EmailContent {
     }
 }
 
+pub fn key_verification_email(code: &str) -> EmailContent {
+    let subject = "Confirm your Onyx encryption key".to_string();
+
+    let action_html = format!(
+        r#"
+        <tr>
+          <td align="center" style="padding: 0 0 4px 0;">
+            <p style="margin: 0; font-size: 30px; font-weight: 800; color: #fafafa; letter-spacing: 8px; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;">{}</p>
+          </td>
+        </tr>
+        <tr>
+          <td align="center" style="padding: 12px 0 4px 0;">
+            <p style="margin: 0; font-size: 12px; color: #52525b; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;">Expires in <span style="color: #a78bfa; font-weight: 600;">15 minutes</span></p>
+          </td>
+        </tr>
+        "#,
+        code
+    );
+
+    let html = generate_base_template(
+        "Confirm Your Key",
+        "You could read this, so your key works. Enter the code in the app to turn on encrypted mail.",
+        &action_html,
+    );
+
+    let text = format!(
+        "ONYX\n\nConfirm your encryption key.\n\nYour code: {}\n\nExpires in 15 minutes.\n\nIf you didn't upload a key, ignore this email.\n\n—\nStateless relay · Zero-knowledge · No logs",
+        code
+    );
+
+    EmailContent {
+        subject,
+        html,
+        text,
+    }
+}
+
+/// Sent in place of the real message when the recipient's key has expired,
+/// so nothing sensitive goes out unencrypted.
+pub fn key_expired_email() -> EmailContent {
+    let subject = "Onyx security notice".to_string();
+
+    let action_html = r#"
+        <tr>
+          <td align="center" style="padding: 0 0 4px 0;">
+            <p style="margin: 0; font-size: 13px; color: #a1a1aa; line-height: 1.6; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;">Upload a renewed public key in <span style="color: #a78bfa; font-weight: 600;">Settings → Security</span> to receive your messages again.</p>
+          </td>
+        </tr>
+        "#;
+
+    let html = generate_base_template(
+        "Your Key Has Expired",
+        "We held back a message because your OpenPGP key is no longer valid.",
+        action_html,
+    );
+
+    let text = "ONYX\n\nYour OpenPGP key has expired.\n\nWe held back a message rather than send it unencrypted. Upload a renewed public key in Settings → Security to receive your messages again.\n\n—\nStateless relay · Zero-knowledge · No logs".to_string();
+
+    EmailContent {
+        subject,
+        html,
+        text,
+    }
+}
+
+pub fn test_email() -> EmailContent {
+    let subject = "Onyx relay test".to_string();
+
+    let html = generate_base_template(
+        "Relay Test",
+        "This message was queued by an operator to check delivery. No action needed.",
+        "",
+    );
+
+    let text = "ONYX\n\nRelay test.\n\nThis message was queued by an operator to check delivery. No action needed.\n\n—\nStateless relay · Zero-knowledge · No logs".to_string();
+
+    EmailContent {
+        subject,
+        html,
+        text,
+    }
+}
+
+/// Body of a calendar invite. The `.ics` itself is attached by the caller.
+pub fn invite_email(
+    subject_prefix: &str,
+    title: &str,
+    summary: &str,
+    when: &str,
+    location: Option<&str>,
+    organizer: &str,
+) -> EmailContent {
+    let subject = format!("{}: {}", subject_prefix, summary);
+
+    let location_row = location
+        .map(|l| {
+            format!(
+                r#"<p style="margin: 6px 0 0 0; font-size: 13px; color: #a1a1aa; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;">{}</p>"#,
+                escape_html(l)
+            )
+        })
+        .unwrap_or_default();
+
+    let action_html = format!(
+        r#"
+        <tr>
+          <td align="center" style="padding: 0 0 4px 0;">
+            <p style="margin: 0; font-size: 18px; font-weight: 700; color: #fafafa; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;">{summary}</p>
+            <p style="margin: 10px 0 0 0; font-size: 13px; color: #a78bfa; font-weight: 600; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;">{when}</p>
+            {location_row}
+          </td>
+        </tr>
+        <tr>
+          <td align="center" style="padding: 12px 0 4px 0;">
+            <p style="margin: 0; font-size: 12px; color: #52525b; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;">Organized by {organizer}</p>
+          </td>
+        </tr>
+        "#,
+        summary = escape_html(summary),
+        when = escape_html(when),
+        location_row = location_row,
+        organizer = escape_html(organizer),
+    );
+
+    let html = generate_base_template(
+        title,
+        "Use your calendar app to respond.",
+        &action_html,
+    );
+
+    let text = format!(
+        "ONYX\n\n{}\n\n{}\n{}\n{}Organized by {}\n\n—\nStateless relay · Zero-knowledge · No logs",
+        title,
+        summary,
+        when,
+        location.map(|l| format!("{}\n", l)).unwrap_or_default(),
+        organizer
+    );
+
+    EmailContent {
+        subject,
+        html,
+        text,
+    }
+}
+
+// Event fields come from users, unlike the codes and links above
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 fn generate_base_template(title: &str, message: &str, content: &str) -> String {
     format!(
         r#"
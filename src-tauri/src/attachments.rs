@@ -0,0 +1,339 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use std::path::{Path, PathBuf};
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, Manager, State};
+
+pub const URI_SCHEME: &str = "onyx-attachment";
+const URI_PREFIX: &str = "onyx-attachment://";
+
+// Unreferenced blobs younger than this are kept: the note that will embed
+// them may not have been saved yet.
+const GC_GRACE_SECS: i64 = 3600;
+
+#[derive(Serialize, FromRow)]
+pub struct Attachment {
+    pub hash: String,
+    pub size: i64,
+    pub mime: String,
+    pub original_name: Option<String>,
+    pub ref_count: i64,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct StoredAttachment {
+    pub hash: String,
+    pub size: i64,
+    pub mime: String,
+    /// What the editor embeds, e.g. `onyx-attachment://3a7b…`
+    pub uri: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct GcReport {
+    pub removed: u64,
+    pub bytes_freed: u64,
+}
+
+pub fn attachments_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
+    let app_dir = app_handle.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(app_dir.join("attachments"))
+}
+
+// Sharded by the first byte so no directory grows too large
+pub(crate) fn blob_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join(&hash[..2]).join(hash)
+}
+
+fn is_hash(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn mime_for(name: &str) -> &'static str {
+    let ext = name.rsplit_once('.').map(|(_, e)| e.to_lowercase()).unwrap_or_default();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "txt" | "md" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Write a blob (once per distinct content) and register it.
+pub async fn store(
+    conn: &mut SqliteConnection,
+    dir: &Path,
+    name: &str,
+    data: &[u8],
+) -> Result<StoredAttachment, String> {
+    let hash = sha256_hex(data);
+    let path = blob_path(dir, &hash);
+    let mime = mime_for(name);
+
+    if !path.exists() {
+        let parent = path.parent().ok_or("Invalid attachment path")?;
+        tokio::fs::create_dir_all(parent).await.map_err(|e| e.to_string())?;
+
+        // Write then rename, so a crash never leaves a truncated blob under its hash
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, data)
+            .await
+            .map_err(|e| format!("Failed to write attachment: {}", e))?;
+        tokio::fs::rename(&tmp, &path).await.map_err(|e| e.to_string())?;
+    }
+
+    sqlx::query(
+        "INSERT INTO attachments (hash, size, mime, original_name) VALUES ($1, $2, $3, $4)
+         ON CONFLICT(hash) DO NOTHING",
+    )
+    .bind(&hash)
+    .bind(data.len() as i64)
+    .bind(mime)
+    .bind(name)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(StoredAttachment {
+        uri: format!("{}{}", URI_PREFIX, hash),
+        size: data.len() as i64,
+        mime: mime.to_string(),
+        hash,
+    })
+}
+
+/// Hashes of attachments referenced from note content.
+pub fn referenced_hashes(content: &str) -> Vec<String> {
+    let mut hashes: Vec<String> = content
+        .match_indices(URI_PREFIX)
+        .filter_map(|(i, _)| {
+            let rest = &content[i + URI_PREFIX.len()..];
+            // Windows serves custom schemes as http://<scheme>.localhost/, so
+            // links may carry a `localhost/` host in front of the hash
+            let rest = rest.strip_prefix("localhost/").unwrap_or(rest);
+            rest.get(..64).filter(|h| is_hash(h)).map(str::to_string)
+        })
+        .collect();
+    hashes.sort();
+    hashes.dedup();
+    hashes
+}
+
+/// Re-derive which attachments the note references. Reference counts follow
+/// through triggers on `note_attachments`.
+pub async fn sync_refs(conn: &mut SqliteConnection, note_id: i64, content: &str) -> Result<(), sqlx::Error> {
+    let hashes = referenced_hashes(content);
+
+    let current: Vec<(String,)> =
+        sqlx::query_as("SELECT hash FROM note_attachments WHERE note_id = $1 ORDER BY hash")
+            .bind(note_id)
+            .fetch_all(&mut *conn)
+            .await?;
+    if current.iter().map(|r| &r.0).eq(hashes.iter()) {
+        return Ok(());
+    }
+
+    sqlx::query("DELETE FROM note_attachments WHERE note_id = $1")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for hash in &hashes {
+        // Unknown hashes (e.g. pasted from another workspace) are ignored
+        sqlx::query(
+            "INSERT OR IGNORE INTO note_attachments (note_id, hash)
+             SELECT $1, hash FROM attachments WHERE hash = $2",
+        )
+        .bind(note_id)
+        .bind(hash)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+// --- Commands ---
+
+/// Store pasted or dropped bytes. Embed the returned `uri` in the note.
+#[tauri::command]
+pub async fn store_attachment(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    name: String,
+    data: Vec<u8>,
+) -> Result<StoredAttachment, String> {
+    println!("Backend: store_attachment: {} ({} bytes)", name, data.len());
+    let dir = attachments_dir(&app_handle)?;
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    store(&mut conn, &dir, &name, &data).await
+}
+
+/// Store a file from disk, e.g. one picked in the file dialog.
+#[tauri::command]
+pub async fn import_attachment(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    path: String,
+) -> Result<StoredAttachment, String> {
+    let path = PathBuf::from(path);
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("attachment")
+        .to_string();
+    let data = tokio::fs::read(&path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let dir = attachments_dir(&app_handle)?;
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    store(&mut conn, &dir, &name, &data).await
+}
+
+#[tauri::command]
+pub async fn list_note_attachments(
+    pool: State<'_, SqlitePool>,
+    note_id: i64,
+) -> Result<Vec<Attachment>, String> {
+    sqlx::query_as::<_, Attachment>(
+        "SELECT a.hash, a.size, a.mime, a.original_name, a.ref_count, a.created_at
+         FROM attachments a JOIN note_attachments na ON na.hash = a.hash
+         WHERE na.note_id = $1 ORDER BY a.created_at",
+    )
+    .bind(note_id)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn gc_attachments(app_handle: AppHandle, pool: State<'_, SqlitePool>) -> Result<GcReport, String> {
+    let dir = attachments_dir(&app_handle)?;
+    collect_garbage(&pool, &dir).await
+}
+
+/// Delete blobs no note references any more (trashed notes still count), and
+/// stray files that have no row at all.
+pub async fn collect_garbage(pool: &SqlitePool, dir: &Path) -> Result<GcReport, String> {
+    let mut report = GcReport::default();
+
+    let orphans: Vec<(String, i64)> = sqlx::query_as(
+        "DELETE FROM attachments WHERE ref_count <= 0 AND created_at < datetime('now', $1)
+         RETURNING hash, size",
+    )
+    .bind(format!("-{} seconds", GC_GRACE_SECS))
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for (hash, size) in orphans {
+        match tokio::fs::remove_file(blob_path(dir, &hash)).await {
+            Ok(()) => {
+                report.removed += 1;
+                report.bytes_freed += size as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Failed to remove attachment {}: {}", hash, e),
+        }
+    }
+
+    let known: Vec<(String,)> = sqlx::query_as("SELECT hash FROM attachments")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let known: std::collections::HashSet<String> = known.into_iter().map(|r| r.0).collect();
+
+    let Ok(mut shards) = tokio::fs::read_dir(dir).await else {
+        return Ok(report);
+    };
+    while let Ok(Some(shard)) = shards.next_entry().await {
+        let Ok(mut files) = tokio::fs::read_dir(shard.path()).await else {
+            continue;
+        };
+        while let Ok(Some(file)) = files.next_entry().await {
+            let name = file.file_name().to_string_lossy().to_string();
+            if known.contains(&name) {
+                continue;
+            }
+            // Leftover .tmp files and blobs whose row was never committed
+            let modified = file.metadata().await.ok().and_then(|m| m.modified().ok());
+            let old_enough = modified
+                .and_then(|m| m.elapsed().ok())
+                .is_some_and(|age| age.as_secs() as i64 > GC_GRACE_SECS);
+            if old_enough {
+                let size = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+                if tokio::fs::remove_file(file.path()).await.is_ok() {
+                    report.removed += 1;
+                    report.bytes_freed += size;
+                }
+            }
+        }
+    }
+
+    if report.removed > 0 {
+        println!("Attachments: removed {} blobs ({} bytes)", report.removed, report.bytes_freed);
+    }
+    Ok(report)
+}
+
+// --- URI Scheme ---
+
+/// Serve `onyx-attachment://<hash>` to the webview straight from disk.
+pub async fn handle_uri(app_handle: AppHandle, request: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let uri = request.uri();
+    let host = uri.host().unwrap_or_default();
+    let hash = if is_hash(host) {
+        host.to_string()
+    } else {
+        uri.path().trim_start_matches('/').to_string()
+    };
+
+    match serve(&app_handle, &hash).await {
+        Ok((mime, data)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, mime)
+            .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .body(data),
+        Err(status) => Response::builder().status(status).body(Vec::new()),
+    }
+    .unwrap_or_else(|_| Response::new(Vec::new()))
+}
+
+async fn serve(app_handle: &AppHandle, hash: &str) -> Result<(String, Vec<u8>), StatusCode> {
+    if !is_hash(hash) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let pool = app_handle
+        .try_state::<SqlitePool>()
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let row: Option<(String,)> = sqlx::query_as("SELECT mime FROM attachments WHERE hash = $1")
+        .bind(hash)
+        .fetch_optional(&*pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let (mime,) = row.ok_or(StatusCode::NOT_FOUND)?;
+
+    let dir = attachments_dir(app_handle).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let data = tokio::fs::read(blob_path(&dir, hash))
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok((mime, data))
+}
@@ -0,0 +1,160 @@
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use tauri::State;
+
+// Per call to get_pending_changes, unless asked otherwise
+const DEFAULT_PAGE: i64 = 500;
+
+/// An entry of the change log: a note changed and not yet uploaded. The
+/// triggers in the `note_changes_compaction` migration write them, in the
+/// transaction of the change, keeping the latest per note.
+#[derive(Serialize, FromRow)]
+pub struct NoteChange {
+    /// Increases with every change; use it as the cursor
+    pub seq: i64,
+    pub note_id: i64,
+    pub local_uuid: Option<String>,
+    /// As of the change, so deletes can still be matched to the cloud copy
+    pub pb_id: Option<String>,
+    /// `create`, `update` or `delete`
+    pub op: String,
+    pub changed_at: String,
+}
+
+/// Stop logging changes until `resume`, for writes that came from the cloud
+/// and so have nothing to upload. Both must run in the same transaction, so
+/// other connections never see the log paused.
+pub(crate) async fn pause(conn: &mut SqliteConnection) -> Result<(), String> {
+    sqlx::query("INSERT OR IGNORE INTO note_changes_paused (id) VALUES (1)")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub(crate) async fn resume(conn: &mut SqliteConnection) -> Result<(), String> {
+    sqlx::query("DELETE FROM note_changes_paused")
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Log an update the triggers didn't, e.g. a merge made while paused.
+pub(crate) async fn log_update(conn: &mut SqliteConnection, note_id: i64) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO note_changes (note_id, local_uuid, pb_id, op)
+         SELECT id, local_uuid, pb_id, 'update' FROM notes WHERE id = $1",
+    )
+    .bind(note_id)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM note_changes WHERE note_id = $1 AND seq < (SELECT MAX(seq) FROM note_changes WHERE note_id = $1)")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// --- Commands ---
+
+/// Changes not yet acknowledged, oldest first, after the `since` cursor (the
+/// `seq` of the last one already read). A note has at most one entry, for
+/// its latest change; uploading its current state covers it.
+#[tauri::command]
+pub async fn get_pending_changes(
+    pool: State<'_, SqlitePool>,
+    since: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Vec<NoteChange>, String> {
+    sqlx::query_as::<_, NoteChange>(
+        "SELECT seq, note_id, local_uuid, pb_id, op, changed_at FROM note_changes
+         WHERE seq > $1 ORDER BY seq LIMIT $2",
+    )
+    .bind(since.unwrap_or(0))
+    .bind(limit.unwrap_or(DEFAULT_PAGE).max(1))
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Acknowledge every change up to and including `seq` once it's uploaded.
+/// They're dropped from the log; later changes stay pending.
+#[tauri::command]
+pub async fn ack_changes(pool: State<'_, SqlitePool>, seq: i64) -> Result<u64, String> {
+    let result = sqlx::query("DELETE FROM note_changes WHERE seq <= $1")
+        .bind(seq)
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("Backend: ack_changes: {} up to {}", result.rows_affected(), seq);
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    async fn log(pool: &SqlitePool) -> Vec<(i64, String)> {
+        sqlx::query_as("SELECT note_id, op FROM note_changes ORDER BY seq")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn set_content(conn: &mut SqliteConnection, id: i64, content: &str) {
+        sqlx::query("UPDATE notes SET content = $1 WHERE id = $2")
+            .bind(content)
+            .bind(id)
+            .execute(conn)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_one_entry_per_note() {
+        let db = test_support::database().await;
+        let mut conn = db.pool.acquire().await.unwrap();
+        let a = test_support::add_note(&db.pool, "A", "1").await;
+        let b = test_support::add_note(&db.pool, "B", "1").await;
+        set_content(&mut conn, a, "2").await;
+        set_content(&mut conn, a, "3").await;
+        // Not uploaded yet, so still a create
+        assert_eq!(log(&db.pool).await, [(b, "create".to_string()), (a, "create".to_string())]);
+
+        sqlx::query("DELETE FROM note_changes").execute(&db.pool).await.unwrap();
+        set_content(&mut conn, a, "4").await;
+        set_content(&mut conn, b, "2").await;
+        sqlx::query("UPDATE notes SET deleted_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(a)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(log(&db.pool).await, [(b, "update".to_string()), (a, "delete".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn logs_nothing_while_paused() {
+        let db = test_support::database().await;
+        let id = test_support::add_note(&db.pool, "A", "1").await;
+        sqlx::query("DELETE FROM note_changes").execute(&db.pool).await.unwrap();
+
+        let mut tx = db.pool.begin().await.unwrap();
+        pause(&mut tx).await.unwrap();
+        set_content(&mut tx, id, "from the cloud").await;
+        sqlx::query("INSERT INTO notes (title, content) VALUES ('B', '')")
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        resume(&mut tx).await.unwrap();
+        log_update(&mut tx, id).await.unwrap();
+        tx.commit().await.unwrap();
+        assert_eq!(log(&db.pool).await, [(id, "update".to_string())]);
+
+        set_content(&mut db.pool.acquire().await.unwrap(), id, "here").await;
+        assert_eq!(log(&db.pool).await, [(id, "update".to_string())]);
+    }
+}
@@ -0,0 +1,888 @@
+use crate::{attachments, commands, locked, tags, vault, yjs};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Connection, SqliteConnection};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+// Unencrypted: the salt and parameters every device needs to derive the key
+pub const KEY_FILE: &str = "onyx.json";
+pub const NOTES_PREFIX: &str = "notes/";
+pub const ATTACHMENTS_PREFIX: &str = "attachments/";
+const INDEX_FILE: &str = "index";
+// Leading byte of every object, for when the format has to change
+const FORMAT_VERSION: u8 = 1;
+const KEY_CHECK: &[u8] = b"onyx-sync";
+
+/// An object on the remote.
+pub struct Object {
+    pub data: Vec<u8>,
+    pub etag: String,
+}
+
+/// Where encrypted objects are kept. Names are `/`-separated, e.g. `notes/<uuid>`.
+pub trait ObjectStore {
+    /// Name -> ETag of the objects under `prefix`.
+    fn list(&self, prefix: &str) -> impl Future<Output = Result<HashMap<String, String>, String>> + Send;
+
+    fn get(&self, name: &str) -> impl Future<Output = Result<Option<Object>, String>> + Send;
+
+    /// Store `data` if the object is still at `expected` (`None`: doesn't exist
+    /// yet). Returns the new ETag, or `None` if someone else changed it first.
+    fn put(
+        &self,
+        name: &str,
+        data: Vec<u8>,
+        expected: Option<&str>,
+    ) -> impl Future<Output = Result<Option<String>, String>> + Send;
+
+    /// Delete the object if it's still at `expected`. Returns whether it did.
+    fn delete(&self, name: &str, expected: &str) -> impl Future<Output = Result<bool, String>> + Send;
+}
+
+#[derive(Serialize, Default)]
+pub struct CloudSyncReport {
+    pub uploaded: usize,
+    pub downloaded: usize,
+    /// Notes removed here or on the remote to follow a delete on the other side
+    pub deleted: usize,
+    /// Titles of notes created from edits made on both sides
+    pub conflicts: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+/// Encrypted list of every note and attachment object with its ETag, rewritten
+/// by each sync that changes the remote.
+#[derive(Serialize, Deserialize)]
+struct Index {
+    objects: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    kdf: String,
+    salt: String,
+    /// `KEY_CHECK` sealed with the key, so a wrong passphrase is caught up front
+    check: String,
+}
+
+/// A note as stored on the remote.
+#[derive(Serialize, Deserialize, PartialEq)]
+struct NoteObject {
+    uuid: String,
+    title: String,
+    content: String,
+    /// Folder path as shown in the app, `/`-separated
+    folder: Option<String>,
+    tags: Vec<String>,
+    created: Option<String>,
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(value: &str) -> Result<Vec<u8>, String> {
+    value
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| "Malformed hex".to_string())
+        })
+        .collect()
+}
+
+// The object name is bound in as associated data, so the server can't swap
+// one note's ciphertext for another's
+fn seal(key: &[u8; 32], name: &str, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let mut nonce = [0u8; locked::NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = XChaCha20Poly1305::new(key.into())
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg: plaintext, aad: name.as_bytes() })
+        .map_err(|_| "Failed to encrypt".to_string())?;
+
+    let mut sealed = Vec::with_capacity(1 + nonce.len() + ciphertext.len());
+    sealed.push(FORMAT_VERSION);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn open(key: &[u8; 32], name: &str, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let (version, rest) = sealed.split_first().ok_or("Empty object")?;
+    if *version != FORMAT_VERSION || rest.len() < locked::NONCE_LEN {
+        return Err(format!("Unsupported object format in {}", name));
+    }
+    let (nonce, ciphertext) = rest.split_at(locked::NONCE_LEN);
+    XChaCha20Poly1305::new(key.into())
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: name.as_bytes() })
+        .map(Zeroizing::new)
+        .map_err(|_| format!("{} could not be decrypted; it was changed or uses another key", name))
+}
+
+/// Derive the sync key from `passphrase`. The first device to connect writes
+/// the key file; later ones check the passphrase against it.
+pub async fn unlock<S: ObjectStore>(store: &S, passphrase: &str) -> Result<locked::NoteKey, String> {
+    match store.get(KEY_FILE).await? {
+        Some(object) => {
+            let file: KeyFile = serde_json::from_slice(&object.data).map_err(|e| format!("Invalid {}: {}", KEY_FILE, e))?;
+            let key = locked::derive_key(passphrase, &from_hex(&file.salt)?, &file.kdf).await?;
+            open(&key, KEY_FILE, &from_hex(&file.check)?).map_err(|_| "Wrong sync passphrase".to_string())?;
+            Ok(key)
+        }
+        None => {
+            let mut salt = [0u8; locked::SALT_LEN];
+            rand::thread_rng().fill_bytes(&mut salt);
+            let kdf = locked::kdf_string();
+            let key = locked::derive_key(passphrase, &salt, &kdf).await?;
+            let file = KeyFile {
+                kdf,
+                salt: to_hex(&salt),
+                check: to_hex(&seal(&key, KEY_FILE, KEY_CHECK)?),
+            };
+            let data = serde_json::to_vec_pretty(&file).map_err(|e| e.to_string())?;
+            if store.put(KEY_FILE, data, None).await?.is_none() {
+                return Err("Another device is setting up this remote; try again".to_string());
+            }
+            Ok(key)
+        }
+    }
+}
+
+// Attachment names are keyed, so the server can't confirm a known file by its hash
+fn attachment_name(key: &[u8; 32], hash: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(key);
+    hasher.update(hash.as_bytes());
+    format!("{}{}", ATTACHMENTS_PREFIX, to_hex(&hasher.finalize()))
+}
+
+// --- Manifest ---
+
+/// What was last synced, per object: its ETag on the remote and the hash of
+/// its plaintext. Comparing against it tells which side changed.
+struct Synced {
+    etag: String,
+    hash: String,
+}
+
+async fn load_manifest(conn: &mut SqliteConnection, provider: &str) -> Result<HashMap<String, Synced>, String> {
+    let rows: Vec<(String, String, String)> =
+        sqlx::query_as("SELECT name, etag, hash FROM sync_objects WHERE provider = $1")
+            .bind(provider)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    Ok(rows
+        .into_iter()
+        .map(|(name, etag, hash)| (name, Synced { etag, hash }))
+        .collect())
+}
+
+async fn record(conn: &mut SqliteConnection, provider: &str, name: &str, etag: &str, hash: &str) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO sync_objects (provider, name, etag, hash) VALUES ($1, $2, $3, $4)
+         ON CONFLICT(provider, name) DO UPDATE SET etag = excluded.etag, hash = excluded.hash",
+    )
+    .bind(provider)
+    .bind(name)
+    .bind(etag)
+    .bind(hash)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn forget(conn: &mut SqliteConnection, provider: &str, name: &str) -> Result<(), String> {
+    sqlx::query("DELETE FROM sync_objects WHERE provider = $1 AND name = $2")
+        .bind(provider)
+        .bind(name)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Forget everything synced with `provider`, e.g. when it points somewhere new.
+pub async fn reset(conn: &mut SqliteConnection, provider: &str) -> Result<(), String> {
+    sqlx::query("DELETE FROM sync_objects WHERE provider = $1")
+        .bind(provider)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// --- Notes ---
+
+struct LocalNote {
+    title: String,
+    data: Vec<u8>,
+    hash: String,
+}
+
+// Locked notes aren't uploaded; see `locked_names`
+async fn local_notes(conn: &mut SqliteConnection) -> Result<HashMap<String, LocalNote>, String> {
+    vault::assign_uuids(conn).await?;
+    let tags = vault::load_tags(conn).await?;
+    let folders = vault::folder_paths(conn).await?;
+
+    let mut notes = HashMap::new();
+    for note in vault::load_notes(conn).await?.into_iter().filter(|n| !n.locked) {
+        let object = NoteObject {
+            uuid: note.local_uuid.clone().unwrap_or_default(),
+            title: note.title.clone(),
+            content: note.content.clone().unwrap_or_default(),
+            folder: note.folder_id.and_then(|id| folders.get(&id)).map(|(name, _)| name.clone()),
+            tags: tags.get(&note.id).cloned().unwrap_or_default(),
+            created: note.created_at.clone(),
+        };
+        let data = serde_json::to_vec(&object).map_err(|e| e.to_string())?;
+        notes.insert(
+            format!("{}{}", NOTES_PREFIX, object.uuid),
+            LocalNote {
+                title: note.title,
+                hash: vault::sha256_hex(&data),
+                data,
+            },
+        );
+    }
+    Ok(notes)
+}
+
+// Locking isn't deleting: the remote keeps the copy last uploaded, and
+// these are left out of the sync so neither side takes it for a delete
+async fn locked_names(conn: &mut SqliteConnection) -> Result<HashSet<String>, String> {
+    let uuids: Vec<String> = sqlx::query_scalar(
+        "SELECT local_uuid FROM notes
+         WHERE local_uuid IS NOT NULL AND deleted_at IS NULL AND locked_ciphertext IS NOT NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(uuids.into_iter().map(|uuid| format!("{}{}", NOTES_PREFIX, uuid)).collect())
+}
+
+/// Create or update the note from its remote copy. A note deleted here comes
+/// back, since an edit wins over a delete.
+async fn apply_note(conn: &mut SqliteConnection, note: &NoteObject) -> Result<(), String> {
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    let existing: Option<(i64, bool)> =
+        sqlx::query_as("SELECT id, locked_ciphertext IS NOT NULL FROM notes WHERE local_uuid = $1")
+            .bind(&note.uuid)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    let mut folders = HashMap::from([(String::new(), None)]);
+    let folder_id = vault::ensure_folders(&mut tx, note.folder.as_deref().unwrap_or_default(), &mut folders).await?;
+
+    let id = match existing {
+        Some((_, true)) => return Err("Note is locked".to_string()),
+        Some((id, false)) => {
+            crate::revisions::snapshot(&mut tx, id, "sync", false)
+                .await
+                .map_err(|e| e.to_string())?;
+            sqlx::query("UPDATE notes SET title = $1, content = $2, folder_id = $3, deleted_at = NULL WHERE id = $4")
+                .bind(&note.title)
+                .bind(&note.content)
+                .bind(folder_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            id
+        }
+        None => sqlx::query(
+            "INSERT INTO notes (title, content, folder_id, local_uuid, created_at)
+             VALUES ($1, $2, $3, $4, COALESCE($5, CURRENT_TIMESTAMP))",
+        )
+        .bind(&note.title)
+        .bind(&note.content)
+        .bind(folder_id)
+        .bind(&note.uuid)
+        .bind(&note.created)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?
+        .last_insert_rowid(),
+    };
+
+    commands::reindex(&mut tx, id, &note.content).await?;
+    yjs::synced(&mut tx, id, &note.content).await?;
+    tags::set_manual(&mut tx, id, &note.tags).await?;
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+async fn trash_note(conn: &mut SqliteConnection, uuid: &str) -> Result<u64, String> {
+    let result = sqlx::query(
+        "UPDATE notes SET deleted_at = CURRENT_TIMESTAMP
+         WHERE local_uuid = $1 AND deleted_at IS NULL AND locked_ciphertext IS NULL",
+    )
+    .bind(uuid)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
+
+/// The remote as this sync sees it. Writes go through here so the object
+/// list stays current for the index.
+struct Remote<'a, S> {
+    store: &'a S,
+    key: &'a [u8; 32],
+    provider: &'a str,
+    /// Name -> ETag of every note and attachment object
+    objects: HashMap<String, String>,
+    changed: bool,
+    /// A conditional write found the remote not as `objects` has it
+    stale: bool,
+}
+
+impl<S: ObjectStore> Remote<'_, S> {
+    async fn put(&mut self, conn: &mut SqliteConnection, name: &str, plaintext: &[u8], expected: Option<&str>) -> Result<Option<String>, String> {
+        self.begin_write(conn).await?;
+        let etag = self.store.put(name, seal(self.key, name, plaintext)?, expected).await?;
+        match &etag {
+            Some(etag) => {
+                self.objects.insert(name.to_string(), etag.clone());
+            }
+            None => self.stale = true,
+        }
+        Ok(etag)
+    }
+
+    async fn delete(&mut self, conn: &mut SqliteConnection, name: &str, expected: &str) -> Result<bool, String> {
+        self.begin_write(conn).await?;
+        let deleted = self.store.delete(name, expected).await?;
+        if deleted {
+            self.objects.remove(name);
+        } else {
+            self.stale = true;
+        }
+        Ok(deleted)
+    }
+
+    // Until the index is rewritten it doesn't list what's written now, so
+    // stop trusting it in case this sync is cut short
+    async fn begin_write(&mut self, conn: &mut SqliteConnection) -> Result<(), String> {
+        if !self.changed {
+            forget(conn, self.provider, INDEX_FILE).await?;
+            self.changed = true;
+        }
+        Ok(())
+    }
+}
+
+async fn download_note<S: ObjectStore>(remote: &Remote<'_, S>, name: &str) -> Result<Option<(NoteObject, String, String)>, String> {
+    let Some(object) = remote.store.get(name).await? else {
+        return Ok(None);
+    };
+    let plaintext = open(remote.key, name, &object.data)?;
+    let note: NoteObject = serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid {}: {}", name, e))?;
+    if format!("{}{}", NOTES_PREFIX, note.uuid) != name {
+        return Err(format!("{} holds another note", name));
+    }
+    Ok(Some((note, vault::sha256_hex(&plaintext), object.etag)))
+}
+
+/// Upload, download or delete one note, whichever side changed since the
+/// last sync. When both did, ours stays the note and theirs becomes a copy.
+async fn sync_note<S: ObjectStore>(
+    conn: &mut SqliteConnection,
+    remote: &mut Remote<'_, S>,
+    name: &str,
+    local: Option<&LocalNote>,
+    synced: Option<&Synced>,
+    report: &mut CloudSyncReport,
+) -> Result<(), String> {
+    let provider = remote.provider;
+    let etag = remote.objects.get(name).cloned();
+    let local_changed = local.map(|l| &l.hash) != synced.map(|s| &s.hash);
+    let remote_changed = etag.as_ref() != synced.map(|s| &s.etag);
+    let uuid = name.trim_start_matches(NOTES_PREFIX);
+
+    match (local, etag) {
+        _ if !local_changed && !remote_changed => {}
+        (None, None) => forget(conn, provider, name).await?,
+        (Some(local), etag) if !remote_changed || (etag.is_none() && local_changed) => {
+            // Edited here, or edited here and deleted there: the edit wins
+            match remote.put(conn, name, &local.data, etag.as_deref()).await? {
+                Some(etag) => {
+                    record(conn, provider, name, &etag, &local.hash).await?;
+                    report.uploaded += 1;
+                }
+                None => report.skipped.push(format!("{}: changed on the server during sync", local.title)),
+            }
+        }
+        (None, Some(etag)) if !remote_changed => {
+            if remote.delete(conn, name, &etag).await? {
+                forget(conn, provider, name).await?;
+                report.deleted += 1;
+            }
+        }
+        (_, None) => {
+            report.deleted += trash_note(conn, uuid).await? as usize;
+            forget(conn, provider, name).await?;
+        }
+        (local, Some(_)) => {
+            let Some((note, hash, etag)) = download_note(remote, name).await? else {
+                return Ok(());
+            };
+            match local {
+                Some(local) if local_changed && local.hash != hash => {
+                    // Both sides edited: keep ours and theirs as a new note
+                    let title = format!("{} (conflict {})", note.title, conflict_stamp(conn).await?);
+                    let copy = NoteObject {
+                        uuid: uuid::Uuid::new_v4().to_string(),
+                        title: title.clone(),
+                        ..note
+                    };
+                    apply_note(conn, &copy).await?;
+                    report.conflicts.push(title);
+                    match remote.put(conn, name, &local.data, Some(&etag)).await? {
+                        Some(etag) => record(conn, provider, name, &etag, &local.hash).await?,
+                        None => report.skipped.push(format!("{}: changed on the server during sync", local.title)),
+                    }
+                }
+                Some(local) if local.hash == hash => record(conn, provider, name, &etag, &hash).await?,
+                // Edited there, and unchanged or deleted here
+                _ => {
+                    apply_note(conn, &note).await?;
+                    record(conn, provider, name, &etag, &hash).await?;
+                    report.downloaded += 1;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn conflict_stamp(conn: &mut SqliteConnection) -> Result<String, String> {
+    let stamp: (String,) = sqlx::query_as("SELECT strftime('%Y-%m-%d %H%M%S', 'now')")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(stamp.0)
+}
+
+// --- Attachments ---
+
+/// Upload attachments the remote doesn't have and download the ones synced
+/// notes embed. Attachments never change, so no manifest is needed; they
+/// are left on the remote when notes stop using them.
+async fn sync_attachments<S: ObjectStore>(
+    conn: &mut SqliteConnection,
+    remote: &mut Remote<'_, S>,
+    dir: &Path,
+    report: &mut CloudSyncReport,
+) -> Result<(), String> {
+    // From content rather than note_attachments, which only has the ones stored here
+    let notes: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT id, content FROM notes
+         WHERE deleted_at IS NULL AND locked_ciphertext IS NULL AND content IS NOT NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    let mut used: BTreeMap<String, Vec<(i64, &str)>> = BTreeMap::new();
+    for (id, content) in &notes {
+        for hash in attachments::referenced_hashes(content) {
+            used.entry(hash).or_default().push((*id, content));
+        }
+    }
+
+    for (hash, notes) in used {
+        let name = attachment_name(remote.key, &hash);
+        let blob = attachments::blob_path(dir, &hash);
+        let on_remote = remote.objects.contains_key(&name);
+        let result = if blob.exists() && !on_remote {
+            upload_attachment(conn, remote, &name, &blob, &hash).await
+        } else if !blob.exists() && on_remote {
+            download_attachment(conn, remote, &name, &hash, dir, &notes).await
+        } else {
+            continue;
+        };
+        match result {
+            Ok(true) => report.uploaded += 1,
+            Ok(false) => report.downloaded += 1,
+            Err(e) => report.skipped.push(format!("Attachment {}: {}", hash, e)),
+        }
+    }
+    Ok(())
+}
+
+// The plaintext is the file name, a newline, then the content
+async fn upload_attachment<S: ObjectStore>(
+    conn: &mut SqliteConnection,
+    remote: &mut Remote<'_, S>,
+    name: &str,
+    blob: &Path,
+    hash: &str,
+) -> Result<bool, String> {
+    let original_name: Option<(String,)> = sqlx::query_as("SELECT original_name FROM attachments WHERE hash = $1")
+        .bind(hash)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let file_name = original_name.map(|r| r.0).unwrap_or_else(|| hash.to_string());
+
+    let mut plaintext = Zeroizing::new(file_name.replace('\n', " ").into_bytes());
+    plaintext.push(b'\n');
+    plaintext.extend(tokio::fs::read(blob).await.map_err(|e| e.to_string())?);
+    // Already there means another device uploaded the same content
+    remote.put(conn, name, &plaintext, None).await?;
+    Ok(true)
+}
+
+async fn download_attachment<S: ObjectStore>(
+    conn: &mut SqliteConnection,
+    remote: &Remote<'_, S>,
+    name: &str,
+    hash: &str,
+    dir: &Path,
+    notes: &[(i64, &str)],
+) -> Result<bool, String> {
+    let object = remote.store.get(name).await?.ok_or("Missing on the server")?;
+    let plaintext = open(remote.key, name, &object.data)?;
+    let split = plaintext.iter().position(|b| *b == b'\n').ok_or("Malformed attachment")?;
+    let file_name = String::from_utf8_lossy(&plaintext[..split]).to_string();
+    let stored = attachments::store(conn, dir, &file_name, &plaintext[split + 1..]).await?;
+    if stored.hash != hash {
+        return Err("Content doesn't match its hash".to_string());
+    }
+    // The notes were indexed before the attachment was here
+    for (id, content) in notes {
+        attachments::sync_refs(conn, *id, content).await.map_err(|e| e.to_string())?;
+    }
+    Ok(false)
+}
+
+// --- Index ---
+
+async fn list_objects<S: ObjectStore>(store: &S) -> Result<HashMap<String, String>, String> {
+    let mut objects = store.list(NOTES_PREFIX).await?;
+    objects.extend(store.list(ATTACHMENTS_PREFIX).await?);
+    Ok(objects)
+}
+
+async fn read_index<S: ObjectStore>(store: &S, key: &[u8; 32]) -> Result<Option<(Index, String)>, String> {
+    let Some(object) = store.get(INDEX_FILE).await? else {
+        return Ok(None);
+    };
+    let plaintext = open(key, INDEX_FILE, &object.data)?;
+    let index = serde_json::from_slice(&plaintext).map_err(|e| format!("Invalid {}: {}", INDEX_FILE, e))?;
+    Ok(Some((index, object.etag)))
+}
+
+/// Replace the index with what this sync left on the remote. If another
+/// device got there first, list again so its objects aren't dropped.
+async fn write_index<S: ObjectStore>(conn: &mut SqliteConnection, remote: &Remote<'_, S>, mut expected: Option<String>) -> Result<bool, String> {
+    let mut objects = remote.objects.clone();
+    for _ in 0..3 {
+        let index = Index { objects };
+        let data = serde_json::to_vec(&index).map_err(|e| e.to_string())?;
+        let sealed = seal(remote.key, INDEX_FILE, &data)?;
+        if let Some(etag) = remote.store.put(INDEX_FILE, sealed, expected.as_deref()).await? {
+            record(conn, remote.provider, INDEX_FILE, &etag, "").await?;
+            return Ok(true);
+        }
+        objects = list_objects(remote.store).await?;
+        expected = remote.store.get(INDEX_FILE).await?.map(|o| o.etag);
+    }
+    Ok(false)
+}
+
+// --- Sync ---
+
+/// One full sync of notes and attachments with `store`. `provider` keys the
+/// manifest, so several remotes can be synced side by side.
+pub async fn sync<S: ObjectStore>(
+    conn: &mut SqliteConnection,
+    store: &S,
+    key: &[u8; 32],
+    provider: &str,
+    attachments_dir: &Path,
+) -> Result<CloudSyncReport, String> {
+    let mut report = CloudSyncReport::default();
+    let synced = load_manifest(conn, provider).await?;
+    let index = read_index(store, key).await?;
+    let index_etag = index.as_ref().map(|(_, etag)| etag.clone());
+
+    // The index only changes when a device writes, so if it's the one seen
+    // last time, it lists the remote and listing can be skipped
+    let fresh = index_etag.is_some() && index_etag.as_ref() == synced.get(INDEX_FILE).map(|s| &s.etag);
+    let (indexed, objects) = match index {
+        Some((index, _)) if fresh => (index.objects.clone(), index.objects),
+        Some((index, _)) => (index.objects, list_objects(store).await?),
+        None => (HashMap::new(), list_objects(store).await?),
+    };
+    let mut remote = Remote {
+        store,
+        key,
+        provider,
+        objects,
+        changed: false,
+        stale: false,
+    };
+
+    let local = local_notes(conn).await?;
+    let locked = locked_names(conn).await?;
+    let remote_names: Vec<String> = remote.objects.keys().filter(|n| n.starts_with(NOTES_PREFIX)).cloned().collect();
+    let names: BTreeSet<&String> = local
+        .keys()
+        .chain(remote_names.iter())
+        .chain(synced.keys().filter(|n| n.starts_with(NOTES_PREFIX)))
+        .filter(|n| !locked.contains(*n))
+        .collect();
+    for name in names {
+        let result = sync_note(conn, &mut remote, name, local.get(name), synced.get(name), &mut report).await;
+        if let Err(e) = result {
+            let title = local.get(name).map(|l| l.title.as_str()).unwrap_or(name);
+            report.skipped.push(format!("{}: {}", title, e));
+        }
+    }
+
+    sync_attachments(conn, &mut remote, attachments_dir, &mut report).await?;
+
+    // Another device wrote in the meantime; the index must list its objects too
+    if remote.stale {
+        remote.objects = list_objects(store).await?;
+    }
+    if remote.changed || remote.objects != indexed {
+        if !write_index(conn, &remote, index_etag).await? {
+            report.skipped.push("Another device is syncing; sync again to pick up its changes".to_string());
+        }
+    } else if let Some(etag) = index_etag.filter(|_| !fresh) {
+        record(conn, provider, INDEX_FILE, &etag, "").await?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    /// A remote kept in memory. ETags are a counter, so every write gets a new one.
+    #[derive(Default)]
+    struct MemoryStore {
+        objects: Mutex<HashMap<String, Object>>,
+        writes: AtomicUsize,
+        lists: AtomicUsize,
+        /// Objects another device writes just before this one's next write
+        race: Mutex<Vec<String>>,
+    }
+
+    impl MemoryStore {
+        fn names(&self) -> BTreeSet<String> {
+            self.objects.lock().unwrap().keys().cloned().collect()
+        }
+
+        fn etag(&self) -> String {
+            format!("\"{}\"", self.writes.fetch_add(1, Ordering::SeqCst))
+        }
+    }
+
+    impl ObjectStore for MemoryStore {
+        async fn list(&self, prefix: &str) -> Result<HashMap<String, String>, String> {
+            self.lists.fetch_add(1, Ordering::SeqCst);
+            let objects = self.objects.lock().unwrap();
+            Ok(objects
+                .iter()
+                .filter(|(name, _)| name.starts_with(prefix))
+                .map(|(name, object)| (name.clone(), object.etag.clone()))
+                .collect())
+        }
+
+        async fn get(&self, name: &str) -> Result<Option<Object>, String> {
+            let objects = self.objects.lock().unwrap();
+            Ok(objects.get(name).map(|o| Object { data: o.data.clone(), etag: o.etag.clone() }))
+        }
+
+        async fn put(&self, name: &str, data: Vec<u8>, expected: Option<&str>) -> Result<Option<String>, String> {
+            let mut objects = self.objects.lock().unwrap();
+            for raced in self.race.lock().unwrap().drain(..) {
+                objects.insert(raced, Object { data: Vec::new(), etag: self.etag() });
+            }
+            if objects.get(name).map(|o| o.etag.as_str()) != expected {
+                return Ok(None);
+            }
+            let etag = self.etag();
+            objects.insert(name.to_string(), Object { data, etag: etag.clone() });
+            Ok(Some(etag))
+        }
+
+        async fn delete(&self, name: &str, expected: &str) -> Result<bool, String> {
+            let mut objects = self.objects.lock().unwrap();
+            match objects.get(name) {
+                Some(object) if object.etag != expected => Ok(false),
+                // Already gone is as good as deleted, as on WebDAV
+                _ => {
+                    objects.remove(name);
+                    Ok(true)
+                }
+            }
+        }
+    }
+
+    /// A device: its database and attachments.
+    struct Device {
+        db: TestDb,
+        attachments: TempDir,
+    }
+
+    impl Device {
+        async fn new() -> Self {
+            Device { db: test_support::database().await, attachments: tempfile::tempdir().unwrap() }
+        }
+
+        async fn sync(&self, store: &MemoryStore) -> CloudSyncReport {
+            let mut conn = self.db.pool.acquire().await.unwrap();
+            let report = sync(&mut conn, store, &KEY, "memory", self.attachments.path()).await.unwrap();
+            assert!(report.skipped.is_empty(), "{:?}", report.skipped);
+            report
+        }
+    }
+
+    #[test]
+    fn open_reads_back_what_seal_wrote() {
+        let sealed = seal(&KEY, "notes/a", b"hello").unwrap();
+        assert_eq!(sealed[0], FORMAT_VERSION);
+        assert_eq!(open(&KEY, "notes/a", &sealed).unwrap().as_slice(), b"hello");
+    }
+
+    #[test]
+    fn open_refuses_another_name_or_key() {
+        let sealed = seal(&KEY, "notes/a", b"hello").unwrap();
+        // The server swapped two objects
+        assert!(open(&KEY, "notes/b", &sealed).is_err());
+        assert!(open(&[8; 32], "notes/a", &sealed).is_err());
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(&KEY, "notes/a", &tampered).is_err());
+        assert!(open(&KEY, "notes/a", &[]).is_err());
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+        assert_eq!(from_hex("000fff").unwrap(), [0, 15, 255]);
+        assert!(from_hex("0f0").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+
+    #[tokio::test]
+    async fn notes_travel_between_devices() {
+        let store = MemoryStore::default();
+        let (a, b) = (Device::new().await, Device::new().await);
+
+        test_support::add_note(&a.db.pool, "Groceries", "milk").await;
+        assert_eq!(a.sync(&store).await.uploaded, 1);
+        assert_eq!(b.sync(&store).await.downloaded, 1);
+        assert_eq!(test_support::notes(&b.db.pool).await, [("Groceries".to_string(), "milk".to_string())]);
+
+        sqlx::query("UPDATE notes SET content = 'milk, eggs'").execute(&b.db.pool).await.unwrap();
+        assert_eq!(b.sync(&store).await.uploaded, 1);
+        assert_eq!(a.sync(&store).await.downloaded, 1);
+        assert_eq!(test_support::notes(&a.db.pool).await, [("Groceries".to_string(), "milk, eggs".to_string())]);
+
+        sqlx::query("UPDATE notes SET deleted_at = CURRENT_TIMESTAMP").execute(&a.db.pool).await.unwrap();
+        assert_eq!(a.sync(&store).await.deleted, 1);
+        assert_eq!(b.sync(&store).await.deleted, 1);
+        assert!(test_support::notes(&b.db.pool).await.is_empty());
+        assert!(!store.names().iter().any(|n| n.starts_with(NOTES_PREFIX)));
+    }
+
+    #[tokio::test]
+    async fn edits_on_both_sides_keep_both() {
+        let store = MemoryStore::default();
+        let (a, b) = (Device::new().await, Device::new().await);
+        test_support::add_note(&a.db.pool, "Plan", "one").await;
+        a.sync(&store).await;
+        b.sync(&store).await;
+
+        sqlx::query("UPDATE notes SET content = 'two'").execute(&a.db.pool).await.unwrap();
+        sqlx::query("UPDATE notes SET content = 'three'").execute(&b.db.pool).await.unwrap();
+        a.sync(&store).await;
+        let report = b.sync(&store).await;
+        assert_eq!(report.conflicts.len(), 1);
+
+        let notes = test_support::notes(&b.db.pool).await;
+        assert_eq!(notes[0], ("Plan".to_string(), "three".to_string()));
+        assert!(notes[1].0.starts_with("Plan (conflict ") && notes[1].1 == "two");
+    }
+
+    #[tokio::test]
+    async fn locked_notes_stay_on_the_remote() {
+        let store = MemoryStore::default();
+        let (a, b) = (Device::new().await, Device::new().await);
+        test_support::add_note(&a.db.pool, "Diary", "secret").await;
+        a.sync(&store).await;
+        b.sync(&store).await;
+        let before = store.names();
+
+        sqlx::query("UPDATE notes SET content = '', locked_ciphertext = x'00'").execute(&a.db.pool).await.unwrap();
+        let report = a.sync(&store).await;
+        assert_eq!((report.uploaded, report.deleted), (0, 0));
+        assert_eq!(store.names(), before);
+
+        assert_eq!(b.sync(&store).await.deleted, 0);
+        assert_eq!(test_support::notes(&b.db.pool).await, [("Diary".to_string(), "secret".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn an_unchanged_index_saves_listing() {
+        let store = MemoryStore::default();
+        let (a, b) = (Device::new().await, Device::new().await);
+        test_support::add_note(&a.db.pool, "Groceries", "milk").await;
+        a.sync(&store).await;
+        b.sync(&store).await;
+
+        let lists = store.lists.load(Ordering::SeqCst);
+        b.sync(&store).await;
+        assert_eq!(store.lists.load(Ordering::SeqCst), lists);
+
+        // Another device wrote, so the index is no longer the one seen last
+        test_support::add_note(&a.db.pool, "Errands", "post office").await;
+        a.sync(&store).await;
+        let lists = store.lists.load(Ordering::SeqCst);
+        assert_eq!(b.sync(&store).await.downloaded, 1);
+        assert!(store.lists.load(Ordering::SeqCst) > lists);
+    }
+
+    #[tokio::test]
+    async fn the_index_keeps_objects_written_during_a_sync() {
+        let store = MemoryStore::default();
+        let a = Device::new().await;
+        test_support::add_note(&a.db.pool, "Groceries", "milk").await;
+        a.sync(&store).await;
+
+        // Another device adds a note and rewrites the index while this one syncs
+        let other = format!("{}{}", NOTES_PREFIX, uuid::Uuid::new_v4());
+        *store.race.lock().unwrap() = vec![other.clone(), INDEX_FILE.to_string()];
+        sqlx::query("UPDATE notes SET content = 'milk, eggs'").execute(&a.db.pool).await.unwrap();
+        a.sync(&store).await;
+
+        let (index, _) = read_index(&store, &KEY).await.unwrap().unwrap();
+        let mut expected = store.names();
+        expected.remove(INDEX_FILE);
+        assert!(expected.contains(&other));
+        assert_eq!(index.objects.into_keys().collect::<BTreeSet<_>>(), expected);
+    }
+}
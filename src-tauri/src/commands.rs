@@ -1,5 +1,7 @@
+use crate::conflicts::{self, ImportOutcome, PbImportResult};
+use crate::{attachments, changes, hlc, links, locked, revisions, settings, tags, yjs};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
 use tauri::State;
 
 #[derive(Serialize, FromRow)]
@@ -7,18 +9,25 @@ pub struct Note {
     pub id: i64,
     pub title: String,
     pub updated_at: String,
+    /// Hybrid logical clock reading of the last edit; send it along on upload
+    pub hlc: Option<String>,
     pub pb_id: Option<String>,
     pub local_uuid: Option<String>,
+    pub folder_id: Option<i64>,
+    pub locked: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct NoteDetail {
     pub id: i64,
     pub title: String,
+    /// `None` for locked notes, see `open_locked_note`
     pub content: Option<String>,
     pub updated_at: String,
+    pub hlc: Option<String>,
     pub pb_id: Option<String>,
     pub local_uuid: Option<String>,
+    pub locked: bool,
 }
 
 #[tauri::command]
@@ -31,23 +40,73 @@ pub async fn create_note(
     pool: State<'_, SqlitePool>,
     title: String,
     content: String,
+    folder_id: Option<i64>,
 ) -> Result<i64, String> {
-    let result = sqlx::query("INSERT INTO notes (title, content, local_uuid) VALUES ($1, $2, lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))),2,3) || '-' || substr('89ab',abs(random()) % 4 + 1, 1) || substr(lower(hex(randomblob(2))),2,3) || '-' || lower(hex(randomblob(6))))")
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let result = sqlx::query("INSERT INTO notes (title, content, folder_id, local_uuid) VALUES ($1, $2, $3, lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))),2,3) || '-' || substr('89ab',abs(random()) % 4 + 1, 1) || substr(lower(hex(randomblob(2))),2,3) || '-' || lower(hex(randomblob(6))))")
         .bind(title)
-        .bind(content)
-        .execute(&*pool)
+        .bind(&content)
+        .bind(folder_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let id = result.last_insert_rowid();
+    reindex(&mut tx, id, &content).await?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+// Bump when reindex() starts deriving something new, so existing notes get backfilled
+const CONTENT_INDEX_VERSION: i64 = 2;
+
+/// Refresh everything derived from a note's content.
+pub(crate) async fn reindex(conn: &mut SqliteConnection, id: i64, content: &str) -> Result<(), String> {
+    tags::sync_inline(&mut *conn, id, content)
+        .await
+        .map_err(|e| e.to_string())?;
+    links::sync_links(&mut *conn, id, content)
+        .await
+        .map_err(|e| e.to_string())?;
+    attachments::sync_refs(&mut *conn, id, content)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Run `reindex` over every note if the index was built by an older version.
+pub(crate) async fn reindex_all_if_stale(pool: &SqlitePool) -> Result<(), String> {
+    let current = settings::get(pool, "content_index_version").await?;
+    if current.as_deref() == Some(CONTENT_INDEX_VERSION.to_string().as_str()) {
+        return Ok(());
+    }
+
+    let notes: Vec<(i64, Option<String>)> = sqlx::query_as("SELECT id, content FROM notes")
+        .fetch_all(pool)
         .await
         .map_err(|e| e.to_string())?;
+    println!("Reindexing {} notes...", notes.len());
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    for (id, content) in notes {
+        reindex(&mut tx, id, content.as_deref().unwrap_or_default()).await?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
 
-    Ok(result.last_insert_rowid())
+    settings::set(pool, "content_index_version", &CONTENT_INDEX_VERSION.to_string()).await
 }
 
+/// All notes, or only those directly in `folder_id` when it is given.
 #[tauri::command]
-pub async fn get_notes(pool: State<'_, SqlitePool>) -> Result<Vec<Note>, String> {
+pub async fn get_notes(
+    pool: State<'_, SqlitePool>,
+    folder_id: Option<i64>,
+) -> Result<Vec<Note>, String> {
     println!("Backend: get_notes called");
     let notes = sqlx::query_as::<_, Note>(
-        "SELECT id, title, updated_at, pb_id, local_uuid FROM notes ORDER BY updated_at DESC, id DESC",
+        "SELECT id, title, updated_at, hlc, pb_id, local_uuid, folder_id, locked_ciphertext IS NOT NULL AS locked FROM notes WHERE deleted_at IS NULL AND ($1 IS NULL OR folder_id = $1) ORDER BY updated_at DESC, id DESC",
     )
+    .bind(folder_id)
     .fetch_all(&*pool)
     .await
     .map_err(|e| e.to_string())?;
@@ -61,7 +120,7 @@ pub async fn get_note_content(
     pool: State<'_, SqlitePool>,
 ) -> Result<Option<NoteDetail>, String> {
     let note = sqlx::query_as::<_, NoteDetail>(
-        "SELECT id, title, content, updated_at, pb_id, local_uuid FROM notes WHERE id = $1",
+        "SELECT id, title, content, updated_at, hlc, pb_id, local_uuid, locked_ciphertext IS NOT NULL AS locked FROM notes WHERE id = $1",
     )
     .bind(id)
     .fetch_optional(&*pool)
@@ -77,13 +136,29 @@ pub async fn update_note(
     title: String,
     content: String,
 ) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    // Locked notes are saved encrypted through save_locked_note
+    if locked::is_locked(&mut tx, id).await.map_err(|e| e.to_string())? {
+        return Err("Note is locked".to_string());
+    }
+
+    // Keep the state from before this burst of typing
+    revisions::snapshot(&mut tx, id, "edit", true)
+        .await
+        .map_err(|e| e.to_string())?;
+
     sqlx::query("UPDATE notes SET title = $1, content = $2 WHERE id = $3")
         .bind(title)
-        .bind(content)
+        .bind(&content)
         .bind(id)
-        .execute(&*pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| e.to_string())?;
+
+    reindex(&mut tx, id, &content).await?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
     Ok(())
 }
 
@@ -94,15 +169,28 @@ pub async fn update_note_pb_id(
     pb_id: String,
 ) -> Result<(), String> {
     println!("Backend: update_note_pb_id: id={} pb_id={}", id, pb_id);
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
     sqlx::query("UPDATE notes SET pb_id = $1 WHERE id = $2")
         .bind(pb_id)
         .bind(id)
-        .execute(&*pool)
+        .execute(&mut *tx)
         .await
         .map_err(|e| e.to_string())?;
-    Ok(())
+
+    // Just uploaded, so the cloud holds what's here
+    sqlx::query(
+        "INSERT OR REPLACE INTO note_sync_base (note_id, title, content)
+         SELECT id, title, COALESCE(content, '') FROM notes WHERE id = $1 AND locked_ciphertext IS NULL",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())
 }
 
+/// Bring in a note from PocketBase. An existing copy is merged with it
+/// against the version last synced, so local edits aren't lost.
 #[tauri::command]
 pub async fn import_note_from_pb(
     pool: State<'_, SqlitePool>,
@@ -111,62 +199,103 @@ pub async fn import_note_from_pb(
     content: String,
     updated_at: String,
     local_uuid: Option<String>,
-) -> Result<i64, String> {
+    hlc: Option<String>,
+) -> Result<PbImportResult, String> {
     println!("Backend: import_note_from_pb: {}", title);
 
-    // 1. Check for valid local_uuid if provided
-    let uuid_query = if let Some(ref uuid) = local_uuid {
-        Some(uuid.clone())
-    } else {
-        None
-    };
-
-    // 2. Check existence by PB_ID or UUID
-    let existing: Option<(i64,)> = sqlx::query_as(
-        "SELECT id FROM notes WHERE pb_id = $1 OR (local_uuid IS NOT NULL AND local_uuid = $2)",
+    // Check existence by PB_ID or UUID
+    let existing: Option<(i64, bool, bool)> = sqlx::query_as(
+        "SELECT id, deleted_at IS NOT NULL, locked_ciphertext IS NOT NULL FROM notes WHERE pb_id = $1 OR (local_uuid IS NOT NULL AND local_uuid = $2)",
     )
     .bind(&pb_id)
-    .bind(&uuid_query)
+    .bind(&local_uuid)
     .fetch_optional(&*pool)
     .await
     .map_err(|e| e.to_string())?;
 
-    if let Some((id,)) = existing {
-        println!("Backend: Note already exists (id={}). Updating...", id);
-        // Update existing note to match cloud state
-        sqlx::query("UPDATE notes SET title = $1, content = $2, updated_at = $3, pb_id = $4, local_uuid = COALESCE(local_uuid, $5) WHERE id = $6")
-            .bind(&title)
-            .bind(&content)
-            .bind(&updated_at)
+    if existing.is_none() {
+        // Purged here: the tombstone wins, as for a note still in the trash
+        let purged: Option<i64> = sqlx::query_scalar(
+            "SELECT note_id FROM note_tombstones WHERE pb_id = $1 OR (local_uuid IS NOT NULL AND local_uuid = $2)",
+        )
+        .bind(&pb_id)
+        .bind(&local_uuid)
+        .fetch_optional(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+        if let Some(id) = purged {
+            println!("Backend: Note {} was purged, keeping tombstone", id);
+            return Ok(PbImportResult { id, outcome: ImportOutcome::Unchanged });
+        }
+    }
+
+    if let Some((id, true, _)) = existing {
+        // Deleted here but not yet on the server; the tombstone wins so the
+        // delete can propagate instead of the note coming back.
+        println!("Backend: Note {} is in the trash, keeping tombstone", id);
+        return Ok(PbImportResult { id, outcome: ImportOutcome::Unchanged });
+    }
+
+    if let Some((id, _, true)) = existing {
+        // Writing the cloud content would put plaintext back next to the ciphertext
+        println!("Backend: Note {} is locked locally, skipping cloud update", id);
+        return Ok(PbImportResult { id, outcome: ImportOutcome::Unchanged });
+    }
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let stamp = hlc::receive(&mut tx, &updated_at, hlc.as_deref()).await?;
+    // The cloud already has what comes from it
+    changes::pause(&mut tx).await?;
+
+    if let Some((id, _, _)) = existing {
+        println!("Backend: Note already exists (id={}). Reconciling...", id);
+        sqlx::query("UPDATE notes SET pb_id = $1, local_uuid = COALESCE(local_uuid, $2) WHERE id = $3")
             .bind(&pb_id)
             .bind(&local_uuid)
             .bind(id)
-            .execute(&*pool)
+            .execute(&mut *tx)
             .await
             .map_err(|e| e.to_string())?;
+        let outcome = conflicts::reconcile(&mut tx, id, &title, &content, &stamp).await?;
+        changes::resume(&mut tx).await?;
+        // Except a merge, which holds local edits it hasn't seen
+        if outcome == ImportOutcome::Merged {
+            changes::log_update(&mut tx, id).await?;
+        }
 
-        return Ok(id);
+        tx.commit().await.map_err(|e| e.to_string())?;
+        return Ok(PbImportResult { id, outcome });
     }
 
-    // 3. Insert New
+    // Insert New
     let result = sqlx::query(
-        "INSERT INTO notes (title, content, updated_at, pb_id, local_uuid) VALUES ($1, $2, $3, $4, COALESCE($5, lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))),2,3) || '-' || substr('89ab',abs(random()) % 4 + 1, 1) || substr(lower(hex(randomblob(2))),2,3) || '-' || lower(hex(randomblob(6)))))",
+        "INSERT INTO notes (title, content, updated_at, hlc, pb_id, local_uuid) VALUES ($1, $2, $3, $4, $5, COALESCE($6, lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))),2,3) || '-' || substr('89ab',abs(random()) % 4 + 1, 1) || substr(lower(hex(randomblob(2))),2,3) || '-' || lower(hex(randomblob(6)))))",
     )
-    .bind(title)
-    .bind(content)
-    .bind(updated_at)
+    .bind(&title)
+    .bind(&content)
+    .bind(stamp.updated_at)
+    .bind(stamp.hlc)
     .bind(pb_id)
     .bind(local_uuid)
-    .execute(&*pool)
+    .execute(&mut *tx)
     .await
     .map_err(|e| e.to_string())?;
 
-    Ok(result.last_insert_rowid())
+    let id = result.last_insert_rowid();
+    reindex(&mut tx, id, &content).await?;
+    yjs::synced(&mut tx, id, &content).await?;
+    conflicts::set_base(&mut tx, id, &title, &content).await?;
+    changes::resume(&mut tx).await?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(PbImportResult { id, outcome: ImportOutcome::Created })
 }
 
+/// Move a note to the trash. It is purged later by `purge_note`, `empty_trash`
+/// or the auto-purge on startup.
 #[tauri::command]
 pub async fn delete_note(pool: State<'_, SqlitePool>, id: i64) -> Result<(), String> {
-    sqlx::query("DELETE FROM notes WHERE id = $1")
+    sqlx::query("UPDATE notes SET deleted_at = CURRENT_TIMESTAMP WHERE id = $1 AND deleted_at IS NULL")
         .bind(id)
         .execute(&*pool)
         .await
@@ -180,7 +309,7 @@ pub async fn delete_note_by_pb_id(
     pb_id: String,
 ) -> Result<(), String> {
     println!("Backend: delete_note_by_pb_id: {}", pb_id);
-    sqlx::query("DELETE FROM notes WHERE pb_id = $1")
+    sqlx::query("UPDATE notes SET deleted_at = CURRENT_TIMESTAMP WHERE pb_id = $1 AND deleted_at IS NULL")
         .bind(pb_id)
         .execute(&*pool)
         .await
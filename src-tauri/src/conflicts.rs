@@ -0,0 +1,240 @@
+use crate::hlc::Stamp;
+use crate::{commands, locked, revisions, yjs};
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use tauri::State;
+
+/// What `import_note_from_pb` did with the cloud copy.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportOutcome {
+    Created,
+    /// The cloud copy replaced an unedited local one
+    Updated,
+    /// Both sides changed different parts; the note now holds both edits
+    Merged,
+    /// Both sides changed the same part. The local copy is kept and the cloud
+    /// one waits in `list_note_conflicts`.
+    Conflicted,
+    /// Nothing to apply: same as the local copy, already seen, or the note is
+    /// trashed or locked here
+    Unchanged,
+}
+
+#[derive(Serialize)]
+pub struct PbImportResult {
+    pub id: i64,
+    pub outcome: ImportOutcome,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct NoteConflict {
+    pub note_id: i64,
+    pub local_title: String,
+    pub local_content: String,
+    pub remote_title: String,
+    pub remote_content: String,
+    pub remote_updated_at: Option<String>,
+    pub created_at: String,
+}
+
+/// Remember `title`/`content` as the version both sides last agreed on. The
+/// next import is merged against it.
+pub(crate) async fn set_base(conn: &mut SqliteConnection, note_id: i64, title: &str, content: &str) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO note_sync_base (note_id, title, content) VALUES ($1, $2, $3)
+         ON CONFLICT(note_id) DO UPDATE SET title = excluded.title, content = excluded.content",
+    )
+    .bind(note_id)
+    .bind(title)
+    .bind(content)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Whichever side moved away from the base; `None` if both did, differently
+fn merge_title<'a>(base: &str, local: &'a str, remote: &'a str) -> Option<&'a str> {
+    if local == remote || remote == base {
+        Some(local)
+    } else if local == base {
+        Some(remote)
+    } else {
+        None
+    }
+}
+
+/// Bring the cloud copy of an existing, unlocked note into it. Local edits
+/// made since the last sync are kept: merged line by line with the cloud's
+/// when they don't overlap, otherwise set aside as a conflict.
+pub(crate) async fn reconcile(
+    conn: &mut SqliteConnection,
+    id: i64,
+    remote_title: &str,
+    remote_content: &str,
+    remote: &Stamp,
+) -> Result<ImportOutcome, String> {
+    let (local_title, local_content): (String, Option<String>) =
+        sqlx::query_as("SELECT title, content FROM notes WHERE id = $1")
+            .bind(id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    let local_content = local_content.unwrap_or_default();
+    let base: Option<(String, String)> = sqlx::query_as("SELECT title, content FROM note_sync_base WHERE note_id = $1")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if (local_title.as_str(), local_content.as_str()) == (remote_title, remote_content) {
+        set_base(conn, id, remote_title, remote_content).await?;
+        clear(conn, id).await?;
+        return Ok(ImportOutcome::Unchanged);
+    }
+
+    // Notes synced before bases were tracked: the cloud wins, as it used to
+    let (base_title, base_content) = base.unwrap_or_else(|| (local_title.clone(), local_content.clone()));
+    let remote_changed = (base_title.as_str(), base_content.as_str()) != (remote_title, remote_content);
+    let local_changed = (base_title.as_str(), base_content.as_str()) != (local_title.as_str(), local_content.as_str());
+
+    if !remote_changed {
+        // Local edits not pushed yet
+        return Ok(ImportOutcome::Unchanged);
+    }
+
+    let merged = match local_changed {
+        false => Some((remote_title.to_string(), remote_content.to_string())),
+        true => merge_title(&base_title, &local_title, remote_title).and_then(|title| {
+            diffy::merge(&base_content, &local_content, remote_content)
+                .ok()
+                .map(|content| (title.to_string(), content))
+        }),
+    };
+    let Some((title, content)) = merged else {
+        sqlx::query(
+            "INSERT INTO note_conflicts (note_id, remote_title, remote_content, remote_updated_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT(note_id) DO UPDATE SET remote_title = excluded.remote_title,
+                 remote_content = excluded.remote_content, remote_updated_at = excluded.remote_updated_at,
+                 created_at = CURRENT_TIMESTAMP",
+        )
+        .bind(id)
+        .bind(remote_title)
+        .bind(remote_content)
+        .bind(&remote.updated_at)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+        println!("Backend: Note {} was edited here and in the cloud, keeping both", id);
+        return Ok(ImportOutcome::Conflicted);
+    };
+
+    // The local copy stays in the history either way
+    revisions::snapshot(conn, id, "sync", false)
+        .await
+        .map_err(|e| e.to_string())?;
+    // A merge is a local change the cloud hasn't seen, so it ticks the clock
+    // here; otherwise the note takes the cloud's reading
+    let remote = (!local_changed).then_some(remote);
+    sqlx::query(
+        "UPDATE notes SET title = $1, content = $2, updated_at = COALESCE($3, updated_at), hlc = COALESCE($4, hlc)
+         WHERE id = $5",
+    )
+    .bind(&title)
+    .bind(&content)
+    .bind(remote.map(|r| &r.updated_at))
+    .bind(remote.map(|r| &r.hlc))
+    .bind(id)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    commands::reindex(conn, id, &content).await?;
+    yjs::synced(conn, id, &content).await?;
+    set_base(conn, id, remote_title, remote_content).await?;
+    clear(conn, id).await?;
+
+    Ok(if local_changed { ImportOutcome::Merged } else { ImportOutcome::Updated })
+}
+
+async fn clear(conn: &mut SqliteConnection, note_id: i64) -> Result<(), String> {
+    sqlx::query("DELETE FROM note_conflicts WHERE note_id = $1")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// --- Commands ---
+
+/// Notes whose cloud copy couldn't be merged, with both versions.
+#[tauri::command]
+pub async fn list_note_conflicts(pool: State<'_, SqlitePool>) -> Result<Vec<NoteConflict>, String> {
+    sqlx::query_as::<_, NoteConflict>(
+        "SELECT c.note_id, n.title AS local_title, COALESCE(n.content, '') AS local_content,
+                c.remote_title, c.remote_content, c.remote_updated_at, c.created_at
+         FROM note_conflicts c JOIN notes n ON n.id = c.note_id
+         WHERE n.deleted_at IS NULL
+         ORDER BY c.created_at, c.note_id",
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Settle a conflict with the version to keep: the local one, the cloud one
+/// or a hand-merged one. Unless it's the cloud's, push the note afterwards.
+#[tauri::command]
+pub async fn resolve_note_conflict(
+    pool: State<'_, SqlitePool>,
+    note_id: i64,
+    title: String,
+    content: String,
+) -> Result<(), String> {
+    println!("Backend: resolve_note_conflict: {}", note_id);
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    if locked::is_locked(&mut tx, note_id).await.map_err(|e| e.to_string())? {
+        return Err("Note is locked".to_string());
+    }
+    let remote: Option<(String, String)> =
+        sqlx::query_as("SELECT remote_title, remote_content FROM note_conflicts WHERE note_id = $1")
+            .bind(note_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    let (remote_title, remote_content) = remote.ok_or("The note has no conflict")?;
+
+    revisions::snapshot(&mut tx, note_id, "conflict", false)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("UPDATE notes SET title = $1, content = $2 WHERE id = $3")
+        .bind(&title)
+        .bind(&content)
+        .bind(note_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    commands::reindex(&mut tx, note_id, &content).await?;
+    // The cloud copy has been seen, so it's the base the resolution is pushed over
+    set_base(&mut tx, note_id, &remote_title, &remote_content).await?;
+    clear(&mut tx, note_id).await?;
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+/// Record that `title`/`content` is what the cloud now holds for the note,
+/// after pushing it.
+#[tauri::command]
+pub async fn mark_note_synced(
+    pool: State<'_, SqlitePool>,
+    id: i64,
+    title: String,
+    content: String,
+) -> Result<(), String> {
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    // A locked note's plaintext isn't kept anywhere
+    if locked::is_locked(&mut conn, id).await.map_err(|e| e.to_string())? {
+        return Err("Note is locked".to_string());
+    }
+    set_base(&mut conn, id, &title, &content).await
+}
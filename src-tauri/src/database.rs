@@ -1,10 +1,17 @@
+use crate::{attachments, commands, migrations, trash_bin};
+use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
 use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
 use tauri::Manager;
 
 // CONSTANTS:
 // The name of our database file.
 const DB_NAME: &str = "onyx.db";
+// First 16 bytes of every plaintext SQLite file. SQLCipher puts a random salt there.
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
 
 // 1. THE BLUEPRINT
 pub struct Database;
@@ -25,82 +32,80 @@ impl Database {
         path.to_str().unwrap().to_string()
     }
 
-    pub async fn setup(app_handle: &tauri::AppHandle) -> SqlitePool {
+    /// Whether `onyx.db` is encrypted and has to be opened with a passphrase.
+    pub async fn is_encrypted(app_handle: &tauri::AppHandle) -> bool {
+        let path = Self::get_db_path(app_handle).await;
+        let mut header = [0u8; 16];
+        match fs::File::open(&path).and_then(|mut f| f.read_exact(&mut header)) {
+            Ok(()) => &header != SQLITE_HEADER,
+            // Missing or empty: it will be created in plaintext
+            Err(_) => false,
+        }
+    }
+
+    /// Quote a passphrase for `PRAGMA key` / `PRAGMA rekey`.
+    pub fn key_pragma(passphrase: &str) -> String {
+        format!("'{}'", passphrase.replace('\'', "''"))
+    }
+
+    /// Open the database, run migrations and housekeeping. `passphrase` is
+    /// required when the database is encrypted.
+    pub async fn setup(app_handle: &tauri::AppHandle, passphrase: Option<&str>) -> Result<SqlitePool, String> {
         let path = Self::get_db_path(app_handle).await;
         let db_url = format!("sqlite:{}", path);
 
         if !Sqlite::database_exists(&db_url).await.unwrap_or(false) {
-            Sqlite::create_database(&db_url).await.unwrap();
+            Sqlite::create_database(&db_url)
+                .await
+                .map_err(|e| format!("Failed to create database: {}", e))?;
         }
 
-        let pool = SqlitePool::connect(&db_url).await.unwrap();
+        // Zero deleted content on disk, e.g. the plaintext of a note that was just locked
+        let mut options = SqliteConnectOptions::from_str(&db_url)
+            .map_err(|e| e.to_string())?
+            .pragma("secure_delete", "on");
+        if let Some(passphrase) = passphrase {
+            // sqlx sends `key` before any other pragma, as SQLCipher requires.
+            // The options keep it for as long as the pool opens connections.
+            options = options.pragma("key", Self::key_pragma(passphrase));
+        }
+
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+
+        // First read of the file; with the wrong key SQLCipher reports SQLITE_NOTADB
+        sqlx::query("SELECT COUNT(*) FROM sqlite_master")
+            .execute(&pool)
+            .await
+            .map_err(|e| match e.as_database_error().and_then(|d| d.code()) {
+                Some(code) if code == "26" && passphrase.is_some() => "Wrong passphrase".to_string(),
+                _ => format!("Failed to open database: {}", e),
+            })?;
 
         // WAL Mode
         sqlx::query("PRAGMA journal_mode=WAL;")
             .execute(&pool)
             .await
-            .unwrap();
-
-        // Table Creation
-        println!("Checking 'notes' table...");
-        if let Err(e) = sqlx::query(
-            "CREATE TABLE IF NOT EXISTS notes (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                title TEXT NOT NULL,
-                content TEXT,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
-                pb_id TEXT,
-                local_uuid TEXT UNIQUE
-            )",
-        )
-        .execute(&pool)
-        .await
-        {
-            eprintln!("CRITICAL ERROR: Failed to create tables: {}", e);
-            panic!("Database setup failed: {}", e);
-        }
-
-        // Migration: Add pb_id if missing
-        let table_info: Vec<(i64, String, String, i64, Option<String>, i64)> =
-            sqlx::query_as("PRAGMA table_info('notes')")
-                .fetch_all(&pool)
-                .await
-                .unwrap_or_default();
+            .map_err(|e| e.to_string())?;
 
-        let has_pb_id = table_info.iter().any(|c| c.1 == "pb_id");
-        let has_uuid = table_info.iter().any(|c| c.1 == "local_uuid");
+        println!("Checking database schema...");
+        migrations::run(&pool, Path::new(&path)).await?;
+        commands::reindex_all_if_stale(&pool).await?;
 
-        if !has_pb_id {
-            println!("Applying migration: Adding pb_id column...");
-            let _ = sqlx::query("ALTER TABLE notes ADD COLUMN pb_id TEXT")
-                .execute(&pool)
-                .await;
+        // Housekeeping only, a failure here shouldn't keep the app from starting
+        if let Err(e) = trash_bin::auto_purge(&pool).await {
+            eprintln!("Trash auto-purge failed: {}", e);
         }
-
-        if !has_uuid {
-            println!("Applying migration: Adding local_uuid column...");
-            let _ = sqlx::query("ALTER TABLE notes ADD COLUMN local_uuid TEXT")
-                .execute(&pool)
-                .await;
-            // Generate UUIDs for existing notes
-            let _ = sqlx::query("UPDATE notes SET local_uuid = (lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))),2,3) || '-' || substr('89ab',abs(random()) % 4 + 1, 1) || substr(lower(hex(randomblob(2))),2,3) || '-' || lower(hex(randomblob(6)))) WHERE local_uuid IS NULL")
-                .execute(&pool)
-                .await;
+        match attachments::attachments_dir(app_handle) {
+            Ok(dir) => {
+                if let Err(e) = attachments::collect_garbage(&pool, &dir).await {
+                    eprintln!("Attachment GC failed: {}", e);
+                }
+            }
+            Err(e) => eprintln!("Attachment GC failed: {}", e),
         }
 
-        // Trigger Creation
-        sqlx::query(
-            "CREATE TRIGGER IF NOT EXISTS update_note_timestamp 
-             AFTER UPDATE ON notes
-             BEGIN
-                UPDATE notes SET updated_at = CURRENT_TIMESTAMP WHERE id = old.id;
-             END;",
-        )
-        .execute(&pool)
-        .await
-        .unwrap();
-
-        pool
+        Ok(pool)
     }
 }
@@ -3,6 +3,12 @@ use serde::Serialize;
 use std::env;
 use tauri::command;
 
+// Body of the relay's 422 when the address is on its suppression list (bounced
+// or complained). A malformed request gets a 422 too, with a different body.
+const SUPPRESSED_ERROR: &str = "recipient_suppressed";
+const SUPPRESSED_MESSAGE: &str =
+    "Emails to this address are blocked because earlier messages bounced. Check the address or contact support.";
+
 #[derive(Serialize)]
 struct LinkPayload {
     email: String,
@@ -65,7 +71,7 @@ pub async fn send_magic_link_email(email: String, link: String) -> Result<String
         // Fallback to SMTP or fail? Ideally fail if strict.
         // But for dev, we might fall through.
         // Let's return error to force fix.
-        return Err(format!("Relay failed: {}", res.status()));
+        return Err(relay_error(res).await);
     }
 
     /*
@@ -101,7 +107,17 @@ pub async fn send_otp_email(email: String, code: String) -> Result<String, Strin
     if res.status().is_success() {
         return Ok("Sent via Relay".to_string());
     } else {
-        return Err(format!("Relay failed: {}", res.status()));
+        return Err(relay_error(res).await);
+    }
+}
+
+async fn relay_error(res: reqwest::Response) -> String {
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default();
+    if status == reqwest::StatusCode::UNPROCESSABLE_ENTITY && body.trim() == SUPPRESSED_ERROR {
+        SUPPRESSED_MESSAGE.to_string()
+    } else {
+        format!("Relay failed: {} {}", status, body.trim())
     }
 }
 
@@ -0,0 +1,219 @@
+use crate::database::Database;
+use crate::{migrations, mirror};
+use serde::Serialize;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, Connection, SqlitePool};
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager, State};
+use zeroize::Zeroizing;
+
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+#[derive(Serialize)]
+pub struct DatabaseStatus {
+    pub encrypted: bool,
+    /// `false` until `unlock_database` succeeds; commands that need the
+    /// database fail before that
+    pub unlocked: bool,
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("Use at least {} characters", MIN_PASSPHRASE_LEN));
+    }
+    Ok(())
+}
+
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Copy the whole database at `path` (opened with `key`, if it's encrypted)
+/// into a new file encrypted with `passphrase`. The copy gets a fresh salt,
+/// so even the same passphrase yields a new key.
+async fn export_to(path: &Path, key: Option<&str>, target: &Path, passphrase: &str) -> Result<(), String> {
+    let mut options = SqliteConnectOptions::new().filename(path);
+    if let Some(key) = key {
+        options = options.pragma("key", Database::key_pragma(key));
+    }
+    let mut conn = options.connect().await.map_err(|e| e.to_string())?;
+
+    // The app's connections don't open with SQLITE_OPEN_CREATE, and ATTACH
+    // inherits that; an empty file is a valid new database
+    std::fs::File::create(target).map_err(|e| e.to_string())?;
+
+    // ATTACH is per connection, so all three have to run on this one
+    sqlx::query("ATTACH DATABASE $1 AS rekeyed KEY $2")
+        .bind(target.to_string_lossy().to_string())
+        .bind(passphrase)
+        .execute(&mut conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let exported = sqlx::query("SELECT sqlcipher_export('rekeyed')")
+        .execute(&mut conn)
+        .await
+        .map_err(|e| format!("Failed to encrypt database: {}", e));
+    sqlx::query("DETACH DATABASE rekeyed")
+        .execute(&mut conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    conn.close().await.map_err(|e| e.to_string())?;
+    exported.map(|_| ())
+}
+
+/// Replace the database with a copy encrypted with `passphrase` and restart,
+/// so it is opened (and unlocked) like on a normal start. `key` opens the
+/// current file. The pool is closed before the copy is made, so no write can
+/// land in the old file after it; from then on the app restarts either way,
+/// with the old database if the copy couldn't be put in place.
+async fn replace_and_restart(
+    app_handle: &AppHandle,
+    pool: &SqlitePool,
+    path: &Path,
+    key: Option<&str>,
+    passphrase: &str,
+) -> Result<(), String> {
+    // The mirror holds a connection, which would keep close() waiting
+    mirror::stop(app_handle);
+
+    // Move everything into the main file first: the old WAL belongs to the
+    // old file, and replayed onto the new one it would corrupt it
+    let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE)")
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    if busy != 0 {
+        tauri::async_runtime::spawn(mirror::start_if_enabled(app_handle.clone()));
+        return Err("The database is busy, try again".to_string());
+    }
+    pool.close().await;
+
+    let exported = sidecar(path, ".rekeyed");
+    let replaced = match export_to(path, key, &exported, passphrase).await {
+        Ok(()) => std::fs::rename(&exported, path).map_err(|e| format!("Failed to replace database: {}", e)),
+        Err(e) => Err(e),
+    };
+    match replaced {
+        Ok(()) => {
+            let cleaned = std::fs::remove_file(sidecar(path, "-wal"))
+                .or_else(ignore_missing)
+                .and_then(|_| std::fs::remove_file(sidecar(path, "-shm")).or_else(ignore_missing));
+            if let Err(e) = cleaned {
+                eprintln!("Failed to remove the old WAL: {}", e);
+            }
+            if let Err(e) = migrations::remove_backups(path) {
+                eprintln!("Failed to remove old backups: {}", e);
+            }
+            println!("Backend: Restarting to reopen the database");
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&exported);
+            eprintln!("{}; restarting with the old database", e);
+        }
+    }
+    app_handle.restart()
+}
+
+fn ignore_missing(e: std::io::Error) -> std::io::Result<()> {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        Ok(())
+    } else {
+        Err(e)
+    }
+}
+
+// --- Commands ---
+
+#[tauri::command]
+pub async fn get_database_status(app_handle: AppHandle) -> Result<DatabaseStatus, String> {
+    Ok(DatabaseStatus {
+        encrypted: Database::is_encrypted(&app_handle).await,
+        unlocked: app_handle.try_state::<SqlitePool>().is_some(),
+    })
+}
+
+/// Open the encrypted database with the user's passphrase.
+#[tauri::command]
+pub async fn unlock_database(app_handle: AppHandle, passphrase: String) -> Result<(), String> {
+    let passphrase = Zeroizing::new(passphrase);
+    if app_handle.try_state::<SqlitePool>().is_some() {
+        return Err("Database is already unlocked".to_string());
+    }
+
+    let pool = Database::setup(&app_handle, Some(&passphrase)).await?;
+    app_handle.manage(pool);
+    println!("Backend: Database unlocked");
+    tauri::async_runtime::spawn(mirror::start_if_enabled(app_handle));
+    Ok(())
+}
+
+/// Encrypt the plaintext database, including titles and sync metadata.
+/// Old plaintext backups are deleted and the app restarts to ask for the passphrase.
+#[tauri::command]
+pub async fn enable_database_encryption(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    passphrase: String,
+) -> Result<(), String> {
+    let passphrase = Zeroizing::new(passphrase);
+    check_passphrase(&passphrase)?;
+    if Database::is_encrypted(&app_handle).await {
+        return Err("Database is already encrypted".to_string());
+    }
+    println!("Backend: Enabling database encryption");
+
+    let path = PathBuf::from(Database::get_db_path(&app_handle).await);
+    replace_and_restart(&app_handle, &pool, &path, None, &passphrase).await
+}
+
+/// Re-encrypt the database under a new passphrase. The app restarts afterwards.
+#[tauri::command]
+pub async fn change_database_passphrase(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    current: String,
+    new: String,
+) -> Result<(), String> {
+    let current = Zeroizing::new(current);
+    let new = Zeroizing::new(new);
+    check_passphrase(&new)?;
+    rekey(&app_handle, &pool, &current, &new).await
+}
+
+/// Re-encrypt the database under a fresh key with the same passphrase, e.g.
+/// after a copy of the file may have leaked along with the old key.
+#[tauri::command]
+pub async fn rekey_database(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    passphrase: String,
+) -> Result<(), String> {
+    let passphrase = Zeroizing::new(passphrase);
+    rekey(&app_handle, &pool, &passphrase, &passphrase).await
+}
+
+async fn rekey(app_handle: &AppHandle, pool: &SqlitePool, current: &str, new: &str) -> Result<(), String> {
+    if !Database::is_encrypted(app_handle).await {
+        return Err("Database is not encrypted".to_string());
+    }
+    println!("Backend: Re-keying database");
+
+    // The pool was opened with the current key already; this checks the
+    // user still knows it before anything changes
+    let path = PathBuf::from(Database::get_db_path(app_handle).await);
+    let mut conn = SqliteConnectOptions::new()
+        .filename(&path)
+        .pragma("key", Database::key_pragma(current))
+        .connect()
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("SELECT COUNT(*) FROM sqlite_master")
+        .execute(&mut conn)
+        .await
+        .map_err(|_| "Wrong passphrase".to_string())?;
+    conn.close().await.map_err(|e| e.to_string())?;
+
+    replace_and_restart(app_handle, pool, &path, Some(current), new).await
+}
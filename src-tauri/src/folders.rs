@@ -0,0 +1,267 @@
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use tauri::State;
+
+#[derive(FromRow)]
+struct FolderRow {
+    id: i64,
+    name: String,
+    parent_id: Option<i64>,
+    note_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct FolderNode {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    /// Notes directly in this folder
+    pub note_count: i64,
+    /// Notes in this folder and everything below it
+    pub total_count: i64,
+    pub children: Vec<FolderNode>,
+}
+
+#[derive(Serialize)]
+pub struct FolderTree {
+    pub folders: Vec<FolderNode>,
+    /// Notes that aren't in any folder
+    pub unfiled_count: i64,
+}
+
+#[tauri::command]
+pub async fn create_folder(
+    pool: State<'_, SqlitePool>,
+    name: String,
+    parent_id: Option<i64>,
+) -> Result<i64, String> {
+    let name = clean_name(&name)?;
+    println!("Backend: create_folder: {}", name);
+
+    let result = sqlx::query("INSERT INTO folders (name, parent_id) VALUES ($1, $2)")
+        .bind(name)
+        .bind(parent_id)
+        .execute(&*pool)
+        .await
+        .map_err(folder_error)?;
+
+    Ok(result.last_insert_rowid())
+}
+
+#[tauri::command]
+pub async fn rename_folder(pool: State<'_, SqlitePool>, id: i64, name: String) -> Result<(), String> {
+    let name = clean_name(&name)?;
+
+    let result = sqlx::query("UPDATE folders SET name = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+        .bind(name)
+        .bind(id)
+        .execute(&*pool)
+        .await
+        .map_err(folder_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err("Folder not found".to_string());
+    }
+    Ok(())
+}
+
+/// Move a folder, with everything inside it, under `parent_id` (or to the top level).
+#[tauri::command]
+pub async fn move_folder(
+    pool: State<'_, SqlitePool>,
+    id: i64,
+    parent_id: Option<i64>,
+) -> Result<(), String> {
+    println!("Backend: move_folder: {} -> {:?}", id, parent_id);
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    if let Some(parent_id) = parent_id {
+        // The new parent must not be the folder itself or one of its descendants
+        if subtree(&mut tx, id).await?.contains(&parent_id) {
+            return Err("Can't move a folder into itself".to_string());
+        }
+    }
+
+    let result = sqlx::query("UPDATE folders SET parent_id = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+        .bind(parent_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(folder_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err("Folder not found".to_string());
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Delete a folder and its subfolders. Their notes go to the trash with
+/// `trash_notes`, otherwise they move up to the deleted folder's parent.
+#[tauri::command]
+pub async fn delete_folder(
+    pool: State<'_, SqlitePool>,
+    id: i64,
+    trash_notes: bool,
+) -> Result<(), String> {
+    println!("Backend: delete_folder: {} (trash_notes={})", id, trash_notes);
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    let parent: Option<(Option<i64>,)> = sqlx::query_as("SELECT parent_id FROM folders WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (parent_id,) = parent.ok_or("Folder not found")?;
+
+    for folder_id in subtree(&mut tx, id).await? {
+        let query = if trash_notes {
+            "UPDATE notes SET deleted_at = COALESCE(deleted_at, CURRENT_TIMESTAMP), folder_id = NULL WHERE folder_id = $1"
+        } else {
+            "UPDATE notes SET folder_id = $2 WHERE folder_id = $1"
+        };
+        sqlx::query(query)
+            .bind(folder_id)
+            .bind(parent_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    // Subfolders go with it through ON DELETE CASCADE
+    sqlx::query("DELETE FROM folders WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn move_note(
+    pool: State<'_, SqlitePool>,
+    id: i64,
+    folder_id: Option<i64>,
+) -> Result<(), String> {
+    sqlx::query("UPDATE notes SET folder_id = $1 WHERE id = $2")
+        .bind(folder_id)
+        .bind(id)
+        .execute(&*pool)
+        .await
+        .map_err(folder_error)?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_folder_tree(pool: State<'_, SqlitePool>) -> Result<FolderTree, String> {
+    let rows = sqlx::query_as::<_, FolderRow>(
+        "SELECT f.id, f.name, f.parent_id,
+                (SELECT COUNT(*) FROM notes n WHERE n.folder_id = f.id AND n.deleted_at IS NULL) AS note_count
+         FROM folders f
+         ORDER BY f.name COLLATE NOCASE",
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let unfiled: (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM notes WHERE folder_id IS NULL AND deleted_at IS NULL")
+            .fetch_one(&*pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    let mut by_parent: HashMap<Option<i64>, Vec<FolderRow>> = HashMap::new();
+    for row in rows {
+        by_parent.entry(row.parent_id).or_default().push(row);
+    }
+
+    Ok(FolderTree {
+        folders: build_nodes(&mut by_parent, None),
+        unfiled_count: unfiled.0,
+    })
+}
+
+fn build_nodes(by_parent: &mut HashMap<Option<i64>, Vec<FolderRow>>, parent: Option<i64>) -> Vec<FolderNode> {
+    let rows = by_parent.remove(&parent).unwrap_or_default();
+    rows.into_iter()
+        .map(|row| {
+            let children = build_nodes(by_parent, Some(row.id));
+            let total_count = row.note_count + children.iter().map(|c| c.total_count).sum::<i64>();
+            FolderNode {
+                id: row.id,
+                name: row.name,
+                parent_id: row.parent_id,
+                note_count: row.note_count,
+                total_count,
+                children,
+            }
+        })
+        .collect()
+}
+
+/// The folder and all of its descendants.
+async fn subtree(conn: &mut SqliteConnection, id: i64) -> Result<Vec<i64>, String> {
+    let rows: Vec<(i64,)> = sqlx::query_as(
+        "WITH RECURSIVE tree(id) AS (
+            SELECT $1
+            UNION
+            SELECT f.id FROM folders f JOIN tree ON f.parent_id = tree.id
+        )
+        SELECT id FROM tree",
+    )
+    .bind(id)
+    .fetch_all(conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+/// The folder called `name` under `parent_id`, created if there is none.
+pub async fn ensure_folder(
+    conn: &mut SqliteConnection,
+    name: &str,
+    parent_id: Option<i64>,
+) -> Result<i64, String> {
+    let name = clean_name(name)?;
+    let existing: Option<(i64,)> =
+        sqlx::query_as("SELECT id FROM folders WHERE parent_id IS $1 AND name = $2 COLLATE NOCASE")
+            .bind(parent_id)
+            .bind(name)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    if let Some((id,)) = existing {
+        return Ok(id);
+    }
+
+    let result = sqlx::query("INSERT INTO folders (name, parent_id) VALUES ($1, $2)")
+        .bind(name)
+        .bind(parent_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(folder_error)?;
+    Ok(result.last_insert_rowid())
+}
+
+fn clean_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Folder name can't be empty".to_string());
+    }
+    if name.contains(['/', '\\']) {
+        return Err("Folder names can't contain slashes".to_string());
+    }
+    Ok(name)
+}
+
+fn folder_error(e: sqlx::Error) -> String {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => "A folder with that name already exists here".to_string(),
+        Some(db) if db.is_foreign_key_violation() => "Folder not found".to_string(),
+        _ => e.to_string(),
+    }
+}
@@ -0,0 +1,960 @@
+use crate::{commands, mirror, revisions, settings, tags, vault, yjs};
+use git2::{
+    Branch, Cred, CredentialType, FetchOptions, IndexAddOption, ObjectType, PushOptions, RemoteCallbacks,
+    Repository, RepositoryInitOptions, Signature, Tree, TreeWalkMode, TreeWalkResult,
+};
+use serde::Serialize;
+use sqlx::{Connection, SqliteConnection, SqlitePool};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use tauri::{AppHandle, Manager, State};
+use zeroize::Zeroizing;
+
+// Under the app data dir; a plain git work tree, so it can be inspected by hand
+const WORK_DIR: &str = "git-sync";
+const REMOTE_NAME: &str = "origin";
+const DEFAULT_BRANCH: &str = "main";
+
+const REMOTE_URL_KEY: &str = "git_remote_url";
+const BRANCH_KEY: &str = "git_branch";
+const AUTHOR_NAME_KEY: &str = "git_author_name";
+const AUTHOR_EMAIL_KEY: &str = "git_author_email";
+const USERNAME_KEY: &str = "git_username";
+const TOKEN_KEY: &str = "git_token";
+const SSH_KEY_KEY: &str = "git_ssh_key";
+const SSH_PASSPHRASE_KEY: &str = "git_ssh_passphrase";
+
+/// Held while a sync runs, so two can't work on the tree at once.
+#[derive(Default)]
+pub struct GitSyncState(tokio::sync::Mutex<()>);
+
+/// Git sync settings as shown in the app. Secrets are write-only.
+#[derive(Serialize)]
+pub struct GitSyncConfig {
+    pub remote_url: Option<String>,
+    pub branch: String,
+    pub author_name: Option<String>,
+    pub author_email: Option<String>,
+    pub username: Option<String>,
+    pub has_token: bool,
+    pub has_ssh_key: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct GitSyncReport {
+    /// Changes made in the app were committed
+    pub committed: bool,
+    /// Commits from the remote were merged in
+    pub pulled: bool,
+    pub pushed: bool,
+    /// Notes created or updated from the remote
+    pub imported: usize,
+    /// Notes moved to the trash because they were deleted on the remote
+    pub deleted: usize,
+    /// Titles of notes created from edits that couldn't be merged
+    pub conflicts: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+#[derive(Clone)]
+struct Remote {
+    url: String,
+    branch: String,
+    author_name: Option<String>,
+    author_email: Option<String>,
+    username: Option<String>,
+    token: Option<Zeroizing<String>>,
+    ssh_key: Option<Zeroizing<String>>,
+    ssh_passphrase: Option<Zeroizing<String>>,
+}
+
+/// A file in a commit.
+#[derive(Clone, PartialEq)]
+struct Entry {
+    path: String,
+    data: Vec<u8>,
+}
+
+/// What merging the remote did to the work tree.
+#[derive(Default)]
+struct Pulled {
+    merged: bool,
+    /// Markdown files the merge added or changed
+    changed: Vec<String>,
+    /// Uuids of notes deleted on the remote
+    removed: Vec<String>,
+    /// Files the merge deleted or moved away from
+    removed_files: Vec<String>,
+    conflicts: Vec<String>,
+}
+
+async fn load_remote(pool: &SqlitePool) -> Result<Remote, String> {
+    let get = |key: &'static str| settings::get(pool, key);
+    let secret = |value: Option<String>| value.map(Zeroizing::new);
+    Ok(Remote {
+        url: get(REMOTE_URL_KEY).await?.ok_or("Set a remote for git sync first")?,
+        branch: get(BRANCH_KEY).await?.unwrap_or_else(|| DEFAULT_BRANCH.to_string()),
+        author_name: get(AUTHOR_NAME_KEY).await?,
+        author_email: get(AUTHOR_EMAIL_KEY).await?,
+        username: get(USERNAME_KEY).await?,
+        token: secret(get(TOKEN_KEY).await?),
+        ssh_key: secret(get(SSH_KEY_KEY).await?),
+        ssh_passphrase: secret(get(SSH_PASSPHRASE_KEY).await?),
+    })
+}
+
+// --- Work tree <-> database ---
+
+// Edit times differ per device, and an imported edit gets the local time;
+// left in the files they would bounce between devices. Git has the history.
+async fn load_notes(conn: &mut SqliteConnection) -> Result<Vec<vault::ExportRow>, String> {
+    let mut notes = vault::load_notes(conn).await?;
+    // Locked notes aren't written; see `export`
+    notes.retain(|n| !n.locked);
+    for note in &mut notes {
+        note.updated_at = None;
+    }
+    Ok(notes)
+}
+
+fn render(
+    note: &vault::ExportRow,
+    folders: &HashMap<i64, (String, String)>,
+    tags: &HashMap<i64, Vec<String>>,
+) -> Result<String, String> {
+    let folder = note.folder_id.and_then(|id| folders.get(&id)).map(|(name, _)| name.as_str());
+    vault::render(note, folder, tags.get(&note.id).map(Vec::as_slice).unwrap_or_default())
+}
+
+/// Write every note into the work tree and remove the files of notes that
+/// were deleted or purged here. Files without a uuid are left for `import` to
+/// pick up; files of notes this device doesn't know are left alone and
+/// reported. A locked note keeps the file it was last synced as: locking
+/// isn't deleting, and a removed file would trash the note everywhere else.
+async fn export(conn: &mut SqliteConnection, root: &Path, report: &mut GitSyncReport) -> Result<(), String> {
+    vault::assign_uuids(conn).await?;
+    let notes = load_notes(conn).await?;
+    let tags = vault::load_tags(conn).await?;
+    let folders = vault::folder_paths(conn).await?;
+
+    let mirror::Scan { by_uuid: files, unknown, unreadable } = mirror::scan(root)?;
+    let mut taken: HashSet<String> = unknown
+        .iter()
+        .map(|f| f.path.to_lowercase())
+        .chain(unreadable.iter().map(|p| p.to_lowercase()))
+        .collect();
+    let gone: HashSet<String> = sqlx::query_scalar(
+        "SELECT local_uuid FROM notes WHERE local_uuid IS NOT NULL AND deleted_at IS NOT NULL
+         UNION SELECT local_uuid FROM note_tombstones WHERE local_uuid IS NOT NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?
+    .into_iter()
+    .collect();
+    let locked: HashSet<String> = sqlx::query_scalar(
+        "SELECT local_uuid FROM notes
+         WHERE local_uuid IS NOT NULL AND deleted_at IS NULL AND locked_ciphertext IS NOT NULL",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?
+    .into_iter()
+    .collect();
+    let live: HashSet<&str> = notes.iter().filter_map(|n| n.local_uuid.as_deref()).collect();
+    for (uuid, file) in &files {
+        if live.contains(uuid.as_str()) || locked.contains(uuid) {
+            taken.insert(file.path.to_lowercase());
+        } else if gone.contains(uuid) {
+            let path = vault::to_disk(root, &file.path);
+            tokio::fs::remove_file(&path).await.map_err(|e| e.to_string())?;
+            vault::remove_empty_dirs(root, &path).await;
+        } else {
+            // Most likely a note from the remote that couldn't be imported
+            taken.insert(file.path.to_lowercase());
+            let skipped = format!("{}: not imported, left on the remote", file.path);
+            if !report.skipped.contains(&skipped) {
+                report.skipped.push(skipped);
+            }
+        }
+    }
+
+    for note in &notes {
+        let dir = note
+            .folder_id
+            .and_then(|id| folders.get(&id))
+            .map(|(_, dir)| dir.clone())
+            .unwrap_or_default();
+        let stem = vault::safe_file_name(&note.title);
+        let existing = note.local_uuid.as_ref().and_then(|uuid| files.get(uuid));
+
+        let path = match existing {
+            Some(file) if vault::fits(&file.path, &dir, &stem) => file.path.clone(),
+            _ => vault::unique_path(&dir, &stem, ".md", &mut taken),
+        };
+        let rendered = render(note, &folders, &tags)?;
+        if existing.is_some_and(|f| f.path == path && f.hash == vault::sha256_hex(rendered.as_bytes())) {
+            continue;
+        }
+        vault::write_file(&vault::to_disk(root, &path), rendered.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+        // Renamed or moved in the app
+        if let Some(old) = existing.filter(|f| !f.path.eq_ignore_ascii_case(&path)) {
+            let old = vault::to_disk(root, &old.path);
+            let _ = tokio::fs::remove_file(&old).await;
+            vault::remove_empty_dirs(root, &old).await;
+        }
+    }
+    Ok(())
+}
+
+/// Bring what the merge changed into the database.
+async fn import(conn: &mut SqliteConnection, root: &Path, pulled: &Pulled, report: &mut GitSyncReport) -> Result<(), String> {
+    for uuid in &pulled.removed {
+        let result = sqlx::query(
+            "UPDATE notes SET deleted_at = CURRENT_TIMESTAMP
+             WHERE local_uuid = $1 AND deleted_at IS NULL AND locked_ciphertext IS NULL",
+        )
+        .bind(uuid)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+        report.deleted += result.rows_affected() as usize;
+    }
+
+    let mirror::Scan { by_uuid, unknown, .. } = mirror::scan(root)?;
+    let files: HashMap<&str, (Option<&str>, &mirror::DiskFile)> = by_uuid
+        .iter()
+        .map(|(uuid, file)| (file.path.as_str(), (Some(uuid.as_str()), file)))
+        .chain(unknown.iter().map(|file| (file.path.as_str(), (None, file))))
+        .collect();
+
+    let mut folders = HashMap::from([(String::new(), None)]);
+    let mut rewrite = Vec::new();
+    for path in &pulled.changed {
+        let Some((uuid, file)) = files.get(path.as_str()) else {
+            continue;
+        };
+        match import_file(conn, *uuid, file, &mut folders).await {
+            Ok(id) => {
+                report.imported += 1;
+                if uuid.is_none() {
+                    rewrite.push((id, path));
+                }
+            }
+            Err(e) => report.skipped.push(format!("{}: {}", file.title, e)),
+        }
+    }
+
+    // Files added on the remote without front-matter get their uuid written
+    // back in place, so the next export doesn't see a second copy
+    if rewrite.is_empty() {
+        return Ok(());
+    }
+    let notes = load_notes(conn).await?;
+    let tags = vault::load_tags(conn).await?;
+    let folders = vault::folder_paths(conn).await?;
+    for (id, path) in rewrite {
+        if let Some(note) = notes.iter().find(|n| n.id == id) {
+            vault::write_file(&vault::to_disk(root, path), render(note, &folders, &tags)?.as_bytes())
+                .await
+                .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+        }
+    }
+    Ok(())
+}
+
+async fn import_file(
+    conn: &mut SqliteConnection,
+    uuid: Option<&str>,
+    file: &mirror::DiskFile,
+    folders: &mut HashMap<String, Option<i64>>,
+) -> Result<i64, String> {
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    let existing: Option<(i64, bool)> = match uuid {
+        Some(uuid) => sqlx::query_as("SELECT id, locked_ciphertext IS NOT NULL FROM notes WHERE local_uuid = $1")
+            .bind(uuid)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?,
+        None => None,
+    };
+    let folder_id = vault::ensure_folders(&mut tx, mirror::parent_dir(&file.path), folders).await?;
+
+    let id = match existing {
+        Some((_, true)) => return Err("Note is locked".to_string()),
+        Some((id, false)) => {
+            revisions::snapshot(&mut tx, id, "sync", false)
+                .await
+                .map_err(|e| e.to_string())?;
+            // An edit on the remote brings a note back out of the trash
+            sqlx::query("UPDATE notes SET title = $1, content = $2, folder_id = $3, deleted_at = NULL WHERE id = $4")
+                .bind(&file.title)
+                .bind(&file.body)
+                .bind(folder_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            id
+        }
+        None => {
+            let uuid = uuid.map(str::to_string).unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            sqlx::query(
+                "INSERT INTO notes (title, content, folder_id, local_uuid, created_at)
+                 VALUES ($1, $2, $3, $4, COALESCE($5, CURRENT_TIMESTAMP))",
+            )
+            .bind(&file.title)
+            .bind(&file.body)
+            .bind(folder_id)
+            .bind(uuid)
+            .bind(&file.created)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?
+            .last_insert_rowid()
+        }
+    };
+
+    commands::reindex(&mut tx, id, &file.body).await?;
+    yjs::synced(&mut tx, id, &file.body).await?;
+    tags::set_manual(&mut tx, id, &file.tags).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+// --- Git ---
+
+fn open_repo(root: &Path, branch: &str) -> Result<Repository, git2::Error> {
+    let repo = match Repository::open(root) {
+        Ok(repo) => repo,
+        Err(_) => Repository::init_opts(root, RepositoryInitOptions::new().initial_head(branch))?,
+    };
+    // The branch setting may have changed since the last sync
+    let head = format!("refs/heads/{}", branch);
+    if repo.find_reference("HEAD")?.symbolic_target() != Some(head.as_str()) {
+        repo.set_head(&head)?;
+    }
+    Ok(repo)
+}
+
+fn signature(repo: &Repository, remote: &Remote) -> Result<Signature<'static>, git2::Error> {
+    match (&remote.author_name, &remote.author_email) {
+        (Some(name), Some(email)) => Signature::now(name, email),
+        _ => repo.signature().or_else(|_| Signature::now("Onyx", "onyx@localhost")),
+    }
+}
+
+fn stage_all(repo: &Repository) -> Result<Tree<'_>, git2::Error> {
+    let mut index = repo.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.update_all(["*"], None)?;
+    index.write()?;
+    repo.find_tree(index.write_tree()?)
+}
+
+fn file_name(path: &str) -> &str {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    name.strip_suffix(".md").unwrap_or(name)
+}
+
+// "Edit Groceries", or "Update 3 notes" with the names in the body
+fn describe(diff: &git2::Diff) -> String {
+    let mut changes: [(&str, &str, Vec<String>); 4] = [
+        ("Add", "Added", Vec::new()),
+        ("Edit", "Edited", Vec::new()),
+        ("Rename", "Renamed", Vec::new()),
+        ("Delete", "Deleted", Vec::new()),
+    ];
+    for delta in diff.deltas() {
+        let name = |file: git2::DiffFile| file.path().map(|p| file_name(&p.to_string_lossy()).to_string());
+        let (slot, name) = match delta.status() {
+            git2::Delta::Added => (0, name(delta.new_file())),
+            git2::Delta::Deleted => (3, name(delta.old_file())),
+            git2::Delta::Renamed => (
+                2,
+                name(delta.old_file()).zip(name(delta.new_file())).map(|(old, new)| format!("{} to {}", old, new)),
+            ),
+            _ => (1, name(delta.new_file())),
+        };
+        changes[slot].2.extend(name);
+    }
+
+    let total: usize = changes.iter().map(|(_, _, names)| names.len()).sum();
+    if total == 1 {
+        if let Some((verb, _, names)) = changes.iter().find(|(_, _, names)| !names.is_empty()) {
+            return format!("{} {}", verb, names[0]);
+        }
+    }
+    let mut message = format!("Update {} notes\n", total);
+    for (_, label, names) in changes.iter().filter(|(_, _, names)| !names.is_empty()) {
+        message.push_str(&format!("\n{}: {}", label, names.join(", ")));
+    }
+    message
+}
+
+/// Commit whatever the work tree has that HEAD doesn't. Returns whether there was anything.
+fn commit_local(root: &Path, remote: &Remote) -> Result<bool, git2::Error> {
+    let repo = open_repo(root, &remote.branch)?;
+    let tree = stage_all(&repo)?;
+    let head = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+    let head_tree = head.as_ref().map(|c| c.tree()).transpose()?;
+    if head_tree.as_ref().map(|t| t.id()) == Some(tree.id()) || (head.is_none() && tree.is_empty()) {
+        return Ok(false);
+    }
+
+    let mut diff = repo.diff_tree_to_tree(head_tree.as_ref(), Some(&tree), None)?;
+    diff.find_similar(None)?;
+    let signature = signature(&repo, remote)?;
+    let parents: Vec<&git2::Commit> = head.iter().collect();
+    repo.commit(Some("HEAD"), &signature, &signature, &describe(&diff), &tree, &parents)?;
+    Ok(true)
+}
+
+fn callbacks(remote: &Remote) -> RemoteCallbacks<'_> {
+    let mut callbacks = RemoteCallbacks::new();
+    let mut tried = HashSet::new();
+    callbacks.credentials(move |_url, username_from_url, allowed| {
+        let username = remote.username.as_deref().or(username_from_url).unwrap_or("git");
+        let kind = if allowed.contains(CredentialType::SSH_KEY) {
+            CredentialType::SSH_KEY
+        } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            CredentialType::USER_PASS_PLAINTEXT
+        } else {
+            // SSH asks for the user name on its own first
+            return Cred::username(username);
+        };
+        // libgit2 asks again after a rejected credential; don't loop on it
+        if !tried.insert(kind.bits()) {
+            return Err(git2::Error::from_str("Authentication failed, check the git sync credentials"));
+        }
+
+        match (kind, &remote.ssh_key, &remote.token) {
+            (CredentialType::SSH_KEY, Some(key), _) => {
+                Cred::ssh_key_from_memory(username, None, key, remote.ssh_passphrase.as_ref().map(|p| p.as_str()))
+            }
+            (CredentialType::SSH_KEY, None, _) => Cred::ssh_key_from_agent(username),
+            (_, _, Some(token)) => Cred::userpass_plaintext(username, token),
+            _ => Err(git2::Error::from_str("The remote needs credentials, add a token or SSH key")),
+        }
+    });
+    callbacks.push_update_reference(|refname, status| match status {
+        Some(reason) => Err(git2::Error::from_str(&format!("Push of {} rejected: {}", refname, reason))),
+        None => Ok(()),
+    });
+    callbacks
+}
+
+fn find_remote<'r>(repo: &'r Repository, remote: &Remote) -> Result<git2::Remote<'r>, git2::Error> {
+    match repo.find_remote(REMOTE_NAME) {
+        Ok(found) if found.url() == Some(remote.url.as_str()) => Ok(found),
+        Ok(_) => {
+            repo.remote_set_url(REMOTE_NAME, &remote.url)?;
+            repo.find_remote(REMOTE_NAME)
+        }
+        Err(_) => repo.remote(REMOTE_NAME, &remote.url),
+    }
+}
+
+fn tracking_ref(remote: &Remote) -> String {
+    format!("refs/remotes/{}/{}", REMOTE_NAME, remote.branch)
+}
+
+fn fetch(root: &Path, remote: &Remote) -> Result<(), git2::Error> {
+    let repo = open_repo(root, &remote.branch)?;
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(remote));
+    let refspec = format!("+refs/heads/{}:{}", remote.branch, tracking_ref(remote));
+    let mut origin = find_remote(&repo, remote)?;
+    origin.fetch(&[refspec], Some(&mut options), None)
+}
+
+/// Push the branch if it has commits the remote doesn't. Returns whether it did.
+fn push(root: &Path, remote: &Remote) -> Result<bool, git2::Error> {
+    let repo = open_repo(root, &remote.branch)?;
+    let Some(local) = repo.head().ok().and_then(|h| h.target()) else {
+        return Ok(false);
+    };
+    if repo.refname_to_id(&tracking_ref(remote)).ok() == Some(local) {
+        return Ok(false);
+    }
+
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks(remote));
+    let refspec = format!("refs/heads/{0}:refs/heads/{0}", remote.branch);
+    find_remote(&repo, remote)?.push(&[refspec], Some(&mut options))?;
+    repo.reference(&tracking_ref(remote), local, true, "push")?;
+    Ok(true)
+}
+
+fn note_uuid(path: &str, data: &[u8]) -> Option<String> {
+    if !vault::is_markdown(Path::new(path)) {
+        return None;
+    }
+    let (front_matter, _) = vault::split_front_matter(std::str::from_utf8(data).ok()?);
+    front_matter?.get("local_uuid")?.as_str().map(str::to_string)
+}
+
+/// Files in a commit, keyed by note uuid, or by path for anything else,
+/// so a note renamed on one side still lines up with its edits on the other.
+fn read_tree(repo: &Repository, tree: &Tree) -> Result<HashMap<String, Entry>, git2::Error> {
+    let mut blobs = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            blobs.push((format!("{}{}", dir, entry.name().unwrap_or_default()), entry.id()));
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    let mut files = HashMap::new();
+    for (path, id) in blobs {
+        let data = repo.find_blob(id)?.content().to_vec();
+        let key = match note_uuid(&path, &data) {
+            // A copied file carries the original's uuid
+            Some(uuid) if !files.contains_key(&format!("uuid:{}", uuid)) => format!("uuid:{}", uuid),
+            _ => format!("path:{}", path),
+        };
+        files.insert(key, Entry { path, data });
+    }
+    Ok(files)
+}
+
+// The side that changed wins; if both did, ours
+fn pick<T: PartialEq + Clone>(base: Option<&T>, ours: Option<&T>, theirs: Option<&T>) -> Option<T> {
+    if ours == theirs || ours != base {
+        ours.cloned()
+    } else {
+        theirs.cloned()
+    }
+}
+
+/// Three-way merge of a file both sides changed. Front-matter is merged
+/// field by field, the body line by line; `None` if the body conflicts.
+fn merge_file(base: Option<&Entry>, ours: &Entry, theirs: &Entry) -> Option<Entry> {
+    let path = pick(base.map(|b| &b.path), Some(&ours.path), Some(&theirs.path))?;
+    if !vault::is_markdown(Path::new(&path)) {
+        return None;
+    }
+
+    let text = |entry: &Entry| String::from_utf8(entry.data.clone()).ok();
+    let (base_text, ours_text, theirs_text) = (base.and_then(text).unwrap_or_default(), text(ours)?, text(theirs)?);
+    let split = |text: &str| {
+        let (front_matter, body) = vault::split_front_matter(text);
+        let mapping = front_matter.and_then(|f| f.as_mapping().cloned()).unwrap_or_default();
+        (mapping, body.to_string())
+    };
+    let (base_fm, base_body) = split(&base_text);
+    let (ours_fm, ours_body) = split(&ours_text);
+    let (theirs_fm, theirs_body) = split(&theirs_text);
+
+    let body = diffy::merge(&base_body, &ours_body, &theirs_body).ok()?;
+    let mut front_matter = serde_yaml::Mapping::new();
+    for key in ours_fm.keys().chain(theirs_fm.keys()) {
+        if front_matter.contains_key(key) {
+            continue;
+        }
+        if let Some(value) = pick(base_fm.get(key), ours_fm.get(key), theirs_fm.get(key)) {
+            front_matter.insert(key.clone(), value);
+        }
+    }
+
+    let data = if front_matter.is_empty() {
+        body
+    } else {
+        format!("---\n{}---\n\n{}", serde_yaml::to_string(&front_matter).ok()?, body)
+    };
+    Some(Entry { path, data: data.into_bytes() })
+}
+
+/// Their side of a file that couldn't be merged, as a new note next to ours.
+fn conflict_copy(theirs: &Entry, stamp: &str) -> (Entry, String) {
+    let dir = mirror::parent_dir(&theirs.path);
+    let text = String::from_utf8_lossy(&theirs.data);
+    if !vault::is_markdown(Path::new(&theirs.path)) {
+        let (stem, ext) = file_name(&theirs.path).rsplit_once('.').unwrap_or((file_name(&theirs.path), ""));
+        let name = format!("{} (conflict {}).{}", stem, stamp, ext);
+        return (Entry { path: vault::join(dir, &name), data: theirs.data.clone() }, name);
+    }
+
+    let (front_matter, body) = vault::split_front_matter(&text);
+    let mut front_matter = front_matter.and_then(|f| f.as_mapping().cloned()).unwrap_or_default();
+    let title = front_matter.get("title").and_then(|t| t.as_str()).unwrap_or(file_name(&theirs.path));
+    let title = format!("{} (conflict {})", title, stamp);
+    front_matter.insert("title".into(), title.clone().into());
+    front_matter.insert("local_uuid".into(), uuid::Uuid::new_v4().to_string().into());
+
+    let data = format!("---\n{}---\n\n{}", serde_yaml::to_string(&front_matter).unwrap_or_default(), body);
+    let path = vault::join(dir, &format!("{}.md", vault::safe_file_name(&title)));
+    (Entry { path, data: data.into_bytes() }, title)
+}
+
+/// Merge the fetched branch into HEAD, writing the result to the work tree.
+fn merge(root: &Path, remote: &Remote, stamp: &str) -> Result<Pulled, git2::Error> {
+    let repo = open_repo(root, &remote.branch)?;
+    let Ok(theirs) = repo.find_reference(&tracking_ref(remote)).and_then(|r| r.peel_to_commit()) else {
+        // Nothing on the remote yet
+        return Ok(Pulled::default());
+    };
+    let ours = repo.head().ok().and_then(|h| h.peel_to_commit().ok());
+    let base = match &ours {
+        Some(ours) => repo.merge_base(ours.id(), theirs.id()).ok(),
+        None => None,
+    };
+    if base == Some(theirs.id()) {
+        return Ok(Pulled::default());
+    }
+
+    let read = |commit: Option<&git2::Commit>| match commit {
+        Some(commit) => read_tree(&repo, &commit.tree()?),
+        None => Ok(HashMap::new()),
+    };
+    let base_files = read(base.map(|id| repo.find_commit(id)).transpose()?.as_ref())?;
+    let ours_files = read(ours.as_ref())?;
+    let theirs_files = read(Some(&theirs))?;
+
+    let mut pulled = Pulled {
+        merged: true,
+        ..Default::default()
+    };
+    let mut merged: HashMap<String, Entry> = HashMap::new();
+    let keys: BTreeSet<&String> = ours_files.keys().chain(theirs_files.keys()).collect();
+    for key in keys {
+        let (b, o, t) = (base_files.get(key), ours_files.get(key), theirs_files.get(key));
+        let resolved = match (o, t) {
+            _ if o == t || t == b => o.cloned(),
+            _ if o == b => t.cloned(),
+            // Edited on one side, deleted on the other: the edit wins
+            (None, t) | (t, None) => t.cloned(),
+            (Some(o), Some(t)) => match merge_file(b, o, t) {
+                Some(entry) => Some(entry),
+                None => {
+                    let (copy, title) = conflict_copy(t, stamp);
+                    merged.insert(format!("path:{}", copy.path), copy);
+                    pulled.conflicts.push(title);
+                    Some(o.clone())
+                }
+            },
+        };
+        if let Some(entry) = resolved {
+            merged.insert(key.clone(), entry);
+        }
+    }
+
+    // Two sides may have picked the same name for different notes; what
+    // was already here keeps it
+    let mut taken = HashSet::new();
+    let mut keys: Vec<String> = merged.keys().cloned().collect();
+    keys.sort_by_key(|k| (ours_files.get(k) != merged.get(k), k.clone()));
+    for key in keys {
+        let entry = merged.get_mut(&key).expect("key from the map");
+        if !taken.insert(entry.path.to_lowercase()) {
+            let name = file_name(&entry.path).to_string();
+            let ext = entry.path.rsplit_once('.').map(|(_, ext)| format!(".{}", ext)).unwrap_or_default();
+            entry.path = vault::unique_path(mirror::parent_dir(&entry.path), &name, &ext, &mut taken);
+        }
+    }
+
+    for (key, entry) in &ours_files {
+        match merged.get(key) {
+            Some(m) if m.path == entry.path => {}
+            other => {
+                std::fs::remove_file(vault::to_disk(root, &entry.path)).ok();
+                pulled.removed_files.push(entry.path.clone());
+                if let (None, Some(uuid)) = (other, key.strip_prefix("uuid:")) {
+                    pulled.removed.push(uuid.to_string());
+                }
+            }
+        }
+    }
+    for (key, entry) in &merged {
+        if ours_files.get(key) == Some(entry) {
+            continue;
+        }
+        let file = vault::to_disk(root, &entry.path);
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+        }
+        std::fs::write(&file, &entry.data).map_err(|e| git2::Error::from_str(&e.to_string()))?;
+        if vault::is_markdown(Path::new(&entry.path)) {
+            pulled.changed.push(entry.path.clone());
+        }
+    }
+
+    let tree = stage_all(&repo)?;
+    let branch = format!("refs/heads/{}", remote.branch);
+    if base == ours.as_ref().map(|c| c.id()) && tree.id() == theirs.tree_id() {
+        repo.reference(&branch, theirs.id(), true, "sync: fast-forward")?;
+    } else {
+        let mut message = format!("Merge {}/{}", REMOTE_NAME, remote.branch);
+        if !pulled.conflicts.is_empty() {
+            message.push_str(&format!("\n\nKept both versions of: {}", pulled.conflicts.join(", ")));
+        }
+        let signature = signature(&repo, remote)?;
+        let parents: Vec<&git2::Commit> = ours.iter().chain([&theirs]).collect();
+        repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &parents)?;
+    }
+    Ok(pulled)
+}
+
+async fn run_blocking<T: Send + 'static>(
+    root: &Path,
+    remote: &Remote,
+    task: fn(&Path, &Remote) -> Result<T, git2::Error>,
+) -> Result<T, String> {
+    let (root, remote) = (root.to_path_buf(), remote.clone());
+    tauri::async_runtime::spawn_blocking(move || task(&root, &remote))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.message().to_string())
+}
+
+/// Export, commit, fetch, merge, import, then export and push what changed.
+async fn run(conn: &mut SqliteConnection, root: &Path, remote: &Remote) -> Result<GitSyncReport, String> {
+    let mut report = GitSyncReport::default();
+    export(conn, root, &mut report).await?;
+    report.committed = commit_local(root, remote).map_err(|e| e.message().to_string())?;
+
+    run_blocking(root, remote, fetch).await?;
+    let stamp: (String,) = sqlx::query_as("SELECT strftime('%Y-%m-%d %H%M%S', 'now')")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let pulled = merge(root, remote, &stamp.0).map_err(|e| e.message().to_string())?;
+    for path in &pulled.removed_files {
+        vault::remove_empty_dirs(root, &vault::to_disk(root, path)).await;
+    }
+    import(conn, root, &pulled, &mut report).await?;
+    report.pulled = pulled.merged;
+    report.conflicts = pulled.conflicts;
+
+    // Whatever the import normalized goes out with this sync, not the next
+    export(conn, root, &mut report).await?;
+    report.committed |= commit_local(root, remote).map_err(|e| e.message().to_string())?;
+    report.pushed = run_blocking(root, remote, push).await?;
+    Ok(report)
+}
+
+// --- Commands ---
+
+#[tauri::command]
+pub async fn get_git_sync_config(pool: State<'_, SqlitePool>) -> Result<GitSyncConfig, String> {
+    Ok(GitSyncConfig {
+        remote_url: settings::get(&pool, REMOTE_URL_KEY).await?,
+        branch: settings::get(&pool, BRANCH_KEY)
+            .await?
+            .unwrap_or_else(|| DEFAULT_BRANCH.to_string()),
+        author_name: settings::get(&pool, AUTHOR_NAME_KEY).await?,
+        author_email: settings::get(&pool, AUTHOR_EMAIL_KEY).await?,
+        username: settings::get(&pool, USERNAME_KEY).await?,
+        has_token: settings::get(&pool, TOKEN_KEY).await?.is_some(),
+        has_ssh_key: settings::get(&pool, SSH_KEY_KEY).await?.is_some(),
+    })
+}
+
+// Empty clears the setting
+async fn store(pool: &SqlitePool, key: &str, value: Option<String>) -> Result<(), String> {
+    match value.filter(|v| !v.trim().is_empty()) {
+        Some(value) => settings::set(pool, key, value.trim()).await,
+        None => settings::remove(pool, key).await,
+    }
+}
+
+/// Remote to sync with: any URL git understands, or a path to a bare repository.
+#[tauri::command]
+pub async fn set_git_sync_config(
+    pool: State<'_, SqlitePool>,
+    remote_url: String,
+    branch: Option<String>,
+    author_name: Option<String>,
+    author_email: Option<String>,
+) -> Result<(), String> {
+    println!("Backend: set_git_sync_config: {}", remote_url);
+    if let Some(branch) = branch.as_deref().filter(|b| !b.trim().is_empty()) {
+        if !Branch::name_is_valid(branch.trim()).map_err(|e| e.to_string())? {
+            return Err(format!("Invalid branch name: {}", branch));
+        }
+    }
+    store(&pool, REMOTE_URL_KEY, Some(remote_url)).await?;
+    store(&pool, BRANCH_KEY, branch).await?;
+    store(&pool, AUTHOR_NAME_KEY, author_name).await?;
+    store(&pool, AUTHOR_EMAIL_KEY, author_email).await
+}
+
+/// Credentials for the remote: a token (HTTPS) or a private key in OpenSSH
+/// format. They're kept with the other settings, so they're encrypted along
+/// with the database when that is on. Without a key, the SSH agent is tried.
+#[tauri::command]
+pub async fn set_git_credentials(
+    pool: State<'_, SqlitePool>,
+    username: Option<String>,
+    token: Option<String>,
+    ssh_key: Option<String>,
+    ssh_passphrase: Option<String>,
+) -> Result<(), String> {
+    println!("Backend: set_git_credentials");
+    store(&pool, USERNAME_KEY, username).await?;
+    store(&pool, TOKEN_KEY, token).await?;
+    store(&pool, SSH_KEY_KEY, ssh_key).await?;
+    store(&pool, SSH_PASSPHRASE_KEY, ssh_passphrase).await
+}
+
+/// Commit changes made in the app, pull and merge the remote into the notes,
+/// then push.
+#[tauri::command]
+pub async fn git_sync(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    sync: State<'_, GitSyncState>,
+) -> Result<GitSyncReport, String> {
+    let _running = sync.0.try_lock().map_err(|_| "A sync is already running".to_string())?;
+    let remote = load_remote(&pool).await?;
+    let root = app_handle.path().app_data_dir().map_err(|e| e.to_string())?.join(WORK_DIR);
+    println!("Backend: git_sync: {} ({})", remote.url, remote.branch);
+    tokio::fs::create_dir_all(&root).await.map_err(|e| e.to_string())?;
+
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    let report = run(&mut conn, &root, &remote).await?;
+    println!(
+        "Backend: git_sync done: {} imported, {} deleted, {} conflicts",
+        report.imported,
+        report.deleted,
+        report.conflicts.len()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, TestDb};
+    use tempfile::TempDir;
+
+    fn entry(path: &str, data: &str) -> Entry {
+        Entry { path: path.to_string(), data: data.as_bytes().to_vec() }
+    }
+
+    fn text(entry: &Entry) -> &str {
+        std::str::from_utf8(&entry.data).unwrap()
+    }
+
+    #[test]
+    fn pick_takes_the_side_that_changed() {
+        assert_eq!(pick(Some(&1), Some(&1), Some(&2)), Some(2));
+        assert_eq!(pick(Some(&1), Some(&2), Some(&1)), Some(2));
+        assert_eq!(pick(Some(&1), Some(&2), Some(&3)), Some(2));
+        assert_eq!(pick(Some(&1), Some(&1), None), None);
+        assert_eq!(pick(None, None, Some(&3)), Some(3));
+    }
+
+    #[test]
+    fn merge_file_combines_front_matter_and_body() {
+        let base = entry("a.md", "---\ntitle: A\nlocal_uuid: u\n---\n\none\ntwo\nthree\n");
+        let ours = entry("a.md", "---\ntitle: A\nlocal_uuid: u\nfolder: x\n---\n\nONE\ntwo\nthree\n");
+        let theirs = entry("b.md", "---\ntitle: B\nlocal_uuid: u\n---\n\none\ntwo\nTHREE\n");
+
+        let merged = merge_file(Some(&base), &ours, &theirs).unwrap();
+        assert_eq!(merged.path, "b.md");
+        let (front_matter, body) = vault::split_front_matter(text(&merged));
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.get("title").and_then(|t| t.as_str()), Some("B"));
+        assert_eq!(front_matter.get("folder").and_then(|t| t.as_str()), Some("x"));
+        assert_eq!(body, "ONE\ntwo\nTHREE\n");
+    }
+
+    #[test]
+    fn merge_file_gives_up_on_overlapping_edits() {
+        let base = entry("a.md", "one\n");
+        assert!(merge_file(Some(&base), &entry("a.md", "ours\n"), &entry("a.md", "theirs\n")).is_none());
+        // Only markdown is merged
+        let base = entry("a.txt", "one\ntwo\n");
+        assert!(merge_file(Some(&base), &entry("a.txt", "1\ntwo\n"), &entry("a.txt", "one\n2\n")).is_none());
+    }
+
+    /// A device: its database and its work tree.
+    struct Device {
+        db: TestDb,
+        root: TempDir,
+    }
+
+    impl Device {
+        async fn new() -> Self {
+            Device { db: test_support::database().await, root: tempfile::tempdir().unwrap() }
+        }
+
+        async fn sync(&self, remote: &Remote) -> GitSyncReport {
+            let mut conn = self.db.pool.acquire().await.unwrap();
+            run(&mut conn, self.root.path(), remote).await.unwrap()
+        }
+    }
+
+    fn bare_remote(dir: &TempDir) -> Remote {
+        Repository::init_bare(dir.path()).unwrap();
+        Remote {
+            url: dir.path().to_string_lossy().to_string(),
+            branch: DEFAULT_BRANCH.to_string(),
+            author_name: Some("Test".to_string()),
+            author_email: Some("test@localhost".to_string()),
+            username: None,
+            token: None,
+            ssh_key: None,
+            ssh_passphrase: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn edits_travel_between_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = bare_remote(&dir);
+        let (a, b) = (Device::new().await, Device::new().await);
+
+        test_support::add_note(&a.db.pool, "Groceries", "milk\n").await;
+        let report = a.sync(&remote).await;
+        assert!(report.committed && report.pushed);
+
+        let report = b.sync(&remote).await;
+        assert_eq!(report.imported, 1);
+        assert_eq!(test_support::notes(&b.db.pool).await, [("Groceries".to_string(), "milk\n".to_string())]);
+
+        sqlx::query("UPDATE notes SET content = 'milk\neggs\n'")
+            .execute(&b.db.pool)
+            .await
+            .unwrap();
+        b.sync(&remote).await;
+        a.sync(&remote).await;
+        assert_eq!(test_support::notes(&a.db.pool).await, [("Groceries".to_string(), "milk\neggs\n".to_string())]);
+
+        // Deleting it here trashes it there
+        sqlx::query("UPDATE notes SET deleted_at = CURRENT_TIMESTAMP")
+            .execute(&a.db.pool)
+            .await
+            .unwrap();
+        a.sync(&remote).await;
+        let report = b.sync(&remote).await;
+        assert_eq!(report.deleted, 1);
+        assert!(test_support::notes(&b.db.pool).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn locking_a_note_leaves_it_on_other_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = bare_remote(&dir);
+        let (a, b) = (Device::new().await, Device::new().await);
+
+        test_support::add_note(&a.db.pool, "Diary", "secret\n").await;
+        a.sync(&remote).await;
+        b.sync(&remote).await;
+
+        sqlx::query("UPDATE notes SET content = '', locked_ciphertext = x'00'")
+            .execute(&a.db.pool)
+            .await
+            .unwrap();
+        a.sync(&remote).await;
+        assert!(a.root.path().join("Diary.md").exists());
+
+        let report = b.sync(&remote).await;
+        assert_eq!(report.deleted, 0);
+        assert_eq!(test_support::notes(&b.db.pool).await, [("Diary".to_string(), "secret\n".to_string())]);
+    }
+}
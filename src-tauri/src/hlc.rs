@@ -0,0 +1,171 @@
+use sqlx::SqliteConnection;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Every note carries a hybrid logical clock reading, `hlc`, formatted as
+// `<unix ms, 13 digits>:<counter, 5 digits>:<device id>` so readings order as
+// text. Local edits take theirs from the `hlc_clock` row through the triggers
+// in the `hybrid_clock` migration; `updated_at` is the reading's wall time.
+
+// Stands in for the device of cloud copies uploaded without a clock reading
+const UNKNOWN_DEVICE: &str = "remote";
+// How far ahead of this device's clock a reading may move it. One from
+// further out (a device or server with a wrong clock) would drag every later
+// edit along.
+const MAX_DRIFT_MS: i64 = 5 * 60 * 1000;
+
+/// The cloud copy's clock reading, and its edit time as stored here.
+pub(crate) struct Stamp {
+    pub hlc: String,
+    /// UTC, `YYYY-MM-DD HH:MM:SS.SSS`
+    pub updated_at: String,
+}
+
+fn parse(hlc: &str) -> Option<(i64, i64, &str)> {
+    let mut parts = hlc.splitn(3, ':');
+    let wall = parts.next()?.parse().ok()?;
+    let counter = parts.next()?.parse().ok()?;
+    let device = parts.next().filter(|d| !d.is_empty())?;
+    Some((wall, counter, device))
+}
+
+fn format(wall: i64, counter: i64, device: &str) -> String {
+    format!("{:013}:{:05}:{}", wall, counter, device)
+}
+
+/// Take in the clock reading of a cloud copy, or derive one from
+/// `updated_at` for copies uploaded without it. This device's clock moves
+/// past it, so edits made from now on order after the cloud's. A reading too
+/// far in the future is kept on the note, but moves the clock only so far.
+pub(crate) async fn receive(
+    conn: &mut SqliteConnection,
+    updated_at: &str,
+    hlc: Option<&str>,
+) -> Result<Stamp, String> {
+    let (wall, counter, device) = match hlc {
+        Some(hlc) => parse(hlc).ok_or_else(|| format!("Invalid clock reading: {}", hlc))?,
+        None => {
+            let wall: Option<i64> =
+                sqlx::query_scalar("SELECT CAST(ROUND((julianday($1) - 2440587.5) * 86400000) AS INTEGER)")
+                    .bind(updated_at)
+                    .fetch_one(&mut *conn)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            let wall = wall.ok_or_else(|| format!("Invalid timestamp: {}", updated_at))?;
+            (wall, 0, UNKNOWN_DEVICE)
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_millis() as i64;
+    let (clock_wall, clock_counter) = if wall - now > MAX_DRIFT_MS {
+        println!("Backend: Clock reading is {}s ahead of this device, not following it", (wall - now) / 1000);
+        (now + MAX_DRIFT_MS, 0)
+    } else {
+        (wall, counter)
+    };
+
+    sqlx::query("UPDATE hlc_clock SET wall = $1, counter = $2 WHERE (wall, counter) < ($1, $2)")
+        .bind(clock_wall)
+        .bind(clock_counter)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let updated_at: String = sqlx::query_scalar("SELECT strftime('%Y-%m-%d %H:%M:%f', $1 / 1000.0, 'unixepoch')")
+        .bind(wall)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(Stamp { hlc: format(wall, counter, device), updated_at })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+
+    async fn clock() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE hlc_clock (id INTEGER PRIMARY KEY, wall INTEGER NOT NULL, counter INTEGER NOT NULL, device TEXT NOT NULL);
+             INSERT INTO hlc_clock VALUES (1, 0, 0, 'local');",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        conn
+    }
+
+    async fn reading(conn: &mut SqliteConnection) -> (i64, i64) {
+        sqlx::query_as("SELECT wall, counter FROM hlc_clock").fetch_one(conn).await.unwrap()
+    }
+
+    fn now_ms() -> i64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+    }
+
+    #[test]
+    fn readings_order_as_text() {
+        let ordered = [
+            format(999, 7, "b"),
+            format(1_000, 0, "b"),
+            format(1_000, 2, "a"),
+            format(1_000, 10, "a"),
+            format(1_000, 10, "b"),
+            format(1_700_000_000_000, 0, "a"),
+        ];
+        for pair in ordered.windows(2) {
+            assert!(pair[0] < pair[1], "{} should sort before {}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn parse_reads_back_format() {
+        let hlc = format(1_700_000_000_123, 42, "0a1b:c");
+        assert_eq!(parse(&hlc), Some((1_700_000_000_123, 42, "0a1b:c")));
+        assert_eq!(parse("1700000000123:00001:"), None);
+        assert_eq!(parse("1700000000123"), None);
+        assert_eq!(parse("soon:00001:dev"), None);
+    }
+
+    #[tokio::test]
+    async fn receive_moves_the_clock_past_the_reading() {
+        let mut conn = clock().await;
+        let wall = now_ms() - 1_000;
+        let hlc = format(wall, 3, "remote-dev");
+
+        let stamp = receive(&mut conn, "", Some(&hlc)).await.unwrap();
+        assert_eq!(stamp.hlc, hlc);
+        assert_eq!(reading(&mut conn).await, (wall, 3));
+
+        // An older reading leaves it where it is
+        receive(&mut conn, "", Some(&format(wall - 5, 9, "other"))).await.unwrap();
+        assert_eq!(reading(&mut conn).await, (wall, 3));
+    }
+
+    #[tokio::test]
+    async fn receive_keeps_but_does_not_follow_readings_far_ahead() {
+        let mut conn = clock().await;
+        let before = now_ms();
+        let hlc = format(before + 24 * 60 * 60 * 1000, 0, "fast-dev");
+
+        let stamp = receive(&mut conn, "", Some(&hlc)).await.unwrap();
+        assert_eq!(stamp.hlc, hlc);
+        let (wall, _) = reading(&mut conn).await;
+        assert!(wall >= before + MAX_DRIFT_MS && wall <= now_ms() + MAX_DRIFT_MS);
+    }
+
+    #[tokio::test]
+    async fn receive_derives_a_reading_from_updated_at() {
+        let mut conn = clock().await;
+        let stamp = receive(&mut conn, "2024-03-01 12:00:00", None).await.unwrap();
+        assert_eq!(stamp.hlc, format(1_709_294_400_000, 0, UNKNOWN_DEVICE));
+        assert_eq!(stamp.updated_at, "2024-03-01 12:00:00.000");
+
+        // A server clock far ahead of this device's doesn't fail the import
+        let ahead = receive(&mut conn, "2999-01-01 00:00:00", None).await.unwrap();
+        assert_eq!(ahead.updated_at, "2999-01-01 00:00:00.000");
+    }
+}
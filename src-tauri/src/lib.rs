@@ -1,13 +1,54 @@
+mod attachments;
+mod changes;
+mod cloud_sync;
 mod commands;
+mod conflicts;
 mod database;
 mod email; // Tell Rust to look for commands.rs
+mod encryption;
+mod folders;
+mod git_sync;
+mod hlc;
+mod links;
+mod locked;
+mod migrations;
+mod mirror;
+mod revisions;
+mod s3;
+mod search;
+mod settings;
+mod tags;
+#[cfg(test)]
+mod test_support;
+mod trash_bin;
+mod vault;
+mod webdav;
+mod yjs;
 
 use database::Database;
 use tauri::Manager;
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
 
 // We "use" everything from the commands module so the generate_handler can see them
+use attachments::*;
+use changes::*;
 use commands::*;
+use conflicts::*;
 use email::*;
+use encryption::*;
+use folders::*;
+use git_sync::*;
+use links::*;
+use locked::*;
+use mirror::*;
+use revisions::*;
+use s3::*;
+use search::*;
+use tags::*;
+use trash_bin::*;
+use vault::*;
+use webdav::*;
+use yjs::*;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -16,11 +57,37 @@ pub fn run() {
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_oauth::init())
+        .register_asynchronous_uri_scheme_protocol(attachments::URI_SCHEME, |ctx, request, responder| {
+            let app_handle = ctx.app_handle().clone();
+            tauri::async_runtime::spawn(async move {
+                responder.respond(attachments::handle_uri(app_handle, request).await);
+            });
+        })
         .setup(|app| {
-            tauri::async_runtime::block_on(async {
-                let db_pool = Database::setup(app.handle()).await;
+            // An encrypted database stays closed until unlock_database gets the passphrase
+            if tauri::async_runtime::block_on(Database::is_encrypted(app.handle())) {
+                println!("Database is encrypted, waiting for unlock");
+            } else {
+                let db_pool = match tauri::async_runtime::block_on(Database::setup(app.handle(), None)) {
+                    Ok(pool) => pool,
+                    Err(e) => {
+                        eprintln!("CRITICAL ERROR: Database setup failed: {}", e);
+                        app.dialog()
+                            .message(format!("Onyx could not open its database.\n\n{}", e))
+                            .title("Database error")
+                            .kind(MessageDialogKind::Error)
+                            .blocking_show();
+                        return Err(e.into());
+                    }
+                };
                 app.manage(db_pool);
-            });
+            }
+            app.manage(LockedNoteKeys::default());
+            app.manage(MirrorState::default());
+            app.manage(GitSyncState::default());
+            app.manage(WebDavState::default());
+            app.manage(S3State::default());
+            tauri::async_runtime::spawn(mirror::start_if_enabled(app.handle().clone()));
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
@@ -34,10 +101,78 @@ pub fn run() {
             update_note,
             update_note_pb_id,
             import_note_from_pb,
+            list_note_conflicts,
+            resolve_note_conflict,
+            mark_note_synced,
             delete_note,
             delete_note_by_pb_id,
+            get_pending_changes,
+            ack_changes,
             ensure_local_uuid,
-            move_to_trash
+            move_to_trash,
+            search_notes,
+            list_revisions,
+            diff_revisions,
+            restore_revision,
+            list_trash,
+            restore_note,
+            purge_note,
+            empty_trash,
+            get_trash_auto_purge_days,
+            set_trash_auto_purge_days,
+            get_tombstones,
+            create_folder,
+            rename_folder,
+            move_folder,
+            delete_folder,
+            move_note,
+            get_folder_tree,
+            list_tags,
+            get_note_tags,
+            add_tag,
+            remove_tag,
+            rename_tag,
+            merge_tags,
+            find_notes_by_tags,
+            get_backlinks,
+            get_outgoing_links,
+            get_unresolved_links,
+            get_link_graph,
+            rename_note,
+            store_attachment,
+            import_attachment,
+            list_note_attachments,
+            gc_attachments,
+            lock_note,
+            open_locked_note,
+            save_locked_note,
+            close_locked_note,
+            unlock_note,
+            get_database_status,
+            unlock_database,
+            enable_database_encryption,
+            change_database_passphrase,
+            rekey_database,
+            export_workspace,
+            import_markdown_folder,
+            enable_mirror,
+            disable_mirror,
+            get_mirror_status,
+            get_git_sync_config,
+            set_git_sync_config,
+            set_git_credentials,
+            git_sync,
+            get_webdav_config,
+            set_webdav_config,
+            disable_webdav_sync,
+            webdav_sync,
+            get_s3_config,
+            set_s3_config,
+            disable_s3_sync,
+            s3_sync,
+            apply_yjs_update,
+            get_yjs_state_vector,
+            get_yjs_diff
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
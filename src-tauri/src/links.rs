@@ -0,0 +1,353 @@
+use crate::{commands, revisions};
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use std::ops::Range;
+use tauri::State;
+
+/// A `[[Target#Heading|Alias]]` link found in note content.
+pub struct WikiLink {
+    /// The whole link, brackets included
+    pub range: Range<usize>,
+    pub target: String,
+    pub heading: Option<String>,
+    pub alias: Option<String>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct LinkedNote {
+    pub id: i64,
+    pub title: String,
+    pub local_uuid: Option<String>,
+    /// Number of links between the two notes
+    pub link_count: i64,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct OutgoingLink {
+    pub target_title: String,
+    pub alias: Option<String>,
+    /// `None` when no note has that title
+    pub target_id: Option<i64>,
+    pub target_uuid: Option<String>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct UnresolvedLink {
+    pub target_title: String,
+    pub source_id: i64,
+    pub source_title: String,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct GraphNode {
+    pub id: String,
+    pub note_id: i64,
+    pub title: String,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub weight: i64,
+}
+
+#[derive(Serialize)]
+pub struct LinkGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+// Resolves a link target to the oldest live note with that title
+const RESOLVE_TARGET: &str = "(SELECT r.id FROM notes r
+    WHERE lower(r.title) = lower(l.target_title) AND r.deleted_at IS NULL
+    ORDER BY r.id LIMIT 1)";
+
+// --- Parsing ---
+
+/// Wiki-links in markdown, skipping embeds (`![[...]]`), code spans and fenced code blocks.
+pub fn parse_links(content: &str) -> Vec<WikiLink> {
+    let mut found = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence {
+            scan_line(line, offset, &mut found);
+        }
+        offset += line.len();
+    }
+
+    found
+}
+
+fn scan_line(line: &str, offset: usize, found: &mut Vec<WikiLink>) {
+    let bytes = line.as_bytes();
+    let mut in_code = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'`' => in_code = !in_code,
+            b'[' if !in_code && bytes.get(i + 1) == Some(&b'[') && (i == 0 || bytes[i - 1] != b'!') => {
+                if let Some(len) = line[i + 2..].find("]]") {
+                    let inner = &line[i + 2..i + 2 + len];
+                    let end = i + 2 + len + 2;
+                    if let Some(link) = parse_inner(inner, offset + i..offset + end) {
+                        found.push(link);
+                    }
+                    i = end;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+}
+
+fn parse_inner(inner: &str, range: Range<usize>) -> Option<WikiLink> {
+    let (path, alias) = match inner.split_once('|') {
+        Some((path, alias)) => (path, Some(alias.trim().to_string())),
+        None => (inner, None),
+    };
+    let (target, heading) = match path.split_once('#') {
+        Some((target, heading)) => (target, Some(heading.trim().to_string())),
+        None => (path, None),
+    };
+
+    let target = target.trim();
+    if target.is_empty() || target.contains('[') {
+        return None;
+    }
+
+    Some(WikiLink {
+        range,
+        target: target.to_string(),
+        heading,
+        alias: alias.filter(|a| !a.is_empty()),
+    })
+}
+
+/// Re-derive the note's outgoing links from its content.
+pub async fn sync_links(conn: &mut SqliteConnection, note_id: i64, content: &str) -> Result<(), sqlx::Error> {
+    let uuid: Option<(Option<String>,)> = sqlx::query_as("SELECT local_uuid FROM notes WHERE id = $1")
+        .bind(note_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    let Some((Some(uuid),)) = uuid else {
+        return Ok(());
+    };
+
+    sqlx::query("DELETE FROM note_links WHERE source_uuid = $1")
+        .bind(&uuid)
+        .execute(&mut *conn)
+        .await?;
+
+    for (position, link) in parse_links(content).into_iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO note_links (source_uuid, position, target_title, alias) VALUES ($1, $2, $3, $4)",
+        )
+        .bind(&uuid)
+        .bind(position as i64)
+        .bind(&link.target)
+        .bind(&link.alias)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+// --- Commands ---
+
+/// Notes that link to this one.
+#[tauri::command]
+pub async fn get_backlinks(pool: State<'_, SqlitePool>, note_id: i64) -> Result<Vec<LinkedNote>, String> {
+    let sql = format!(
+        "SELECT s.id, s.title, s.local_uuid, COUNT(*) AS link_count
+         FROM note_links l
+         JOIN notes s ON s.local_uuid = l.source_uuid AND s.deleted_at IS NULL
+         WHERE {} = $1
+         GROUP BY s.id
+         ORDER BY s.title COLLATE NOCASE",
+        RESOLVE_TARGET
+    );
+
+    sqlx::query_as::<_, LinkedNote>(&sql)
+        .bind(note_id)
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Links in this note, in the order they appear.
+#[tauri::command]
+pub async fn get_outgoing_links(pool: State<'_, SqlitePool>, note_id: i64) -> Result<Vec<OutgoingLink>, String> {
+    let sql = format!(
+        "SELECT l.target_title, l.alias, t.id AS target_id, t.local_uuid AS target_uuid
+         FROM note_links l
+         JOIN notes s ON s.local_uuid = l.source_uuid
+         LEFT JOIN notes t ON t.id = {}
+         WHERE s.id = $1
+         ORDER BY l.position",
+        RESOLVE_TARGET
+    );
+
+    sqlx::query_as::<_, OutgoingLink>(&sql)
+        .bind(note_id)
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Links to titles no note has, across the workspace.
+#[tauri::command]
+pub async fn get_unresolved_links(pool: State<'_, SqlitePool>) -> Result<Vec<UnresolvedLink>, String> {
+    let sql = format!(
+        "SELECT DISTINCT l.target_title, s.id AS source_id, s.title AS source_title
+         FROM note_links l
+         JOIN notes s ON s.local_uuid = l.source_uuid AND s.deleted_at IS NULL
+         WHERE {} IS NULL
+         ORDER BY l.target_title COLLATE NOCASE, s.title COLLATE NOCASE",
+        RESOLVE_TARGET
+    );
+
+    sqlx::query_as::<_, UnresolvedLink>(&sql)
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Every live note as a node, keyed by `local_uuid`, and resolved links as edges.
+#[tauri::command]
+pub async fn get_link_graph(pool: State<'_, SqlitePool>) -> Result<LinkGraph, String> {
+    let nodes = sqlx::query_as::<_, GraphNode>(
+        "SELECT local_uuid AS id, id AS note_id, title FROM notes
+         WHERE deleted_at IS NULL AND local_uuid IS NOT NULL",
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let sql = format!(
+        "SELECT l.source_uuid AS source, t.local_uuid AS target, COUNT(*) AS weight
+         FROM note_links l
+         JOIN notes s ON s.local_uuid = l.source_uuid AND s.deleted_at IS NULL
+         JOIN notes t ON t.id = {}
+         WHERE t.local_uuid IS NOT NULL
+         GROUP BY l.source_uuid, t.local_uuid",
+        RESOLVE_TARGET
+    );
+    let edges = sqlx::query_as::<_, GraphEdge>(&sql)
+        .fetch_all(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(LinkGraph { nodes, edges })
+}
+
+/// Change a note's title. With `rewrite_links`, every `[[Old Title]]` pointing
+/// at it is updated too (headings and aliases are kept). Returns how many notes
+/// were rewritten.
+#[tauri::command]
+pub async fn rename_note(
+    pool: State<'_, SqlitePool>,
+    id: i64,
+    title: String,
+    rewrite_links: bool,
+) -> Result<usize, String> {
+    let title = title.trim().to_string();
+    if title.is_empty() {
+        return Err("Title can't be empty".to_string());
+    }
+    if rewrite_links && title.contains(['[', ']', '|', '#']) {
+        return Err("Titles with [ ] | or # can't be used in links".to_string());
+    }
+    println!("Backend: rename_note: {} -> {} (rewrite_links={})", id, title, rewrite_links);
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    let old: Option<(String,)> = sqlx::query_as("SELECT title FROM notes WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (old_title,) = old.ok_or("Note not found")?;
+
+    // Find the linking notes while the old title still resolves to this one
+    let sources: Vec<(i64, Option<String>)> = if rewrite_links {
+        let sql = format!(
+            "SELECT DISTINCT s.id, s.content FROM note_links l
+             JOIN notes s ON s.local_uuid = l.source_uuid
+             WHERE {} = $1",
+            RESOLVE_TARGET
+        );
+        sqlx::query_as(&sql)
+            .bind(id)
+            .fetch_all(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?
+    } else {
+        Vec::new()
+    };
+
+    sqlx::query("UPDATE notes SET title = $1 WHERE id = $2")
+        .bind(&title)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut rewritten = 0;
+    for (source_id, content) in sources {
+        let content = content.unwrap_or_default();
+        let updated = rewrite_links_to(&content, &old_title, &title);
+        if updated == content {
+            continue;
+        }
+
+        revisions::snapshot(&mut tx, source_id, "rename", false)
+            .await
+            .map_err(|e| e.to_string())?;
+        sqlx::query("UPDATE notes SET content = $1 WHERE id = $2")
+            .bind(&updated)
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        commands::reindex(&mut tx, source_id, &updated).await?;
+        rewritten += 1;
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(rewritten)
+}
+
+fn rewrite_links_to(content: &str, old_title: &str, new_title: &str) -> String {
+    let old_title = old_title.to_lowercase();
+    let mut out = content.to_string();
+
+    // Back to front so earlier ranges stay valid
+    for link in parse_links(content).into_iter().rev() {
+        if link.target.to_lowercase() != old_title {
+            continue;
+        }
+        let mut replacement = format!("[[{}", new_title);
+        if let Some(heading) = &link.heading {
+            replacement.push('#');
+            replacement.push_str(heading);
+        }
+        if let Some(alias) = &link.alias {
+            replacement.push('|');
+            replacement.push_str(alias);
+        }
+        replacement.push_str("]]");
+        out.replace_range(link.range, &replacement);
+    }
+
+    out
+}
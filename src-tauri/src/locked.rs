@@ -0,0 +1,379 @@
+use crate::{commands, links, tags, yjs};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tauri::State;
+use zeroize::Zeroizing;
+
+const CIPHER: &str = "xchacha20poly1305";
+// Argon2id, 64 MiB, 3 passes: roughly half a second on a laptop
+const KDF_MEMORY_KIB: u32 = 64 * 1024;
+const KDF_ITERATIONS: u32 = 3;
+const KDF_PARALLELISM: u32 = 1;
+pub(crate) const SALT_LEN: usize = 16;
+pub(crate) const NONCE_LEN: usize = 24;
+
+pub(crate) type NoteKey = Zeroizing<[u8; 32]>;
+
+struct OpenNote {
+    key: NoteKey,
+    salt: Vec<u8>,
+    kdf: String,
+}
+
+/// Keys of locked notes the user has opened this session. Dropping an entry
+/// wipes the key; nothing here is ever written to disk.
+#[derive(Default)]
+pub struct LockedNoteKeys(Mutex<HashMap<i64, OpenNote>>);
+
+#[derive(Serialize)]
+pub struct UnlockedNote {
+    pub id: i64,
+    pub title: String,
+    pub content: String,
+}
+
+struct Sealed {
+    ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+}
+
+pub(crate) fn kdf_string() -> String {
+    format!("argon2id$v=19$m={},t={},p={}", KDF_MEMORY_KIB, KDF_ITERATIONS, KDF_PARALLELISM)
+}
+
+/// Argon2id takes about half a second, so it runs on the blocking pool
+/// rather than holding up an async worker.
+pub(crate) async fn derive_key(password: &str, salt: &[u8], kdf: &str) -> Result<NoteKey, String> {
+    let (password, salt, kdf) = (Zeroizing::new(password.to_string()), salt.to_vec(), kdf.to_string());
+    tauri::async_runtime::spawn_blocking(move || derive_key_blocking(&password, &salt, &kdf))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Parameters are stored per note so they can be raised later without
+// breaking notes locked with the old ones
+fn derive_key_blocking(password: &str, salt: &[u8], kdf: &str) -> Result<NoteKey, String> {
+    let mut m = KDF_MEMORY_KIB;
+    let mut t = KDF_ITERATIONS;
+    let mut p = KDF_PARALLELISM;
+
+    let settings = kdf
+        .strip_prefix("argon2id$v=19$")
+        .ok_or_else(|| format!("Unsupported key derivation: {}", kdf))?;
+    for pair in settings.split(',') {
+        let (name, value) = pair.split_once('=').ok_or("Malformed key derivation parameters")?;
+        let value: u32 = value.parse().map_err(|_| "Malformed key derivation parameters")?;
+        match name {
+            "m" => m = value,
+            "t" => t = value,
+            "p" => p = value,
+            _ => return Err(format!("Unknown key derivation parameter: {}", name)),
+        }
+    }
+
+    let params = Params::new(m, t, p, Some(32)).map_err(|e| e.to_string())?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), salt, key.as_mut())
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+// The note's uuid is bound in as associated data, so ciphertext copied onto
+// another note fails to decrypt
+fn seal(key: &NoteKey, uuid: &str, plaintext: &str) -> Result<Sealed, String> {
+    let mut nonce = vec![0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+    let ciphertext = cipher
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext.as_bytes(),
+                aad: uuid.as_bytes(),
+            },
+        )
+        .map_err(|_| "Encryption failed")?;
+
+    Ok(Sealed { ciphertext, nonce })
+}
+
+fn open(key: &NoteKey, uuid: &str, ciphertext: &[u8], nonce: &[u8]) -> Result<Zeroizing<String>, String> {
+    if nonce.len() != NONCE_LEN {
+        return Err("Corrupt locked note".to_string());
+    }
+
+    let cipher = XChaCha20Poly1305::new(key.as_ref().into());
+    let plaintext = Zeroizing::new(
+        cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: uuid.as_bytes(),
+                },
+            )
+            .map_err(|_| "Wrong password")?,
+    );
+
+    String::from_utf8(plaintext.to_vec())
+        .map(Zeroizing::new)
+        .map_err(|_| "Corrupt locked note".to_string())
+}
+
+struct LockedRow {
+    title: String,
+    uuid: String,
+    ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+    salt: Vec<u8>,
+    kdf: String,
+    cipher: String,
+}
+
+#[derive(FromRow)]
+struct LockedColumns {
+    title: String,
+    local_uuid: Option<String>,
+    locked_ciphertext: Option<Vec<u8>>,
+    locked_nonce: Option<Vec<u8>>,
+    locked_salt: Option<Vec<u8>>,
+    locked_kdf: Option<String>,
+    locked_cipher: Option<String>,
+}
+
+async fn fetch_locked(pool: &SqlitePool, id: i64) -> Result<LockedRow, String> {
+    let row = sqlx::query_as::<_, LockedColumns>(
+        "SELECT title, local_uuid, locked_ciphertext, locked_nonce, locked_salt, locked_kdf, locked_cipher
+         FROM notes WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| e.to_string())?
+    .ok_or("Note not found")?;
+
+    match (row.local_uuid, row.locked_ciphertext, row.locked_nonce, row.locked_salt, row.locked_kdf, row.locked_cipher) {
+        (Some(uuid), Some(ciphertext), Some(nonce), Some(salt), Some(kdf), Some(cipher)) => {
+            if cipher != CIPHER {
+                return Err(format!("Unsupported cipher: {}", cipher));
+            }
+            Ok(LockedRow {
+                title: row.title,
+                uuid,
+                ciphertext,
+                nonce,
+                salt,
+                kdf,
+                cipher,
+            })
+        }
+        _ => Err("Note is not locked".to_string()),
+    }
+}
+
+/// Drop everything derived from the plaintext: index entries, history and
+/// the copies kept for merging synced versions. Attachment references stay,
+/// so embedded files survive garbage collection.
+async fn forget_plaintext(conn: &mut SqliteConnection, id: i64) -> Result<(), String> {
+    tags::sync_inline(&mut *conn, id, "").await.map_err(|e| e.to_string())?;
+    links::sync_links(&mut *conn, id, "").await.map_err(|e| e.to_string())?;
+    for sql in [
+        "DELETE FROM note_revisions WHERE note_id = $1",
+        "DELETE FROM note_sync_base WHERE note_id = $1",
+        "DELETE FROM note_conflicts WHERE note_id = $1",
+    ] {
+        sqlx::query(sql)
+            .bind(id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    yjs::forget(&mut *conn, id).await?;
+    Ok(())
+}
+
+pub async fn is_locked(conn: &mut SqliteConnection, id: i64) -> Result<bool, sqlx::Error> {
+    let row: Option<(bool,)> = sqlx::query_as("SELECT locked_ciphertext IS NOT NULL FROM notes WHERE id = $1")
+        .bind(id)
+        .fetch_optional(conn)
+        .await?;
+    Ok(row.is_some_and(|r| r.0))
+}
+
+// --- Commands ---
+
+/// Encrypt a note's content with a password. The plaintext is removed from
+/// the note, its revisions and the search, tag and link indexes. The title
+/// stays readable so the note can still be listed.
+#[tauri::command]
+pub async fn lock_note(pool: State<'_, SqlitePool>, id: i64, password: String) -> Result<(), String> {
+    let password = Zeroizing::new(password);
+    if password.chars().count() < 8 {
+        return Err("Use at least 8 characters".to_string());
+    }
+    println!("Backend: lock_note: {}", id);
+
+    // Argon2 is slow; derive the key before the transaction starts
+    let mut salt = vec![0u8; SALT_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let kdf = kdf_string();
+    let key = derive_key(&password, &salt, &kdf).await?;
+
+    // Read and seal in the transaction, so an edit can't land in between
+    // and stay behind as plaintext
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let row: Option<(Option<String>, Option<String>, bool)> = sqlx::query_as(
+        "SELECT local_uuid, content, locked_ciphertext IS NOT NULL FROM notes WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let (uuid, content, locked) = row.ok_or("Note not found")?;
+    if locked {
+        return Err("Note is already locked".to_string());
+    }
+    let uuid = uuid.ok_or("Note has no uuid")?;
+    let content = Zeroizing::new(content.unwrap_or_default());
+    let sealed = seal(&key, &uuid, &content)?;
+
+    sqlx::query(
+        "UPDATE notes SET content = NULL, locked_ciphertext = $1, locked_nonce = $2,
+         locked_salt = $3, locked_kdf = $4, locked_cipher = $5 WHERE id = $6",
+    )
+    .bind(&sealed.ciphertext)
+    .bind(&sealed.nonce)
+    .bind(&salt)
+    .bind(&kdf)
+    .bind(CIPHER)
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    forget_plaintext(&mut tx, id).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    // Merge FTS segments so the removed terms don't linger in old ones
+    sqlx::query("INSERT INTO notes_fts (notes_fts) VALUES ('optimize')")
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    // secure_delete zeroes freed pages in the main file, but the WAL still
+    // holds copies of the old ones until it's checkpointed and truncated
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Decrypt a locked note for viewing and keep its key for `save_locked_note`
+/// until `close_locked_note`. The stored note stays encrypted.
+#[tauri::command]
+pub async fn open_locked_note(
+    pool: State<'_, SqlitePool>,
+    keys: State<'_, LockedNoteKeys>,
+    id: i64,
+    password: String,
+) -> Result<UnlockedNote, String> {
+    let password = Zeroizing::new(password);
+    let row = fetch_locked(&pool, id).await?;
+    let key = derive_key(&password, &row.salt, &row.kdf).await?;
+    let content = open(&key, &row.uuid, &row.ciphertext, &row.nonce)?;
+
+    keys.0.lock().unwrap().insert(
+        id,
+        OpenNote {
+            key,
+            salt: row.salt,
+            kdf: row.kdf,
+        },
+    );
+
+    Ok(UnlockedNote {
+        id,
+        title: row.title,
+        content: content.to_string(),
+    })
+}
+
+/// Save edits to an opened locked note. Encrypted with a fresh nonce; the
+/// plaintext is never written.
+#[tauri::command]
+pub async fn save_locked_note(
+    pool: State<'_, SqlitePool>,
+    keys: State<'_, LockedNoteKeys>,
+    id: i64,
+    title: String,
+    content: String,
+) -> Result<(), String> {
+    let content = Zeroizing::new(content);
+    let row = fetch_locked(&pool, id).await?;
+
+    let sealed = {
+        let open_notes = keys.0.lock().unwrap();
+        let note = open_notes.get(&id).ok_or("Note is not open")?;
+        if note.salt != row.salt || note.kdf != row.kdf {
+            return Err("Note was re-locked, open it again".to_string());
+        }
+        seal(&note.key, &row.uuid, &content)?
+    };
+
+    sqlx::query("UPDATE notes SET title = $1, locked_ciphertext = $2, locked_nonce = $3 WHERE id = $4")
+        .bind(title)
+        .bind(&sealed.ciphertext)
+        .bind(&sealed.nonce)
+        .bind(id)
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// Forget the key of an opened locked note.
+#[tauri::command]
+pub fn close_locked_note(keys: State<'_, LockedNoteKeys>, id: i64) {
+    keys.0.lock().unwrap().remove(&id);
+}
+
+/// Decrypt a locked note for good and put its content back in the clear.
+#[tauri::command]
+pub async fn unlock_note(
+    pool: State<'_, SqlitePool>,
+    keys: State<'_, LockedNoteKeys>,
+    id: i64,
+    password: String,
+) -> Result<(), String> {
+    let password = Zeroizing::new(password);
+    let row = fetch_locked(&pool, id).await?;
+    let key = derive_key(&password, &row.salt, &row.kdf).await?;
+    let content = open(&key, &row.uuid, &row.ciphertext, &row.nonce)?;
+    println!("Backend: unlock_note: {} ({})", id, row.cipher);
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    sqlx::query(
+        "UPDATE notes SET content = $1, locked_ciphertext = NULL, locked_nonce = NULL,
+         locked_salt = NULL, locked_kdf = NULL, locked_cipher = NULL WHERE id = $2",
+    )
+    .bind(content.as_str())
+    .bind(id)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    commands::reindex(&mut tx, id, &content).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    keys.0.lock().unwrap().remove(&id);
+    Ok(())
+}
@@ -0,0 +1,582 @@
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use std::path::Path;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+// Append only. Never edit a migration that has shipped: its checksum is
+// recorded in `schema_version` and a mismatch stops the app from starting.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_notes",
+        sql: "CREATE TABLE IF NOT EXISTS notes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            title TEXT NOT NULL,
+            content TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            pb_id TEXT,
+            local_uuid TEXT UNIQUE
+        );
+
+        CREATE TRIGGER IF NOT EXISTS update_note_timestamp
+        AFTER UPDATE ON notes
+        BEGIN
+            UPDATE notes SET updated_at = CURRENT_TIMESTAMP WHERE id = old.id;
+        END;",
+    },
+    Migration {
+        version: 2,
+        name: "notes_fts",
+        sql: "CREATE VIRTUAL TABLE notes_fts USING fts5(
+            title,
+            content,
+            content = 'notes',
+            content_rowid = 'id',
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER notes_fts_insert AFTER INSERT ON notes BEGIN
+            INSERT INTO notes_fts (rowid, title, content) VALUES (new.id, new.title, new.content);
+        END;
+
+        CREATE TRIGGER notes_fts_delete AFTER DELETE ON notes BEGIN
+            INSERT INTO notes_fts (notes_fts, rowid, title, content) VALUES ('delete', old.id, old.title, old.content);
+        END;
+
+        CREATE TRIGGER notes_fts_update AFTER UPDATE OF title, content ON notes BEGIN
+            INSERT INTO notes_fts (notes_fts, rowid, title, content) VALUES ('delete', old.id, old.title, old.content);
+            INSERT INTO notes_fts (rowid, title, content) VALUES (new.id, new.title, new.content);
+        END;
+
+        INSERT INTO notes_fts (notes_fts) VALUES ('rebuild');",
+    },
+    Migration {
+        version: 3,
+        name: "note_revisions",
+        sql: "CREATE TABLE note_revisions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+            title TEXT NOT NULL,
+            content TEXT,
+            source TEXT NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE INDEX idx_note_revisions_note ON note_revisions (note_id, id);",
+    },
+    Migration {
+        version: 4,
+        name: "soft_delete",
+        sql: "ALTER TABLE notes ADD COLUMN deleted_at DATETIME;
+
+        CREATE INDEX idx_notes_deleted_at ON notes (deleted_at);
+
+        CREATE TABLE app_settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );",
+    },
+    Migration {
+        version: 5,
+        name: "folders",
+        sql: "CREATE TABLE folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            parent_id INTEGER REFERENCES folders(id) ON DELETE CASCADE,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE UNIQUE INDEX idx_folders_sibling_name ON folders (COALESCE(parent_id, 0), name COLLATE NOCASE);
+
+        ALTER TABLE notes ADD COLUMN folder_id INTEGER REFERENCES folders(id) ON DELETE SET NULL;
+
+        CREATE INDEX idx_notes_folder ON notes (folder_id);",
+    },
+    Migration {
+        version: 6,
+        name: "tags",
+        sql: "CREATE TABLE tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE
+        );
+
+        CREATE TABLE note_tags (
+            note_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+            tag_id INTEGER NOT NULL REFERENCES tags(id) ON DELETE CASCADE,
+            source TEXT NOT NULL CHECK (source IN ('inline', 'manual')),
+            PRIMARY KEY (note_id, tag_id, source)
+        );
+
+        CREATE INDEX idx_note_tags_tag ON note_tags (tag_id);",
+    },
+    Migration {
+        version: 7,
+        name: "note_links",
+        sql: "CREATE TABLE note_links (
+            source_uuid TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            target_title TEXT NOT NULL,
+            alias TEXT,
+            PRIMARY KEY (source_uuid, position)
+        );
+
+        CREATE INDEX idx_note_links_target ON note_links (lower(target_title));
+        CREATE INDEX idx_notes_title_lower ON notes (lower(title));
+
+        -- local_uuid is only UNIQUE on databases created after it was added,
+        -- so clean up by trigger rather than a foreign key
+        CREATE TRIGGER note_links_cleanup AFTER DELETE ON notes BEGIN
+            DELETE FROM note_links WHERE source_uuid = old.local_uuid;
+        END;",
+    },
+    Migration {
+        version: 8,
+        name: "attachments",
+        sql: "CREATE TABLE attachments (
+            hash TEXT PRIMARY KEY,
+            size INTEGER NOT NULL,
+            mime TEXT NOT NULL,
+            original_name TEXT,
+            ref_count INTEGER NOT NULL DEFAULT 0,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE note_attachments (
+            note_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+            hash TEXT NOT NULL REFERENCES attachments(hash),
+            PRIMARY KEY (note_id, hash)
+        );
+
+        CREATE TRIGGER note_attachments_ref AFTER INSERT ON note_attachments BEGIN
+            UPDATE attachments SET ref_count = ref_count + 1 WHERE hash = new.hash;
+        END;
+
+        CREATE TRIGGER note_attachments_unref AFTER DELETE ON note_attachments BEGIN
+            UPDATE attachments SET ref_count = ref_count - 1 WHERE hash = old.hash;
+        END;",
+    },
+    Migration {
+        version: 9,
+        name: "locked_notes",
+        sql: "ALTER TABLE notes ADD COLUMN locked_ciphertext BLOB;
+        ALTER TABLE notes ADD COLUMN locked_nonce BLOB;
+        ALTER TABLE notes ADD COLUMN locked_salt BLOB;
+        ALTER TABLE notes ADD COLUMN locked_kdf TEXT;
+        ALTER TABLE notes ADD COLUMN locked_cipher TEXT;",
+    },
+    Migration {
+        version: 10,
+        name: "import_sources",
+        sql: "CREATE TABLE IF NOT EXISTS import_sources (
+            path TEXT PRIMARY KEY,
+            note_uuid TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            imported_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );",
+    },
+    Migration {
+        version: 11,
+        name: "mirror_files",
+        sql: "CREATE TABLE IF NOT EXISTS mirror_files (
+            note_uuid TEXT PRIMARY KEY,
+            path TEXT NOT NULL,
+            file_hash TEXT NOT NULL,
+            note_hash TEXT NOT NULL
+        );",
+    },
+    Migration {
+        version: 12,
+        name: "sync_objects",
+        sql: "CREATE TABLE IF NOT EXISTS sync_objects (
+            provider TEXT NOT NULL,
+            name TEXT NOT NULL,
+            etag TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            PRIMARY KEY (provider, name)
+        );",
+    },
+    Migration {
+        version: 13,
+        name: "note_conflicts",
+        sql: "CREATE TABLE IF NOT EXISTS note_sync_base (
+            note_id INTEGER PRIMARY KEY REFERENCES notes(id) ON DELETE CASCADE,
+            title TEXT NOT NULL,
+            content TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS note_conflicts (
+            note_id INTEGER PRIMARY KEY REFERENCES notes(id) ON DELETE CASCADE,
+            remote_title TEXT NOT NULL,
+            remote_content TEXT NOT NULL,
+            remote_updated_at TEXT,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );",
+    },
+    Migration {
+        version: 14,
+        name: "note_changes",
+        // No foreign key: a delete has to outlive the note being purged
+        sql: "CREATE TABLE IF NOT EXISTS note_changes (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL,
+            local_uuid TEXT,
+            pb_id TEXT,
+            op TEXT NOT NULL,
+            changed_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );",
+    },
+    Migration {
+        version: 15,
+        name: "hybrid_clock",
+        // The old trigger also overwrote the updated_at of imported notes.
+        // Local edits now tick the clock; writes that set hlc themselves
+        // (imports) keep theirs.
+        sql: "DROP TRIGGER IF EXISTS update_note_timestamp;
+
+        CREATE TABLE IF NOT EXISTS hlc_clock (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            wall INTEGER NOT NULL,
+            counter INTEGER NOT NULL,
+            device TEXT NOT NULL
+        );
+
+        INSERT INTO hlc_clock (id, wall, counter, device) VALUES (1, 0, 0, lower(hex(randomblob(8))));
+
+        ALTER TABLE notes ADD COLUMN hlc TEXT;
+
+        UPDATE notes SET updated_at = COALESCE(
+            strftime('%Y-%m-%d %H:%M:%f', updated_at),
+            strftime('%Y-%m-%d %H:%M:%f', created_at),
+            strftime('%Y-%m-%d %H:%M:%f', 'now')
+        );
+
+        UPDATE notes SET hlc = printf('%013d:%05d:%s',
+            CAST(ROUND((julianday(updated_at) - 2440587.5) * 86400000) AS INTEGER), 0,
+            (SELECT device FROM hlc_clock));
+
+        UPDATE hlc_clock SET wall = (SELECT COALESCE(MAX(CAST(substr(hlc, 1, 13) AS INTEGER)), 0) FROM notes);
+
+        CREATE TRIGGER note_clock_insert AFTER INSERT ON notes
+        WHEN new.hlc IS NULL
+        BEGIN
+            UPDATE hlc_clock SET
+                counter = CASE WHEN wall >= CAST(ROUND((julianday('now') - 2440587.5) * 86400000) AS INTEGER) THEN counter + 1 ELSE 0 END,
+                wall = MAX(wall, CAST(ROUND((julianday('now') - 2440587.5) * 86400000) AS INTEGER));
+            UPDATE notes SET
+                hlc = (SELECT printf('%013d:%05d:%s', wall, counter, device) FROM hlc_clock),
+                updated_at = (SELECT strftime('%Y-%m-%d %H:%M:%f', wall / 1000.0, 'unixepoch') FROM hlc_clock)
+            WHERE id = new.id;
+        END;
+
+        CREATE TRIGGER note_clock_update AFTER UPDATE OF title, content, folder_id, deleted_at, locked_ciphertext ON notes
+        WHEN new.hlc IS old.hlc
+        BEGIN
+            UPDATE hlc_clock SET
+                counter = CASE WHEN wall >= CAST(ROUND((julianday('now') - 2440587.5) * 86400000) AS INTEGER) THEN counter + 1 ELSE 0 END,
+                wall = MAX(wall, CAST(ROUND((julianday('now') - 2440587.5) * 86400000) AS INTEGER));
+            UPDATE notes SET
+                hlc = (SELECT printf('%013d:%05d:%s', wall, counter, device) FROM hlc_clock),
+                updated_at = (SELECT strftime('%Y-%m-%d %H:%M:%f', wall / 1000.0, 'unixepoch') FROM hlc_clock)
+            WHERE id = new.id;
+        END;",
+    },
+    Migration {
+        version: 16,
+        name: "note_yjs",
+        sql: "CREATE TABLE IF NOT EXISTS note_snapshots (
+            note_id INTEGER PRIMARY KEY REFERENCES notes(id) ON DELETE CASCADE,
+            state BLOB NOT NULL,
+            updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE TABLE IF NOT EXISTS note_updates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            note_id INTEGER NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+            data BLOB NOT NULL,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE INDEX idx_note_updates_note ON note_updates (note_id, id);",
+    },
+    Migration {
+        version: 17,
+        name: "note_tombstones",
+        // Purging drops the note but keeps its identity, so sync can still
+        // delete the remote copies
+        sql: "CREATE TABLE IF NOT EXISTS note_tombstones (
+            note_id INTEGER PRIMARY KEY,
+            local_uuid TEXT,
+            pb_id TEXT,
+            deleted_at DATETIME NOT NULL,
+            purged_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE INDEX idx_note_tombstones_uuid ON note_tombstones (local_uuid);
+        CREATE INDEX idx_note_tombstones_pb_id ON note_tombstones (pb_id);
+
+        CREATE TRIGGER note_tombstone AFTER DELETE ON notes BEGIN
+            INSERT OR REPLACE INTO note_tombstones (note_id, local_uuid, pb_id, deleted_at)
+            VALUES (old.id, old.local_uuid, old.pb_id, COALESCE(old.deleted_at, CURRENT_TIMESTAMP));
+        END;",
+    },
+    Migration {
+        version: 18,
+        name: "note_changes_triggers",
+        // Every writer of notes (commands, importers, sync) lands in the log
+        sql: "CREATE TRIGGER note_changes_insert AFTER INSERT ON notes BEGIN
+            INSERT INTO note_changes (note_id, local_uuid, pb_id, op)
+            VALUES (new.id, new.local_uuid, new.pb_id, 'create');
+        END;
+
+        CREATE TRIGGER note_changes_update AFTER UPDATE OF title, content, folder_id, deleted_at, locked_ciphertext ON notes
+        WHEN old.title IS NOT new.title OR old.content IS NOT new.content OR old.folder_id IS NOT new.folder_id
+            OR old.deleted_at IS NOT new.deleted_at OR old.locked_ciphertext IS NOT new.locked_ciphertext
+        BEGIN
+            INSERT INTO note_changes (note_id, local_uuid, pb_id, op)
+            VALUES (new.id, new.local_uuid, new.pb_id,
+                CASE WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL THEN 'delete' ELSE 'update' END);
+        END;",
+    },
+    Migration {
+        version: 19,
+        name: "locked_sync_copies",
+        // Locking used to leave the synced plaintext behind
+        sql: "DELETE FROM note_sync_base WHERE note_id IN (SELECT id FROM notes WHERE locked_ciphertext IS NOT NULL);
+        DELETE FROM note_conflicts WHERE note_id IN (SELECT id FROM notes WHERE locked_ciphertext IS NOT NULL);",
+    },
+    Migration {
+        version: 20,
+        name: "note_yjs_pending",
+        // Content a sync wrote that the note's Yjs document hasn't caught up with
+        sql: "CREATE TABLE IF NOT EXISTS note_yjs_pending (
+            note_id INTEGER PRIMARY KEY REFERENCES notes(id) ON DELETE CASCADE,
+            content TEXT NOT NULL
+        );",
+    },
+    Migration {
+        version: 21,
+        name: "note_changes_compaction",
+        // One entry per note: a later change replaces the pending one, which
+        // an upload of the note's current state covers anyway. A create not
+        // yet uploaded stays a create. While a row is in note_changes_paused
+        // (a sync import, in its own transaction) nothing is logged.
+        sql: "CREATE TABLE IF NOT EXISTS note_changes_paused (id INTEGER PRIMARY KEY CHECK (id = 1));
+
+        UPDATE note_changes SET op = 'create'
+        WHERE op = 'update' AND EXISTS (SELECT 1 FROM note_changes c WHERE c.note_id = note_changes.note_id AND c.op = 'create');
+        DELETE FROM note_changes WHERE seq NOT IN (SELECT MAX(seq) FROM note_changes GROUP BY note_id);
+
+        DROP TRIGGER IF EXISTS note_changes_insert;
+        DROP TRIGGER IF EXISTS note_changes_update;
+
+        CREATE TRIGGER note_changes_insert AFTER INSERT ON notes
+        WHEN NOT EXISTS (SELECT 1 FROM note_changes_paused)
+        BEGIN
+            INSERT INTO note_changes (note_id, local_uuid, pb_id, op)
+            VALUES (new.id, new.local_uuid, new.pb_id, 'create');
+            DELETE FROM note_changes WHERE note_id = new.id AND seq < (SELECT MAX(seq) FROM note_changes WHERE note_id = new.id);
+        END;
+
+        CREATE TRIGGER note_changes_update AFTER UPDATE OF title, content, folder_id, deleted_at, locked_ciphertext ON notes
+        WHEN (old.title IS NOT new.title OR old.content IS NOT new.content OR old.folder_id IS NOT new.folder_id
+            OR old.deleted_at IS NOT new.deleted_at OR old.locked_ciphertext IS NOT new.locked_ciphertext)
+            AND NOT EXISTS (SELECT 1 FROM note_changes_paused)
+        BEGIN
+            INSERT INTO note_changes (note_id, local_uuid, pb_id, op)
+            VALUES (new.id, new.local_uuid, new.pb_id,
+                CASE WHEN old.deleted_at IS NULL AND new.deleted_at IS NOT NULL THEN 'delete'
+                    WHEN EXISTS (SELECT 1 FROM note_changes WHERE note_id = new.id AND op = 'create') THEN 'create'
+                    ELSE 'update' END);
+            DELETE FROM note_changes WHERE note_id = new.id AND seq < (SELECT MAX(seq) FROM note_changes WHERE note_id = new.id);
+        END;",
+    },
+];
+
+fn checksum(sql: &str) -> String {
+    Sha256::digest(sql.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Bring the schema up to date. Each pending migration runs in its own
+/// transaction; a copy of the database is written next to it first.
+pub async fn run(pool: &SqlitePool, db_path: &Path) -> Result<(), String> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        )",
+    )
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to create schema_version table: {}", e))?;
+
+    let applied: Vec<(i64, String, String)> =
+        sqlx::query_as("SELECT version, name, checksum FROM schema_version ORDER BY version")
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    let current = applied.last().map(|(v, _, _)| *v).unwrap_or(0);
+    let latest = MIGRATIONS.last().map(|m| m.version).unwrap_or(0);
+
+    if current > latest {
+        return Err(format!(
+            "Database schema is at version {} but this build of Onyx only knows up to {}. Update the app.",
+            current, latest
+        ));
+    }
+
+    for (version, name, sum) in &applied {
+        match MIGRATIONS.iter().find(|m| m.version == *version) {
+            Some(m) if checksum(m.sql) != *sum => {
+                return Err(format!(
+                    "Migration {} ({}) was changed after it was applied",
+                    version, name
+                ))
+            }
+            Some(_) => {}
+            None => return Err(format!("Unknown migration {} ({}) in schema_version", version, name)),
+        }
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    if has_user_tables(pool).await? {
+        backup(pool, db_path, current).await?;
+    }
+
+    for migration in pending {
+        println!(
+            "Applying migration {}: {}...",
+            migration.version, migration.name
+        );
+
+        let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+        apply(&mut tx, migration).await.map_err(|e| {
+            format!(
+                "Migration {} ({}) failed, database left at version {}: {}",
+                migration.version, migration.name, migration.version - 1, e
+            )
+        })?;
+        tx.commit().await.map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+async fn apply(tx: &mut Transaction<'_, Sqlite>, migration: &Migration) -> Result<(), sqlx::Error> {
+    // Databases from before versioned migrations have `notes` with or without
+    // the sync columns; fill those in so the baseline can be recorded as applied.
+    if migration.version == 1 {
+        adopt_legacy_notes(tx).await?;
+    }
+
+    sqlx::raw_sql(migration.sql).execute(&mut **tx).await?;
+
+    sqlx::query("INSERT INTO schema_version (version, name, checksum) VALUES ($1, $2, $3)")
+        .bind(migration.version)
+        .bind(migration.name)
+        .bind(checksum(migration.sql))
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn adopt_legacy_notes(tx: &mut Transaction<'_, Sqlite>) -> Result<(), sqlx::Error> {
+    let columns: Vec<(String,)> = sqlx::query_as("SELECT name FROM pragma_table_info('notes')")
+        .fetch_all(&mut **tx)
+        .await?;
+
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    if !columns.iter().any(|c| c.0 == "pb_id") {
+        println!("Legacy schema: adding pb_id column...");
+        sqlx::query("ALTER TABLE notes ADD COLUMN pb_id TEXT")
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    if !columns.iter().any(|c| c.0 == "local_uuid") {
+        println!("Legacy schema: adding local_uuid column...");
+        sqlx::query("ALTER TABLE notes ADD COLUMN local_uuid TEXT")
+            .execute(&mut **tx)
+            .await?;
+        // Generate UUIDs for existing notes
+        sqlx::query("UPDATE notes SET local_uuid = (lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' || substr(lower(hex(randomblob(2))),2,3) || '-' || substr('89ab',abs(random()) % 4 + 1, 1) || substr(lower(hex(randomblob(2))),2,3) || '-' || lower(hex(randomblob(6)))) WHERE local_uuid IS NULL")
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Delete the pre-migration backups next to the database, e.g. after it was
+/// encrypted, since they are copies under the old key (or none).
+pub fn remove_backups(db_path: &Path) -> Result<(), String> {
+    let file_name = db_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("onyx.db");
+    let prefix = format!("{}.v", file_name);
+    let Some(dir) = db_path.parent() else {
+        return Ok(());
+    };
+
+    for entry in std::fs::read_dir(dir).map_err(|e| e.to_string())?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with(&prefix) && name.ends_with(".bak") {
+            println!("Removing old backup {}", name);
+            std::fs::remove_file(entry.path()).map_err(|e| format!("Failed to remove {}: {}", name, e))?;
+        }
+    }
+    Ok(())
+}
+
+async fn has_user_tables(pool: &SqlitePool) -> Result<bool, String> {
+    let count: (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name != 'schema_version'",
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(count.0 > 0)
+}
+
+// Consistent snapshot of the live database, e.g. `onyx.db.v3.bak`
+async fn backup(pool: &SqlitePool, db_path: &Path, version: i64) -> Result<(), String> {
+    let file_name = db_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("onyx.db");
+    let target = db_path.with_file_name(format!("{}.v{}.bak", file_name, version));
+
+    if target.exists() {
+        std::fs::remove_file(&target).map_err(|e| format!("Failed to replace old backup: {}", e))?;
+    }
+
+    println!("Backing up database to {}...", target.display());
+    sqlx::query("VACUUM INTO $1")
+        .bind(target.to_string_lossy().to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Pre-migration backup failed: {}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,632 @@
+use crate::{commands, revisions, settings, tags, vault};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::{Sqlite, SqliteConnection, SqlitePool};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, State};
+use tokio::sync::{mpsc, oneshot};
+use walkdir::WalkDir;
+
+const MIRROR_DIR_KEY: &str = "mirror_dir";
+// Editors save in bursts (temp file, rename, touch); wait for the folder to settle
+const DEBOUNCE: Duration = Duration::from_millis(750);
+// How often the database is checked for changes made by the rest of the app
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The running mirror, if mirror mode is on.
+#[derive(Default)]
+pub struct MirrorState(Mutex<Option<Mirror>>);
+
+pub struct Mirror {
+    dir: PathBuf,
+    stop: oneshot::Sender<()>,
+    // Dropping the watcher stops file events
+    _watcher: RecommendedWatcher,
+}
+
+#[derive(Serialize)]
+pub struct MirrorStatus {
+    pub dir: Option<String>,
+    pub running: bool,
+}
+
+#[derive(Serialize, Default, Clone)]
+pub struct MirrorReport {
+    /// Files written from the database
+    pub written: usize,
+    /// Notes created or updated from files
+    pub imported: usize,
+    /// Files removed, or notes moved to the trash, to follow a delete on the other side
+    pub deleted: usize,
+    /// Notes created from files that changed on both sides
+    pub conflicts: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl MirrorReport {
+    fn is_empty(&self) -> bool {
+        self.written == 0 && self.imported == 0 && self.deleted == 0 && self.conflicts.is_empty() && self.errors.is_empty()
+    }
+}
+
+/// What the two sides agreed on after the last sync of a note.
+struct Synced {
+    path: String,
+    file_hash: String,
+    note_hash: String,
+}
+
+/// A Markdown file in a synced folder.
+pub(crate) struct DiskFile {
+    /// Relative to the folder, with `/` separators
+    pub(crate) path: String,
+    pub(crate) hash: String,
+    /// From the front-matter, else the file name
+    pub(crate) title: String,
+    pub(crate) body: String,
+    pub(crate) tags: Vec<String>,
+    pub(crate) created: Option<String>,
+}
+
+// --- Lifecycle ---
+
+/// Start mirroring if it was turned on, once the database is open.
+pub async fn start_if_enabled(app_handle: AppHandle) {
+    let Some(pool) = app_handle.try_state::<SqlitePool>().map(|p| p.inner().clone()) else {
+        return;
+    };
+    match settings::get(&pool, MIRROR_DIR_KEY).await {
+        Ok(Some(dir)) => {
+            if let Err(e) = start(&app_handle, &pool, PathBuf::from(dir)).await {
+                eprintln!("Mirror failed to start: {}", e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Mirror failed to start: {}", e),
+    }
+}
+
+/// Stop the mirror. Its database connection goes back to the pool once the
+/// current pass finishes.
+pub fn stop(app_handle: &AppHandle) {
+    if let Some(state) = app_handle.try_state::<MirrorState>() {
+        if let Some(mirror) = state.0.lock().unwrap().take() {
+            println!("Mirror: stopping for {}", mirror.dir.display());
+            let _ = mirror.stop.send(());
+        }
+    }
+}
+
+async fn start(app_handle: &AppHandle, pool: &SqlitePool, dir: PathBuf) -> Result<(), String> {
+    stop(app_handle);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let (events_tx, events_rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.is_ok() {
+            let _ = events_tx.send(());
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    watcher
+        .watch(&dir, RecursiveMode::Recursive)
+        .map_err(|e| format!("Failed to watch {}: {}", dir.display(), e))?;
+
+    // Changes this connection makes don't bump its own data_version, so the
+    // mirror only wakes up for changes made elsewhere
+    let conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    let (stop_tx, stop_rx) = oneshot::channel();
+    tauri::async_runtime::spawn(run(app_handle.clone(), conn, dir.clone(), events_rx, stop_rx));
+
+    println!("Mirror: watching {}", dir.display());
+    let state = app_handle.state::<MirrorState>();
+    *state.0.lock().unwrap() = Some(Mirror {
+        dir,
+        stop: stop_tx,
+        _watcher: watcher,
+    });
+    Ok(())
+}
+
+async fn run(
+    app_handle: AppHandle,
+    mut conn: PoolConnection<Sqlite>,
+    dir: PathBuf,
+    mut events: mpsc::UnboundedReceiver<()>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let mut last_version = None;
+    let mut last_event: Option<Instant> = None;
+    let mut tick = tokio::time::interval(POLL_INTERVAL.min(DEBOUNCE));
+
+    loop {
+        tokio::select! {
+            _ = &mut stop => break,
+            Some(()) = events.recv() => last_event = Some(Instant::now()),
+            _ = tick.tick() => {
+                let version: Option<(i64,)> = sqlx::query_as("PRAGMA data_version")
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .unwrap_or(None);
+                let db_changed = version.is_some() && version != last_version;
+                let files_settled = last_event.is_some_and(|at| at.elapsed() >= DEBOUNCE);
+                if !db_changed && !files_settled {
+                    continue;
+                }
+                last_version = version;
+                last_event = None;
+
+                let report = match reconcile(&mut conn, &dir).await {
+                    Ok(report) => report,
+                    Err(e) => MirrorReport { errors: vec![e], ..Default::default() },
+                };
+                if !report.is_empty() {
+                    println!(
+                        "Mirror: {} written, {} imported, {} deleted, {} conflicts, {} errors",
+                        report.written,
+                        report.imported,
+                        report.deleted,
+                        report.conflicts.len(),
+                        report.errors.len()
+                    );
+                    let _ = app_handle.emit("mirror-synced", report);
+                }
+            }
+        }
+    }
+}
+
+// --- Reconcile ---
+
+/// The Markdown files of a synced folder.
+pub(crate) struct Scan {
+    /// By front-matter uuid
+    pub(crate) by_uuid: HashMap<String, DiskFile>,
+    /// Without a uuid, or a second file with the same one
+    pub(crate) unknown: Vec<DiskFile>,
+    /// Paths of files that couldn't be read, most likely still being written.
+    /// Their notes are left alone until they can.
+    pub(crate) unreadable: HashSet<String>,
+}
+
+/// List the Markdown files under `root`. Fails if any part of the folder
+/// can't be listed, since a file missing from the result counts as deleted.
+pub(crate) fn scan(root: &Path) -> Result<Scan, String> {
+    if !root.is_dir() {
+        return Err(format!("Folder not found: {}", root.display()));
+    }
+    let mut scan = Scan {
+        by_uuid: HashMap::new(),
+        unknown: Vec::new(),
+        unreadable: HashSet::new(),
+    };
+
+    let walker = WalkDir::new(root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker {
+        let entry = entry.map_err(|e| format!("Failed to list {}: {}", root.display(), e))?;
+        let path = entry.path();
+        if !entry.file_type().is_file() || !vault::is_markdown(path) {
+            continue;
+        }
+        // Unreadable or not UTF-8: most likely still being written
+        let Ok(raw) = std::fs::read_to_string(path) else {
+            scan.unreadable.insert(vault::relative(root, path));
+            continue;
+        };
+
+        let (front_matter, body) = vault::split_front_matter(&raw);
+        let field = |key: &str| front_matter.as_ref().and_then(|f| f.get(key)).and_then(|v| v.as_str());
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let uuid = field("local_uuid").map(str::to_string);
+        let file = DiskFile {
+            path: vault::relative(root, path),
+            hash: vault::sha256_hex(raw.as_bytes()),
+            title: field("title").map(str::trim).filter(|t| !t.is_empty()).unwrap_or(&stem).to_string(),
+            body: body.to_string(),
+            tags: front_matter.as_ref().map(vault::front_matter_tags).unwrap_or_default(),
+            created: field("created").and_then(vault::sqlite_timestamp),
+        };
+
+        // A copied file carries the original's uuid; the copy becomes a new note
+        match uuid {
+            Some(uuid) if !scan.by_uuid.contains_key(&uuid) => {
+                scan.by_uuid.insert(uuid, file);
+            }
+            _ => scan.unknown.push(file),
+        }
+    }
+    Ok(scan)
+}
+
+pub(crate) fn parent_dir(path: &str) -> &str {
+    path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or_default()
+}
+
+/// One pass of two-way sync between the notes table and the mirror folder.
+/// Each side is compared with what both agreed on last time (`mirror_files`)
+/// to tell which one changed.
+async fn reconcile(conn: &mut SqliteConnection, root: &Path) -> Result<MirrorReport, String> {
+    let mut report = MirrorReport::default();
+
+    vault::assign_uuids(conn).await?;
+
+    let synced: Vec<(String, String, String, String)> =
+        sqlx::query_as("SELECT note_uuid, path, file_hash, note_hash FROM mirror_files")
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    let synced: HashMap<String, Synced> = synced
+        .into_iter()
+        .map(|(uuid, path, file_hash, note_hash)| (uuid, Synced { path, file_hash, note_hash }))
+        .collect();
+
+    // Locked notes are left out, so their plaintext file is removed like a deleted note's
+    let notes: HashMap<String, vault::ExportRow> = vault::load_notes(conn)
+        .await?
+        .into_iter()
+        .filter(|n| !n.locked)
+        .filter_map(|n| n.local_uuid.clone().map(|uuid| (uuid, n)))
+        .collect();
+    let tags = vault::load_tags(conn).await?;
+    let folders = vault::folder_paths(conn).await?;
+
+    let Scan { by_uuid: mut files, unknown, unreadable } = scan(root)?;
+    // An empty folder is more likely unmounted than emptied on purpose
+    if files.is_empty() && unknown.is_empty() && unreadable.is_empty() && !synced.is_empty() {
+        return Err("The mirror folder is empty; not deleting its notes".to_string());
+    }
+    let mut taken: HashSet<String> = files
+        .values()
+        .chain(unknown.iter())
+        .map(|f| f.path.to_lowercase())
+        .chain(unreadable.iter().map(|p| p.to_lowercase()))
+        .collect();
+
+    let render = |note: &vault::ExportRow| -> Result<String, String> {
+        let folder = note.folder_id.and_then(|id| folders.get(&id)).map(|(name, _)| name.as_str());
+        vault::render(note, folder, tags.get(&note.id).map(Vec::as_slice).unwrap_or_default())
+    };
+
+    // Notes whose file has to be (re)written at the end: (note id, path to keep)
+    let mut to_write: Vec<(i64, Option<String>)> = Vec::new();
+    let mut folder_cache = HashMap::from([(String::new(), None)]);
+
+    let uuids: BTreeSet<String> = synced.keys().chain(notes.keys()).chain(files.keys()).cloned().collect();
+    for uuid in uuids {
+        let note = notes.get(&uuid);
+        let file = files.remove(&uuid);
+        let base = synced.get(&uuid);
+        // Not listed because it couldn't be read, not because it's gone
+        if file.is_none() && base.is_some_and(|b| unreadable.contains(&b.path)) {
+            continue;
+        }
+
+        let result = match (note, file, base) {
+            // New in the app
+            (Some(note), None, None) => {
+                to_write.push((note.id, None));
+                Ok(())
+            }
+            // Deleted on disk: follow it, unless the note was edited since
+            (Some(note), None, Some(base)) => {
+                if vault::sha256_hex(render(note)?.as_bytes()) == base.note_hash {
+                    report.deleted += 1;
+                    trash_note(conn, note.id, &uuid).await
+                } else {
+                    to_write.push((note.id, None));
+                    Ok(())
+                }
+            }
+            // A file for a note the mirror hasn't seen, e.g. from an export
+            (Some(note), Some(file), None) => {
+                if file.title == note.title && Some(file.body.as_str()) == note.content.as_deref() {
+                    let note_hash = vault::sha256_hex(render(note)?.as_bytes());
+                    record(conn, &uuid, &file.path, &file.hash, &note_hash).await
+                } else {
+                    conflict(conn, note, &file, &mut folder_cache, &mut report, &mut to_write).await
+                }
+            }
+            (Some(note), Some(file), Some(base)) => {
+                let rendered = render(note)?;
+                let note_changed = vault::sha256_hex(rendered.as_bytes()) != base.note_hash;
+                let file_changed = file.hash != base.file_hash || parent_dir(&file.path) != parent_dir(&base.path);
+
+                match (note_changed, file_changed) {
+                    (false, false) if file.path != base.path => {
+                        // Renamed in place; the title comes from the front-matter
+                        record(conn, &uuid, &file.path, &base.file_hash, &base.note_hash).await
+                    }
+                    (false, false) => Ok(()),
+                    (true, false) => {
+                        to_write.push((note.id, Some(file.path.clone())));
+                        Ok(())
+                    }
+                    (false, true) => {
+                        report.imported += 1;
+                        apply_file(conn, note.id, &file, &mut folder_cache).await?;
+                        let note_hash = note_hash(conn, note.id).await?;
+                        record(conn, &uuid, &file.path, &file.hash, &note_hash).await
+                    }
+                    (true, true) => {
+                        if file.title == note.title && Some(file.body.as_str()) == note.content.as_deref() {
+                            // The same edit reached both sides
+                            let note_hash = vault::sha256_hex(rendered.as_bytes());
+                            record(conn, &uuid, &file.path, &file.hash, &note_hash).await
+                        } else {
+                            conflict(conn, note, &file, &mut folder_cache, &mut report, &mut to_write).await
+                        }
+                    }
+                }
+            }
+            // Note deleted, locked or purged in the app
+            (None, Some(file), Some(base)) => {
+                forget(conn, &uuid).await?;
+                if file.hash == base.file_hash {
+                    report.deleted += 1;
+                    let path = vault::to_disk(root, &file.path);
+                    let _ = tokio::fs::remove_file(&path).await;
+                    vault::remove_empty_dirs(root, &path).await;
+                    Ok(())
+                } else {
+                    // Edited after the delete: keep the edit as a new note
+                    report.imported += 1;
+                    let id = create_from_file(conn, &file, &mut folder_cache).await?;
+                    to_write.push((id, Some(file.path.clone())));
+                    Ok(())
+                }
+            }
+            // A file with a uuid the app doesn't have live
+            (None, Some(file), None) => {
+                report.imported += 1;
+                let id = create_from_file(conn, &file, &mut folder_cache).await?;
+                to_write.push((id, Some(file.path.clone())));
+                Ok(())
+            }
+            (None, None, Some(_)) => forget(conn, &uuid).await,
+            (None, None, None) => Ok(()),
+        };
+        if let Err(e) = result {
+            report.errors.push(format!("{}: {}", uuid, e));
+        }
+    }
+
+    // Files without a uuid are new notes; they get one written back
+    for file in unknown {
+        report.imported += 1;
+        match create_from_file(conn, &file, &mut folder_cache).await {
+            Ok(id) => to_write.push((id, Some(file.path.clone()))),
+            Err(e) => report.errors.push(format!("{}: {}", file.path, e)),
+        }
+    }
+
+    if to_write.is_empty() {
+        return Ok(report);
+    }
+
+    // Reload, so notes created or moved above render with their final state
+    let notes: HashMap<i64, vault::ExportRow> = vault::load_notes(conn)
+        .await?
+        .into_iter()
+        .map(|n| (n.id, n))
+        .collect();
+    let tags = vault::load_tags(conn).await?;
+    let folders = vault::folder_paths(conn).await?;
+
+    for (id, keep) in to_write {
+        let Some(note) = notes.get(&id) else {
+            continue;
+        };
+        let folder = note.folder_id.and_then(|id| folders.get(&id));
+        let dir = folder.map(|(_, dir)| dir.clone()).unwrap_or_default();
+        let stem = vault::safe_file_name(&note.title);
+
+        let previous = synced.get(note.local_uuid.as_deref().unwrap_or_default()).map(|b| b.path.clone());
+        let path = match keep.or(previous.clone()) {
+            Some(path) if vault::fits(&path, &dir, &stem) => path,
+            _ => vault::unique_path(&dir, &stem, ".md", &mut taken),
+        };
+
+        let rendered = vault::render(note, folder.map(|(name, _)| name.as_str()), tags.get(&id).map(Vec::as_slice).unwrap_or_default())?;
+        let hash = vault::sha256_hex(rendered.as_bytes());
+        if let Err(e) = vault::write_file(&vault::to_disk(root, &path), rendered.as_bytes()).await {
+            report.errors.push(format!("{}: {}", path, e));
+            continue;
+        }
+        report.written += 1;
+
+        // Renamed or moved in the app: the old file goes
+        if let Some(old) = previous.filter(|old| !old.eq_ignore_ascii_case(&path)) {
+            let old = vault::to_disk(root, &old);
+            let _ = tokio::fs::remove_file(&old).await;
+            vault::remove_empty_dirs(root, &old).await;
+        }
+
+        let uuid = note.local_uuid.clone().unwrap_or_default();
+        record(conn, &uuid, &path, &hash, &hash).await?;
+    }
+
+    Ok(report)
+}
+
+async fn record(conn: &mut SqliteConnection, uuid: &str, path: &str, file_hash: &str, note_hash: &str) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO mirror_files (note_uuid, path, file_hash, note_hash) VALUES ($1, $2, $3, $4)
+         ON CONFLICT(note_uuid) DO UPDATE SET path = excluded.path, file_hash = excluded.file_hash,
+         note_hash = excluded.note_hash",
+    )
+    .bind(uuid)
+    .bind(path)
+    .bind(file_hash)
+    .bind(note_hash)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn forget(conn: &mut SqliteConnection, uuid: &str) -> Result<(), String> {
+    sqlx::query("DELETE FROM mirror_files WHERE note_uuid = $1")
+        .bind(uuid)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+async fn trash_note(conn: &mut SqliteConnection, id: i64, uuid: &str) -> Result<(), String> {
+    sqlx::query("UPDATE notes SET deleted_at = CURRENT_TIMESTAMP WHERE id = $1 AND deleted_at IS NULL")
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    forget(conn, uuid).await
+}
+
+// Hash of the note as it would be written now
+async fn note_hash(conn: &mut SqliteConnection, id: i64) -> Result<String, String> {
+    let notes = vault::load_notes(conn).await?;
+    let note = notes.iter().find(|n| n.id == id).ok_or("Note not found")?;
+    let tags = vault::load_tags(conn).await?;
+    let folders = vault::folder_paths(conn).await?;
+    let folder = note.folder_id.and_then(|id| folders.get(&id)).map(|(name, _)| name.as_str());
+    let rendered = vault::render(note, folder, tags.get(&id).map(Vec::as_slice).unwrap_or_default())?;
+    Ok(vault::sha256_hex(rendered.as_bytes()))
+}
+
+/// Take the file's title, content, folder and front-matter tags into the note.
+async fn apply_file(
+    conn: &mut SqliteConnection,
+    id: i64,
+    file: &DiskFile,
+    folders: &mut HashMap<String, Option<i64>>,
+) -> Result<(), String> {
+    let mut tx = sqlx::Connection::begin(&mut *conn).await.map_err(|e| e.to_string())?;
+    let folder_id = vault::ensure_folders(&mut tx, parent_dir(&file.path), folders).await?;
+
+    revisions::snapshot(&mut tx, id, "mirror", true)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("UPDATE notes SET title = $1, content = $2, folder_id = $3 WHERE id = $4")
+        .bind(&file.title)
+        .bind(&file.body)
+        .bind(folder_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    commands::reindex(&mut tx, id, &file.body).await?;
+    tags::set_manual(&mut tx, id, &file.tags).await?;
+
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+async fn create_from_file(
+    conn: &mut SqliteConnection,
+    file: &DiskFile,
+    folders: &mut HashMap<String, Option<i64>>,
+) -> Result<i64, String> {
+    let mut tx = sqlx::Connection::begin(&mut *conn).await.map_err(|e| e.to_string())?;
+    let folder_id = vault::ensure_folders(&mut tx, parent_dir(&file.path), folders).await?;
+
+    let result = sqlx::query(
+        "INSERT INTO notes (title, content, folder_id, local_uuid, created_at)
+         VALUES ($1, $2, $3, $4, COALESCE($5, CURRENT_TIMESTAMP))",
+    )
+    .bind(&file.title)
+    .bind(&file.body)
+    .bind(folder_id)
+    .bind(uuid::Uuid::new_v4().to_string())
+    .bind(&file.created)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    let id = result.last_insert_rowid();
+    commands::reindex(&mut tx, id, &file.body).await?;
+    tags::set_manual(&mut tx, id, &file.tags).await?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(id)
+}
+
+/// Both sides changed: the app's version keeps the note and the file, the
+/// file's version becomes a new note next to it.
+async fn conflict(
+    conn: &mut SqliteConnection,
+    note: &vault::ExportRow,
+    file: &DiskFile,
+    folders: &mut HashMap<String, Option<i64>>,
+    report: &mut MirrorReport,
+    to_write: &mut Vec<(i64, Option<String>)>,
+) -> Result<(), String> {
+    let stamp: (String,) = sqlx::query_as("SELECT strftime('%Y-%m-%d %H%M%S', 'now')")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let copy = DiskFile {
+        path: file.path.clone(),
+        hash: file.hash.clone(),
+        title: format!("{} (conflict {})", file.title, stamp.0),
+        body: file.body.clone(),
+        tags: file.tags.clone(),
+        created: None,
+    };
+
+    let id = create_from_file(conn, &copy, folders).await?;
+    report.conflicts.push(copy.title);
+    to_write.push((id, None));
+    to_write.push((note.id, Some(file.path.clone())));
+    Ok(())
+}
+
+// --- Commands ---
+
+/// Keep `dir` in step with the notes, both ways, until `disable_mirror`.
+#[tauri::command]
+pub async fn enable_mirror(app_handle: AppHandle, pool: State<'_, SqlitePool>, dir: String) -> Result<(), String> {
+    println!("Backend: enable_mirror: {}", dir);
+    let previous = settings::get(&pool, MIRROR_DIR_KEY).await?;
+    if previous.as_deref() != Some(dir.as_str()) {
+        // What was agreed with another folder says nothing about this one
+        sqlx::query("DELETE FROM mirror_files")
+            .execute(&*pool)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    settings::set(&pool, MIRROR_DIR_KEY, &dir).await?;
+    start(&app_handle, &pool, PathBuf::from(dir)).await
+}
+
+/// Stop mirroring. The files stay where they are.
+#[tauri::command]
+pub async fn disable_mirror(app_handle: AppHandle, pool: State<'_, SqlitePool>) -> Result<(), String> {
+    println!("Backend: disable_mirror");
+    stop(&app_handle);
+    settings::remove(&pool, MIRROR_DIR_KEY).await?;
+    sqlx::query("DELETE FROM mirror_files")
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_mirror_status(
+    pool: State<'_, SqlitePool>,
+    mirror: State<'_, MirrorState>,
+) -> Result<MirrorStatus, String> {
+    let running = mirror.0.lock().unwrap().is_some();
+    Ok(MirrorStatus {
+        dir: settings::get(&pool, MIRROR_DIR_KEY).await?,
+        running,
+    })
+}
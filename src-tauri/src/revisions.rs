@@ -0,0 +1,217 @@
+use crate::{commands, locked};
+use diffy::Line;
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use tauri::State;
+
+// Edits closer together than this share one snapshot
+const SNAPSHOT_INTERVAL_SECS: i64 = 300;
+// Older revisions beyond this are dropped, per note
+const MAX_REVISIONS_PER_NOTE: i64 = 50;
+
+#[derive(Serialize, FromRow)]
+pub struct Revision {
+    pub id: i64,
+    pub note_id: i64,
+    pub title: String,
+    /// Why the snapshot was taken: `edit`, `sync`, `restore`, `rename`, `import`, `mirror` or `conflict`
+    pub source: String,
+    pub size: i64,
+    pub created_at: String,
+}
+
+#[derive(Serialize)]
+pub struct DiffLine {
+    /// `context`, `insert` or `delete`
+    pub kind: &'static str,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Serialize)]
+pub struct RevisionDiff {
+    pub old_title: String,
+    pub new_title: String,
+    pub hunks: Vec<DiffHunk>,
+}
+
+/// Save the note's current title and content as a revision before it gets
+/// overwritten. With `throttle`, nothing is saved if the last snapshot is recent.
+pub async fn snapshot(
+    conn: &mut SqliteConnection,
+    note_id: i64,
+    source: &str,
+    throttle: bool,
+) -> Result<(), sqlx::Error> {
+    let current: Option<(String, Option<String>)> =
+        sqlx::query_as("SELECT title, content FROM notes WHERE id = $1 AND locked_ciphertext IS NULL")
+            .bind(note_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+    // Missing, or locked: history would keep a plaintext copy
+    let Some((title, content)) = current else {
+        return Ok(());
+    };
+
+    let latest: Option<(String, Option<String>, bool)> = sqlx::query_as(
+        "SELECT title, content, created_at > datetime('now', $2) FROM note_revisions
+         WHERE note_id = $1 ORDER BY id DESC LIMIT 1",
+    )
+    .bind(note_id)
+    .bind(format!("-{} seconds", SNAPSHOT_INTERVAL_SECS))
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    if let Some((last_title, last_content, recent)) = latest {
+        if (throttle && recent) || (last_title == title && last_content == content) {
+            return Ok(());
+        }
+    }
+
+    sqlx::query(
+        "INSERT INTO note_revisions (note_id, title, content, source) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(note_id)
+    .bind(&title)
+    .bind(&content)
+    .bind(source)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "DELETE FROM note_revisions WHERE note_id = $1 AND id NOT IN (
+            SELECT id FROM note_revisions WHERE note_id = $1 ORDER BY id DESC LIMIT $2
+        )",
+    )
+    .bind(note_id)
+    .bind(MAX_REVISIONS_PER_NOTE)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_revisions(
+    pool: State<'_, SqlitePool>,
+    note_id: i64,
+) -> Result<Vec<Revision>, String> {
+    sqlx::query_as::<_, Revision>(
+        "SELECT id, note_id, title, source, length(COALESCE(content, '')) AS size, created_at
+         FROM note_revisions WHERE note_id = $1 ORDER BY id DESC",
+    )
+    .bind(note_id)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Line diff from revision `from` to revision `to`, or to the note as it is now.
+#[tauri::command]
+pub async fn diff_revisions(
+    pool: State<'_, SqlitePool>,
+    from: i64,
+    to: Option<i64>,
+) -> Result<RevisionDiff, String> {
+    let (note_id, old_title, old_content) = fetch_revision(&pool, from).await?;
+
+    let (new_title, new_content) = match to {
+        Some(to) => {
+            let (to_note, title, content) = fetch_revision(&pool, to).await?;
+            if to_note != note_id {
+                return Err("Revisions belong to different notes".to_string());
+            }
+            (title, content)
+        }
+        None => {
+            let row: Option<(String, Option<String>)> =
+                sqlx::query_as("SELECT title, content FROM notes WHERE id = $1")
+                    .bind(note_id)
+                    .fetch_optional(&*pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            let (title, content) = row.ok_or("Note not found")?;
+            (title, content.unwrap_or_default())
+        }
+    };
+
+    let patch = diffy::create_patch(&old_content, &new_content);
+    let hunks = patch
+        .hunks()
+        .iter()
+        .map(|hunk| DiffHunk {
+            old_start: hunk.old_range().start(),
+            old_lines: hunk.old_range().len(),
+            new_start: hunk.new_range().start(),
+            new_lines: hunk.new_range().len(),
+            lines: hunk
+                .lines()
+                .iter()
+                .map(|line| {
+                    let (kind, text) = match line {
+                        Line::Context(t) => ("context", t),
+                        Line::Insert(t) => ("insert", t),
+                        Line::Delete(t) => ("delete", t),
+                    };
+                    DiffLine {
+                        kind,
+                        text: text.trim_end_matches('\n').to_string(),
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(RevisionDiff {
+        old_title,
+        new_title,
+        hunks,
+    })
+}
+
+/// Bring back an old revision as a new edit. The current state is snapshotted
+/// first, so a restore can itself be undone.
+#[tauri::command]
+pub async fn restore_revision(pool: State<'_, SqlitePool>, revision_id: i64) -> Result<i64, String> {
+    let (note_id, title, content) = fetch_revision(&pool, revision_id).await?;
+    println!("Backend: restore_revision: note={} revision={}", note_id, revision_id);
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    if locked::is_locked(&mut tx, note_id).await.map_err(|e| e.to_string())? {
+        return Err("Unlock the note before restoring a revision".to_string());
+    }
+    snapshot(&mut tx, note_id, "restore", false)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("UPDATE notes SET title = $1, content = $2 WHERE id = $3")
+        .bind(title)
+        .bind(&content)
+        .bind(note_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+    commands::reindex(&mut tx, note_id, &content).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+
+    Ok(note_id)
+}
+
+async fn fetch_revision(pool: &SqlitePool, id: i64) -> Result<(i64, String, String), String> {
+    let row: Option<(i64, String, Option<String>)> =
+        sqlx::query_as("SELECT note_id, title, content FROM note_revisions WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    let (note_id, title, content) = row.ok_or_else(|| format!("Revision {} not found", id))?;
+    Ok((note_id, title, content.unwrap_or_default()))
+}
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqlitePool};
+use tauri::State;
+
+// Markers FTS5 wraps around matches. Swapped for <mark> after the text is
+// escaped, so note content can never inject markup into the results list.
+const HL_START: &str = "\u{2}";
+const HL_END: &str = "\u{3}";
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Every word must appear somewhere in the note. `word*` matches prefixes.
+    #[default]
+    Words,
+    /// The query must appear verbatim (case-insensitive), like the old title filter.
+    Exact,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct SearchHit {
+    pub id: i64,
+    pub title: String,
+    /// HTML-escaped title with matches wrapped in <mark>
+    pub title_highlight: String,
+    /// HTML-escaped excerpt of the content around the best match
+    pub snippet: String,
+    pub updated_at: String,
+    pub pb_id: Option<String>,
+    pub local_uuid: Option<String>,
+    pub rank: f64,
+}
+
+#[tauri::command]
+pub async fn search_notes(
+    pool: State<'_, SqlitePool>,
+    query: String,
+    mode: Option<SearchMode>,
+    limit: Option<i64>,
+) -> Result<Vec<SearchHit>, String> {
+    let mode = mode.unwrap_or_default();
+    let Some(fts_query) = build_query(&query, mode) else {
+        return Ok(Vec::new());
+    };
+
+    println!("Backend: search_notes: {}", fts_query);
+
+    // Title matches weigh ten times as much as body matches
+    let mut hits = sqlx::query_as::<_, SearchHit>(
+        "SELECT n.id, n.title,
+                highlight(notes_fts, 0, $2, $3) AS title_highlight,
+                snippet(notes_fts, 1, $2, $3, '…', 16) AS snippet,
+                n.updated_at, n.pb_id, n.local_uuid,
+                bm25(notes_fts, 10.0, 1.0) AS rank
+         FROM notes_fts
+         JOIN notes n ON n.id = notes_fts.rowid
+         WHERE notes_fts MATCH $1
+           AND n.deleted_at IS NULL
+           AND ($4 = 0 OR instr(lower(n.title), lower($5)) > 0 OR instr(lower(n.content), lower($5)) > 0)
+         ORDER BY rank
+         LIMIT $6",
+    )
+    .bind(&fts_query)
+    .bind(HL_START)
+    .bind(HL_END)
+    .bind(mode == SearchMode::Exact)
+    .bind(query.trim())
+    .bind(limit.unwrap_or(50))
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for hit in &mut hits {
+        hit.title_highlight = mark_up(&hit.title_highlight);
+        hit.snippet = mark_up(&hit.snippet);
+    }
+
+    Ok(hits)
+}
+
+/// Turn user input into an FTS5 expression. Every term is quoted so stray
+/// operators (`-`, `:`, `NEAR`, unbalanced quotes) can't cause syntax errors.
+fn build_query(input: &str, mode: SearchMode) -> Option<String> {
+    let quote = |term: &str| format!("\"{}\"", term.replace('"', "\"\""));
+
+    match mode {
+        // FTS5 narrows the candidates by phrase; the substring check in SQL
+        // then drops hits where the words only match across punctuation.
+        SearchMode::Exact => {
+            let phrase = input.trim();
+            (!phrase.is_empty()).then(|| quote(phrase))
+        }
+        SearchMode::Words => {
+            let terms: Vec<String> = input
+                .split_whitespace()
+                .filter_map(|word| match word.strip_suffix('*') {
+                    Some("") => None,
+                    Some(prefix) => Some(format!("{}*", quote(prefix))),
+                    None => Some(quote(word)),
+                })
+                .collect();
+            (!terms.is_empty()).then(|| terms.join(" "))
+        }
+    }
+}
+
+fn mark_up(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace(HL_START, "<mark>")
+        .replace(HL_END, "</mark>")
+}
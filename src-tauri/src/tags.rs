@@ -0,0 +1,448 @@
+use crate::commands::Note;
+use crate::revisions;
+use serde::Serialize;
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use std::ops::Range;
+use tauri::State;
+
+#[derive(Serialize, FromRow)]
+pub struct TagCount {
+    pub name: String,
+    pub note_count: i64,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct NoteTag {
+    pub name: String,
+    /// `inline` when it comes from `#tag` in the content, `manual` otherwise
+    pub source: String,
+}
+
+// --- Inline Tags ---
+
+/// Byte ranges of the names of `#tag`s in markdown, skipping headings,
+/// code spans, fenced code blocks and `#` inside URLs or words.
+pub fn inline_tags(content: &str) -> Vec<Range<usize>> {
+    let mut found = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence {
+            scan_line(line, offset, &mut found);
+        }
+        offset += line.len();
+    }
+
+    found
+}
+
+fn scan_line(line: &str, offset: usize, found: &mut Vec<Range<usize>>) {
+    let mut in_code = false;
+    let mut prev: Option<char> = None;
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c == '`' {
+            in_code = !in_code;
+        } else if c == '#' && !in_code && prev.is_none_or(|p| p.is_whitespace() || "([{,;:!?\"'".contains(p)) {
+            let start = i + 1;
+            let mut end = start;
+            while let Some(&(j, n)) = chars.peek() {
+                if !is_tag_char(n) {
+                    break;
+                }
+                end = j + n.len_utf8();
+                chars.next();
+            }
+
+            let name = line[start..end].trim_end_matches('/');
+            if is_valid_name(name) {
+                found.push(offset + start..offset + start + name.len());
+            }
+            prev = line[..end].chars().next_back();
+            continue;
+        }
+        prev = Some(c);
+    }
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-' || c == '/'
+}
+
+// `#2024` is an issue number or a year, not a tag
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(is_tag_char) && !name.chars().all(|c| c.is_ascii_digit())
+}
+
+fn normalize(name: &str) -> Result<String, String> {
+    let name = name.trim().trim_start_matches('#').to_lowercase();
+    if !is_valid_name(&name) {
+        return Err(format!("Invalid tag name: {}", name));
+    }
+    Ok(name)
+}
+
+/// Re-derive the note's inline tags from its content. Manual tags are left alone.
+pub async fn sync_inline(conn: &mut SqliteConnection, note_id: i64, content: &str) -> Result<(), sqlx::Error> {
+    let mut names: Vec<String> = inline_tags(content)
+        .into_iter()
+        .map(|range| content[range].to_lowercase())
+        .collect();
+    names.sort();
+    names.dedup();
+
+    let current: Vec<(String,)> = sqlx::query_as(
+        "SELECT t.name FROM note_tags nt JOIN tags t ON t.id = nt.tag_id
+         WHERE nt.note_id = $1 AND nt.source = 'inline' ORDER BY t.name",
+    )
+    .bind(note_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    // Most saves don't touch tags
+    if current.iter().map(|r| &r.0).eq(names.iter()) {
+        return Ok(());
+    }
+
+    sqlx::query("DELETE FROM note_tags WHERE note_id = $1 AND source = 'inline'")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for name in &names {
+        let tag_id = ensure_tag(conn, name).await?;
+        sqlx::query("INSERT OR IGNORE INTO note_tags (note_id, tag_id, source) VALUES ($1, $2, 'inline')")
+            .bind(note_id)
+            .bind(tag_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    remove_unused(conn).await
+}
+
+async fn ensure_tag(conn: &mut SqliteConnection, name: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query("INSERT INTO tags (name) VALUES ($1) ON CONFLICT(name) DO NOTHING")
+        .bind(name)
+        .execute(&mut *conn)
+        .await?;
+    let row: (i64,) = sqlx::query_as("SELECT id FROM tags WHERE name = $1")
+        .bind(name)
+        .fetch_one(&mut *conn)
+        .await?;
+    Ok(row.0)
+}
+
+async fn remove_unused(conn: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM note_tags)")
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+// --- Commands ---
+
+#[tauri::command]
+pub async fn list_tags(pool: State<'_, SqlitePool>) -> Result<Vec<TagCount>, String> {
+    sqlx::query_as::<_, TagCount>(
+        "SELECT t.name, COUNT(DISTINCT n.id) AS note_count
+         FROM tags t
+         JOIN note_tags nt ON nt.tag_id = t.id
+         JOIN notes n ON n.id = nt.note_id AND n.deleted_at IS NULL
+         GROUP BY t.id
+         ORDER BY t.name",
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_note_tags(pool: State<'_, SqlitePool>, note_id: i64) -> Result<Vec<NoteTag>, String> {
+    sqlx::query_as::<_, NoteTag>(
+        "SELECT t.name, nt.source FROM note_tags nt JOIN tags t ON t.id = nt.tag_id
+         WHERE nt.note_id = $1 ORDER BY t.name, nt.source",
+    )
+    .bind(note_id)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn add_tag(pool: State<'_, SqlitePool>, note_id: i64, name: String) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    add_manual(&mut tx, note_id, &name).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub async fn add_manual(conn: &mut SqliteConnection, note_id: i64, name: &str) -> Result<(), String> {
+    let name = normalize(name)?;
+    let tag_id = ensure_tag(conn, &name).await.map_err(|e| e.to_string())?;
+    sqlx::query("INSERT OR IGNORE INTO note_tags (note_id, tag_id, source) VALUES ($1, $2, 'manual')")
+        .bind(note_id)
+        .bind(tag_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Replace the note's manual tags, e.g. with the ones from synced front-matter.
+/// Names already tagged inline, or that Onyx can't represent, are skipped.
+pub async fn set_manual(conn: &mut SqliteConnection, note_id: i64, names: &[String]) -> Result<(), String> {
+    sqlx::query("DELETE FROM note_tags WHERE note_id = $1 AND source = 'manual'")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let inline: Vec<(String,)> = sqlx::query_as(
+        "SELECT t.name FROM note_tags nt JOIN tags t ON t.id = nt.tag_id WHERE nt.note_id = $1 AND nt.source = 'inline'",
+    )
+    .bind(note_id)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for name in names {
+        let Ok(name) = normalize(name) else {
+            continue;
+        };
+        if !inline.iter().any(|(n,)| *n == name) {
+            add_manual(conn, note_id, &name).await?;
+        }
+    }
+    remove_unused(conn).await.map_err(|e| e.to_string())
+}
+
+/// Remove a manually assigned tag. Inline tags go away by editing the content.
+#[tauri::command]
+pub async fn remove_tag(pool: State<'_, SqlitePool>, note_id: i64, name: String) -> Result<(), String> {
+    let name = normalize(&name)?;
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+
+    sqlx::query(
+        "DELETE FROM note_tags WHERE note_id = $1 AND source = 'manual'
+         AND tag_id = (SELECT id FROM tags WHERE name = $2)",
+    )
+    .bind(note_id)
+    .bind(&name)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    remove_unused(&mut tx).await.map_err(|e| e.to_string())?;
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Rename a tag on every note, rewriting `#from` in note content. If `to`
+/// already exists the two are merged. Returns how many notes were rewritten.
+#[tauri::command]
+pub async fn rename_tag(pool: State<'_, SqlitePool>, from: String, to: String) -> Result<usize, String> {
+    merge_tags(pool, vec![from], to).await
+}
+
+#[tauri::command]
+pub async fn merge_tags(pool: State<'_, SqlitePool>, from: Vec<String>, into: String) -> Result<usize, String> {
+    let into = normalize(&into)?;
+    println!("Backend: merge_tags: {:?} -> {}", from, into);
+
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let target_id = ensure_tag(&mut tx, &into).await.map_err(|e| e.to_string())?;
+    let mut rewritten = 0;
+
+    for name in from {
+        let name = normalize(&name)?;
+        if name == into {
+            continue;
+        }
+
+        let tag: Option<(i64,)> = sqlx::query_as("SELECT id FROM tags WHERE name = $1")
+            .bind(&name)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        let (tag_id,) = tag.ok_or_else(|| format!("Tag not found: {}", name))?;
+
+        let inline: Vec<(i64, Option<String>)> = sqlx::query_as(
+            "SELECT n.id, n.content FROM notes n JOIN note_tags nt ON nt.note_id = n.id
+             WHERE nt.tag_id = $1 AND nt.source = 'inline'",
+        )
+        .bind(tag_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        for (note_id, content) in inline {
+            let content = content.unwrap_or_default();
+            let updated = rewrite_inline(&content, &name, &into);
+            if updated == content {
+                continue;
+            }
+
+            revisions::snapshot(&mut tx, note_id, "rename", false)
+                .await
+                .map_err(|e| e.to_string())?;
+            sqlx::query("UPDATE notes SET content = $1 WHERE id = $2")
+                .bind(&updated)
+                .bind(note_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| e.to_string())?;
+            rewritten += 1;
+        }
+
+        sqlx::query(
+            "INSERT OR IGNORE INTO note_tags (note_id, tag_id, source)
+             SELECT note_id, $2, source FROM note_tags WHERE tag_id = $1",
+        )
+        .bind(tag_id)
+        .bind(target_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        sqlx::query("DELETE FROM tags WHERE id = $1")
+            .bind(tag_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    remove_unused(&mut tx).await.map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(rewritten)
+}
+
+fn rewrite_inline(content: &str, from: &str, to: &str) -> String {
+    let mut out = content.to_string();
+    // Back to front so earlier ranges stay valid
+    for range in inline_tags(content).into_iter().rev() {
+        if content[range.clone()].to_lowercase() == from {
+            out.replace_range(range, to);
+        }
+    }
+    out
+}
+
+/// Notes matching a tag expression, e.g. `work AND (urgent OR later) AND NOT done`.
+/// Adjacent terms are ANDed and `-tag` is short for `NOT tag`.
+#[tauri::command]
+pub async fn find_notes_by_tags(pool: State<'_, SqlitePool>, query: String) -> Result<Vec<Note>, String> {
+    let expr = parse_query(&query)?;
+    let mut binds = Vec::new();
+    let condition = expr.to_sql(&mut binds);
+
+    let sql = format!(
+        "SELECT id, title, updated_at, hlc, pb_id, local_uuid, folder_id, locked_ciphertext IS NOT NULL AS locked FROM notes n
+         WHERE n.deleted_at IS NULL AND {}
+         ORDER BY updated_at DESC, id DESC",
+        condition
+    );
+
+    let mut q = sqlx::query_as::<_, Note>(&sql);
+    for bind in binds {
+        q = q.bind(bind);
+    }
+    q.fetch_all(&*pool).await.map_err(|e| e.to_string())
+}
+
+// --- Tag Expressions ---
+
+enum TagExpr {
+    Tag(String),
+    Not(Box<TagExpr>),
+    And(Vec<TagExpr>),
+    Or(Vec<TagExpr>),
+}
+
+impl TagExpr {
+    fn to_sql(&self, binds: &mut Vec<String>) -> String {
+        match self {
+            TagExpr::Tag(name) => {
+                binds.push(name.clone());
+                format!(
+                    "n.id IN (SELECT nt.note_id FROM note_tags nt JOIN tags t ON t.id = nt.tag_id WHERE t.name = ${})",
+                    binds.len()
+                )
+            }
+            TagExpr::Not(inner) => format!("NOT ({})", inner.to_sql(binds)),
+            TagExpr::And(items) => join_sql(items, " AND ", binds),
+            TagExpr::Or(items) => join_sql(items, " OR ", binds),
+        }
+    }
+}
+
+fn join_sql(items: &[TagExpr], op: &str, binds: &mut Vec<String>) -> String {
+    let parts: Vec<String> = items.iter().map(|item| item.to_sql(binds)).collect();
+    format!("({})", parts.join(op))
+}
+
+fn parse_query(query: &str) -> Result<TagExpr, String> {
+    let spaced = query.replace('(', " ( ").replace(')', " ) ");
+    let tokens: Vec<&str> = spaced.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Err("Empty tag query".to_string());
+    }
+
+    let mut pos = 0;
+    let expr = parse_or(&tokens, &mut pos)?;
+    if pos < tokens.len() {
+        return Err(format!("Unexpected '{}' in tag query", tokens[pos]));
+    }
+    Ok(expr)
+}
+
+fn parse_or(tokens: &[&str], pos: &mut usize) -> Result<TagExpr, String> {
+    let mut items = vec![parse_and(tokens, pos)?];
+    while tokens.get(*pos).is_some_and(|t| t.eq_ignore_ascii_case("OR")) {
+        *pos += 1;
+        items.push(parse_and(tokens, pos)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { TagExpr::Or(items) })
+}
+
+fn parse_and(tokens: &[&str], pos: &mut usize) -> Result<TagExpr, String> {
+    let mut items = vec![parse_unary(tokens, pos)?];
+    loop {
+        match tokens.get(*pos) {
+            Some(t) if t.eq_ignore_ascii_case("AND") => *pos += 1,
+            Some(t) if t.eq_ignore_ascii_case("OR") || *t == ")" => break,
+            Some(_) => {}
+            None => break,
+        }
+        items.push(parse_unary(tokens, pos)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { TagExpr::And(items) })
+}
+
+fn parse_unary(tokens: &[&str], pos: &mut usize) -> Result<TagExpr, String> {
+    let token = *tokens.get(*pos).ok_or("Tag query ends unexpectedly")?;
+    *pos += 1;
+
+    if token.eq_ignore_ascii_case("NOT") {
+        return Ok(TagExpr::Not(Box::new(parse_unary(tokens, pos)?)));
+    }
+    if token == "(" {
+        let inner = parse_or(tokens, pos)?;
+        if tokens.get(*pos) != Some(&")") {
+            return Err("Missing ')' in tag query".to_string());
+        }
+        *pos += 1;
+        return Ok(inner);
+    }
+    if token == ")" {
+        return Err("Unexpected ')' in tag query".to_string());
+    }
+    if let Some(negated) = token.strip_prefix('-') {
+        return Ok(TagExpr::Not(Box::new(TagExpr::Tag(normalize(negated)?))));
+    }
+    Ok(TagExpr::Tag(normalize(token)?))
+}
@@ -0,0 +1,39 @@
+//! Databases for tests: a fresh, migrated file in a temp dir.
+
+use crate::migrations;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::SqlitePool;
+use tempfile::TempDir;
+
+pub struct TestDb {
+    pub pool: SqlitePool,
+    // Removed when the test is done with it
+    _dir: TempDir,
+}
+
+pub async fn database() -> TestDb {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("onyx.db");
+    let options = SqliteConnectOptions::new().filename(&path).create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await.unwrap();
+    migrations::run(&pool, &path).await.unwrap();
+    TestDb { pool, _dir: dir }
+}
+
+pub async fn add_note(pool: &SqlitePool, title: &str, content: &str) -> i64 {
+    sqlx::query("INSERT INTO notes (title, content) VALUES ($1, $2)")
+        .bind(title)
+        .bind(content)
+        .execute(pool)
+        .await
+        .unwrap()
+        .last_insert_rowid()
+}
+
+/// Live notes as (title, content), by title.
+pub async fn notes(pool: &SqlitePool) -> Vec<(String, String)> {
+    sqlx::query_as("SELECT title, COALESCE(content, '') FROM notes WHERE deleted_at IS NULL ORDER BY title")
+        .fetch_all(pool)
+        .await
+        .unwrap()
+}
@@ -0,0 +1,196 @@
+use crate::settings;
+use serde::Serialize;
+use sqlx::{FromRow, SqlitePool};
+use tauri::State;
+
+const AUTO_PURGE_KEY: &str = "trash_auto_purge_days";
+const DEFAULT_AUTO_PURGE_DAYS: i64 = 30;
+// Long enough for every device to have synced a purged note's delete
+const TOMBSTONE_RETENTION_DAYS: i64 = 90;
+
+#[derive(Serialize, FromRow)]
+pub struct TrashedNote {
+    pub id: i64,
+    pub title: String,
+    pub deleted_at: String,
+    pub pb_id: Option<String>,
+    pub local_uuid: Option<String>,
+}
+
+/// A deleted note as the sync layer needs it, to delete the remote copy.
+#[derive(Serialize, FromRow)]
+pub struct Tombstone {
+    pub id: i64,
+    pub pb_id: Option<String>,
+    pub local_uuid: Option<String>,
+    pub deleted_at: String,
+    /// Gone from the trash; only its identity is left
+    pub purged: bool,
+}
+
+#[tauri::command]
+pub async fn list_trash(pool: State<'_, SqlitePool>) -> Result<Vec<TrashedNote>, String> {
+    sqlx::query_as::<_, TrashedNote>(
+        "SELECT id, title, deleted_at, pb_id, local_uuid FROM notes
+         WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id DESC",
+    )
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn restore_note(pool: State<'_, SqlitePool>, id: i64) -> Result<(), String> {
+    println!("Backend: restore_note: {}", id);
+    let result = sqlx::query("UPDATE notes SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL")
+        .bind(id)
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if result.rows_affected() == 0 {
+        return Err("Note is not in the trash".to_string());
+    }
+    Ok(())
+}
+
+/// Permanently delete one trashed note. A tombstone with its uuid and
+/// PocketBase id stays behind for `get_tombstones`.
+#[tauri::command]
+pub async fn purge_note(pool: State<'_, SqlitePool>, id: i64) -> Result<(), String> {
+    println!("Backend: purge_note: {}", id);
+    let result = sqlx::query("DELETE FROM notes WHERE id = $1 AND deleted_at IS NOT NULL")
+        .bind(id)
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if result.rows_affected() == 0 {
+        return Err("Note is not in the trash".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn empty_trash(pool: State<'_, SqlitePool>) -> Result<u64, String> {
+    let result = sqlx::query("DELETE FROM notes WHERE deleted_at IS NOT NULL")
+        .execute(&*pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("Backend: empty_trash: {} notes purged", result.rows_affected());
+    Ok(result.rows_affected())
+}
+
+/// Days a note stays in the trash before it is purged. `None` keeps it forever.
+#[tauri::command]
+pub async fn get_trash_auto_purge_days(pool: State<'_, SqlitePool>) -> Result<Option<i64>, String> {
+    auto_purge_days(&pool).await
+}
+
+#[tauri::command]
+pub async fn set_trash_auto_purge_days(
+    pool: State<'_, SqlitePool>,
+    days: Option<i64>,
+) -> Result<u64, String> {
+    if days.is_some_and(|d| d < 1) {
+        return Err("Auto-purge needs at least one day".to_string());
+    }
+
+    let value = days.map_or("never".to_string(), |d| d.to_string());
+    settings::set(&pool, AUTO_PURGE_KEY, &value).await?;
+    auto_purge(&pool).await
+}
+
+/// Deleted notes since `since` (inclusive), oldest first, for propagating deletes.
+#[tauri::command]
+pub async fn get_tombstones(
+    pool: State<'_, SqlitePool>,
+    since: Option<String>,
+) -> Result<Vec<Tombstone>, String> {
+    sqlx::query_as::<_, Tombstone>(
+        "SELECT id, pb_id, local_uuid, deleted_at, FALSE AS purged FROM notes
+         WHERE deleted_at IS NOT NULL AND ($1 IS NULL OR deleted_at >= $1)
+         UNION ALL
+         SELECT note_id, pb_id, local_uuid, deleted_at, TRUE FROM note_tombstones
+         WHERE $1 IS NULL OR deleted_at >= $1
+         ORDER BY deleted_at, id",
+    )
+    .bind(since)
+    .fetch_all(&*pool)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Purge notes that have been in the trash longer than the configured age,
+/// and forget notes purged longer ago than `TOMBSTONE_RETENTION_DAYS`.
+pub async fn auto_purge(pool: &SqlitePool) -> Result<u64, String> {
+    prune_tombstones(pool).await?;
+    let Some(days) = auto_purge_days(pool).await? else {
+        return Ok(0);
+    };
+
+    let result = sqlx::query("DELETE FROM notes WHERE deleted_at IS NOT NULL AND deleted_at < datetime('now', $1)")
+        .bind(format!("-{} days", days))
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if result.rows_affected() > 0 {
+        println!("Trash: auto-purged {} notes older than {} days", result.rows_affected(), days);
+    }
+    Ok(result.rows_affected())
+}
+
+async fn prune_tombstones(pool: &SqlitePool) -> Result<u64, String> {
+    let result = sqlx::query("DELETE FROM note_tombstones WHERE purged_at < datetime('now', $1)")
+        .bind(format!("-{} days", TOMBSTONE_RETENTION_DAYS))
+        .execute(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if result.rows_affected() > 0 {
+        println!(
+            "Trash: dropped {} tombstones older than {} days",
+            result.rows_affected(),
+            TOMBSTONE_RETENTION_DAYS
+        );
+    }
+    Ok(result.rows_affected())
+}
+
+async fn auto_purge_days(pool: &SqlitePool) -> Result<Option<i64>, String> {
+    match settings::get(pool, AUTO_PURGE_KEY).await?.as_deref() {
+        None => Ok(Some(DEFAULT_AUTO_PURGE_DAYS)),
+        Some("never") => Ok(None),
+        Some(days) => days
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid {} setting: {}", AUTO_PURGE_KEY, days)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    #[tokio::test]
+    async fn drops_tombstones_after_the_retention_window() {
+        let db = test_support::database().await;
+        sqlx::query(
+            "INSERT INTO note_tombstones (note_id, local_uuid, deleted_at, purged_at) VALUES
+             (1, 'old', datetime('now', '-100 days'), datetime('now', '-91 days')),
+             (2, 'recent', datetime('now', '-100 days'), datetime('now', '-89 days'))",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        assert_eq!(prune_tombstones(&db.pool).await.unwrap(), 1);
+        let left: Vec<i64> = sqlx::query_scalar("SELECT note_id FROM note_tombstones")
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(left, [2]);
+    }
+}
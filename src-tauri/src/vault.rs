@@ -0,0 +1,955 @@
+use crate::{attachments, commands, folders, links, revisions, tags};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Emitter, State};
+use walkdir::WalkDir;
+
+// Kept in the export directory so the next run knows what it wrote
+const MANIFEST_NAME: &str = ".onyx-export.json";
+// Leaves room for the directory and a ` (n)` suffix within common path limits
+const MAX_NAME_CHARS: usize = 100;
+
+#[derive(FromRow)]
+pub(crate) struct ExportRow {
+    pub(crate) id: i64,
+    pub(crate) title: String,
+    pub(crate) content: Option<String>,
+    pub(crate) created_at: Option<String>,
+    pub(crate) updated_at: Option<String>,
+    pub(crate) local_uuid: Option<String>,
+    pub(crate) folder_id: Option<i64>,
+    pub(crate) locked: bool,
+}
+
+#[derive(Serialize)]
+struct FrontMatter<'a> {
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_uuid: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    folder: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tags: &'a [String],
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct ExportManifest {
+    /// By `local_uuid`, or `id:<n>` for notes that don't have one
+    files: HashMap<String, ExportedFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedFile {
+    /// Relative to the export directory, `/`-separated
+    path: String,
+    /// SHA-256 of what was written, to tell whether the file was edited since
+    hash: String,
+}
+
+#[derive(Serialize)]
+pub struct SkippedNote {
+    pub id: i64,
+    pub title: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct ExportReport {
+    pub written: usize,
+    pub unchanged: usize,
+    /// Files of notes that were deleted, locked or moved since the last export
+    pub removed: usize,
+    pub skipped: Vec<SkippedNote>,
+}
+
+/// A file or directory name that is valid on Windows, macOS and Linux.
+pub fn safe_file_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if c.is_control() || "<>:\"/\\|?*".contains(c) { '_' } else { c })
+        .take(MAX_NAME_CHARS)
+        .collect();
+    // Windows drops trailing dots and spaces; a leading dot hides the file
+    let mut name = cleaned.trim().trim_end_matches(['.', ' ']).to_string();
+    if name.starts_with('.') {
+        name.replace_range(..1, "_");
+    }
+    if name.is_empty() {
+        return "Untitled".to_string();
+    }
+
+    let base = name.split('.').next().unwrap_or_default().to_uppercase();
+    let reserved = matches!(base.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((base.starts_with("COM") || base.starts_with("LPT"))
+            && base.len() == 4
+            && base.as_bytes()[3].is_ascii_digit());
+    if reserved {
+        name.insert(0, '_');
+    }
+    name
+}
+
+// Case-insensitive, since that's how most desktop file systems compare names
+pub(crate) fn unique_path(dir: &str, stem: &str, ext: &str, taken: &mut HashSet<String>) -> String {
+    let mut n = 1;
+    loop {
+        let name = if n == 1 {
+            format!("{}{}", stem, ext)
+        } else {
+            format!("{} ({}){}", stem, n, ext)
+        };
+        let path = join(dir, &name);
+        if taken.insert(path.to_lowercase()) {
+            return path;
+        }
+        n += 1;
+    }
+}
+
+pub(crate) fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+pub(crate) fn to_disk(root: &Path, relative: &str) -> PathBuf {
+    relative.split('/').fold(root.to_path_buf(), |path, part| path.join(part))
+}
+
+// Whether `path` is what `unique_path` could have produced for this note,
+// so a note keeps its ` (2)` file across runs instead of trading places
+pub(crate) fn fits(path: &str, dir: &str, stem: &str) -> bool {
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let Some(name) = name.strip_suffix(".md") else {
+        return false;
+    };
+    if !parent.eq_ignore_ascii_case(dir) {
+        return false;
+    }
+    name == stem
+        || name
+            .strip_prefix(stem)
+            .and_then(|rest| rest.strip_prefix(" ("))
+            .and_then(|rest| rest.strip_suffix(')'))
+            .is_some_and(|n| n.parse::<u32>().is_ok())
+}
+
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// SQLite's CURRENT_TIMESTAMP is UTC without a zone
+fn iso_timestamp(value: Option<String>) -> Option<String> {
+    value.map(|v| format!("{}Z", v.replacen(' ', "T", 1)))
+}
+
+/// Live notes, locked ones included, oldest first.
+pub(crate) async fn load_notes(conn: &mut SqliteConnection) -> Result<Vec<ExportRow>, String> {
+    sqlx::query_as::<_, ExportRow>(
+        "SELECT id, title, content, created_at, updated_at, local_uuid, folder_id,
+                locked_ciphertext IS NOT NULL AS locked
+         FROM notes WHERE deleted_at IS NULL ORDER BY id",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Give every note a `local_uuid`; exported files are matched back to notes by it.
+pub(crate) async fn assign_uuids(conn: &mut SqliteConnection) -> Result<(), String> {
+    let missing: Vec<(i64,)> = sqlx::query_as("SELECT id FROM notes WHERE local_uuid IS NULL")
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    for (id,) in missing {
+        sqlx::query("UPDATE notes SET local_uuid = $1 WHERE id = $2")
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Note id -> names of all its tags, inline and manual.
+pub(crate) async fn load_tags(conn: &mut SqliteConnection) -> Result<HashMap<i64, Vec<String>>, String> {
+    let rows: Vec<(i64, String)> = sqlx::query_as(
+        "SELECT DISTINCT nt.note_id, t.name FROM note_tags nt JOIN tags t ON t.id = nt.tag_id ORDER BY t.name",
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    for (note_id, name) in rows {
+        tags.entry(note_id).or_default().push(name);
+    }
+    Ok(tags)
+}
+
+/// The note as a Markdown file with YAML front-matter.
+pub(crate) fn render(note: &ExportRow, folder: Option<&str>, tags: &[String]) -> Result<String, String> {
+    let front_matter = FrontMatter {
+        title: &note.title,
+        local_uuid: note.local_uuid.as_deref(),
+        created: iso_timestamp(note.created_at.clone()),
+        updated: iso_timestamp(note.updated_at.clone()),
+        folder,
+        tags,
+    };
+    let yaml = serde_yaml::to_string(&front_matter).map_err(|e| e.to_string())?;
+    Ok(format!("---\n{}---\n\n{}", yaml, note.content.as_deref().unwrap_or_default()))
+}
+
+/// Folder id -> (path as shown in the app, directory in the export).
+pub(crate) async fn folder_paths(conn: &mut SqliteConnection) -> Result<HashMap<i64, (String, String)>, String> {
+    let folders: Vec<(i64, String, Option<i64>)> =
+        sqlx::query_as("SELECT id, name, parent_id FROM folders ORDER BY id")
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    let by_id: HashMap<i64, (&str, Option<i64>)> = folders
+        .iter()
+        .map(|(id, name, parent)| (*id, (name.as_str(), *parent)))
+        .collect();
+
+    // Sibling names that only differ in characters the file system rejects
+    // still need their own directories
+    let mut taken = HashSet::new();
+    let mut dir_names: HashMap<i64, String> = HashMap::new();
+    for (id, name, parent) in &folders {
+        let scope = parent.map(|p| p.to_string()).unwrap_or_default();
+        let name = unique_path(&scope, &safe_file_name(name), "", &mut taken);
+        dir_names.insert(*id, name.rsplit('/').next().unwrap_or_default().to_string());
+    }
+
+    let mut paths = HashMap::new();
+    for (id, _, _) in &folders {
+        let mut names = Vec::new();
+        let mut dirs = Vec::new();
+        let mut current = Some(*id);
+        while let Some(folder) = current {
+            let Some((name, parent)) = by_id.get(&folder) else {
+                break;
+            };
+            names.push(*name);
+            dirs.push(dir_names[&folder].as_str());
+            current = *parent;
+            // move_folder keeps the tree acyclic; this only guards against a corrupt table
+            if names.len() > folders.len() {
+                break;
+            }
+        }
+        names.reverse();
+        dirs.reverse();
+        paths.insert(*id, (names.join("/"), dirs.join("/")));
+    }
+    Ok(paths)
+}
+
+/// Write every note to `dir` as Markdown with YAML front-matter, mirroring the
+/// folder tree. Only notes that changed since the last export are rewritten.
+#[tauri::command]
+pub async fn export_workspace(pool: State<'_, SqlitePool>, dir: String) -> Result<ExportReport, String> {
+    let root = PathBuf::from(&dir);
+    println!("Backend: export_workspace: {}", root.display());
+    tokio::fs::create_dir_all(&root)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", root.display(), e))?;
+
+    let manifest_path = root.join(MANIFEST_NAME);
+    let previous: ExportManifest = match tokio::fs::read(&manifest_path).await {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_default(),
+        Err(_) => ExportManifest::default(),
+    };
+
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    let notes = load_notes(&mut conn).await?;
+    let tags = load_tags(&mut conn).await?;
+    let folders = folder_paths(&mut conn).await?;
+    drop(conn);
+    let mut report = ExportReport::default();
+
+    // Locked notes are never written in the clear
+    let (locked, notes): (Vec<ExportRow>, Vec<ExportRow>) = notes.into_iter().partition(|n| n.locked);
+    for note in locked {
+        report.skipped.push(SkippedNote {
+            id: note.id,
+            title: note.title,
+            reason: "locked".to_string(),
+        });
+    }
+
+    let key = |note: &ExportRow| match &note.local_uuid {
+        Some(uuid) => uuid.clone(),
+        None => format!("id:{}", note.id),
+    };
+    let location = |note: &ExportRow| {
+        let dir = note
+            .folder_id
+            .and_then(|id| folders.get(&id))
+            .map(|(_, dir)| dir.clone())
+            .unwrap_or_default();
+        (dir, safe_file_name(&note.title))
+    };
+
+    // Notes keep last run's file name where it still fits, then the rest get theirs
+    let mut taken = HashSet::new();
+    let mut paths: HashMap<i64, String> = HashMap::new();
+    for note in &notes {
+        let (dir, stem) = location(note);
+        if let Some(old) = previous.files.get(&key(note)) {
+            if fits(&old.path, &dir, &stem) && taken.insert(old.path.to_lowercase()) {
+                paths.insert(note.id, old.path.clone());
+            }
+        }
+    }
+    for note in &notes {
+        paths.entry(note.id).or_insert_with(|| {
+            let (dir, stem) = location(note);
+            unique_path(&dir, &stem, ".md", &mut taken)
+        });
+    }
+
+    // Files from the last run that no note maps to any more; ones the user
+    // edited since are left alone
+    for old in previous.files.values() {
+        if taken.contains(&old.path.to_lowercase()) {
+            continue;
+        }
+        let file = to_disk(&root, &old.path);
+        match tokio::fs::read(&file).await {
+            Ok(data) if sha256_hex(&data) == old.hash => {
+                if tokio::fs::remove_file(&file).await.is_ok() {
+                    report.removed += 1;
+                    remove_empty_dirs(&root, &file).await;
+                }
+            }
+            Ok(_) => println!("Export: keeping {}, it was edited after export", old.path),
+            Err(_) => {}
+        }
+    }
+
+    let mut manifest = ExportManifest::default();
+    for note in &notes {
+        let path = &paths[&note.id];
+        let folder = note.folder_id.and_then(|id| folders.get(&id)).map(|(name, _)| name.as_str());
+        let rendered = render(note, folder, tags.get(&note.id).map(Vec::as_slice).unwrap_or_default())?;
+        let hash = sha256_hex(rendered.as_bytes());
+        let file = to_disk(&root, path);
+
+        let unchanged = previous
+            .files
+            .get(&key(note))
+            .is_some_and(|old| old.path == *path && old.hash == hash)
+            && file.exists();
+        if unchanged {
+            report.unchanged += 1;
+        } else {
+            if let Err(e) = write_file(&file, rendered.as_bytes()).await {
+                report.skipped.push(SkippedNote {
+                    id: note.id,
+                    title: note.title.clone(),
+                    reason: format!("Failed to write {}: {}", path, e),
+                });
+                continue;
+            }
+            report.written += 1;
+        }
+
+        manifest.files.insert(key(note), ExportedFile { path: path.clone(), hash });
+    }
+
+    let data = serde_json::to_vec_pretty(&manifest).map_err(|e| e.to_string())?;
+    tokio::fs::write(&manifest_path, data)
+        .await
+        .map_err(|e| format!("Failed to write export manifest: {}", e))?;
+
+    println!(
+        "Export: {} written, {} unchanged, {} removed, {} skipped",
+        report.written,
+        report.unchanged,
+        report.removed,
+        report.skipped.len()
+    );
+    Ok(report)
+}
+
+pub(crate) async fn write_file(file: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = file.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(file, data).await
+}
+
+// Directories a removed file leaves empty, up to the export root
+pub(crate) async fn remove_empty_dirs(root: &Path, file: &Path) {
+    let mut dir = file.parent();
+    while let Some(current) = dir {
+        if current == root || tokio::fs::remove_dir(current).await.is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+// --- Import ---
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    /// Files stored as attachments from embeds
+    pub attachments: usize,
+    /// `note.md: target` for embeds whose file isn't in the folder
+    pub missing_embeds: Vec<String>,
+    pub skipped: Vec<SkippedFile>,
+}
+
+#[derive(Serialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ImportProgress {
+    pub done: usize,
+    pub total: usize,
+    pub path: String,
+}
+
+struct SourceNote {
+    /// Relative to the imported folder, `/`-separated
+    path: String,
+    title: String,
+    body: String,
+    local_uuid: Option<String>,
+    created: Option<String>,
+    tags: Vec<String>,
+    hash: String,
+}
+
+/// `![[file]]` or `![alt](file)` in note content.
+struct Embed {
+    range: Range<usize>,
+    target: String,
+}
+
+pub(crate) fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("md") || e.eq_ignore_ascii_case("markdown"))
+}
+
+pub(crate) fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn strip_md(name: &str) -> &str {
+    name.strip_suffix(".md").or_else(|| name.strip_suffix(".markdown")).unwrap_or(name)
+}
+
+/// Split YAML front-matter off the top of a Markdown file. Files whose
+/// front-matter doesn't parse are imported whole.
+pub(crate) fn split_front_matter(text: &str) -> (Option<serde_yaml::Value>, &str) {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let Some(rest) = text.strip_prefix("---\n").or_else(|| text.strip_prefix("---\r\n")) else {
+        return (None, text);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            let body = &rest[offset + line.len()..];
+            // The blank line export_workspace puts after the front-matter
+            let body = body.strip_prefix("\r\n").or_else(|| body.strip_prefix('\n')).unwrap_or(body);
+            return match serde_yaml::from_str(&rest[..offset]) {
+                Ok(value) => (Some(value), body),
+                Err(_) => (None, text),
+            };
+        }
+        offset += line.len();
+    }
+    (None, text)
+}
+
+// `tags: [a, b]`, `tags: a, b` or `tags: "#a #b"`, as Obsidian accepts all three
+pub(crate) fn front_matter_tags(value: &serde_yaml::Value) -> Vec<String> {
+    let raw: Vec<String> = match value.get("tags").or_else(|| value.get("tag")) {
+        Some(serde_yaml::Value::Sequence(items)) => items.iter().filter_map(|i| i.as_str()).map(str::to_string).collect(),
+        Some(serde_yaml::Value::String(s)) => s.split([',', ' ']).map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
+    raw.iter()
+        .map(|t| t.trim().trim_start_matches('#').to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+// Back to SQLite's `YYYY-MM-DD HH:MM:SS`; anything else is dropped. Time
+// zone offsets are ignored, front-matter from other tools rarely has them.
+pub(crate) fn sqlite_timestamp(value: &str) -> Option<String> {
+    let value = value.trim().trim_end_matches('Z');
+    let (date, time) = value.split_once(['T', ' ']).unwrap_or((value, "00:00:00"));
+    let time = time.get(..8)?;
+    let shaped = |s: &str, pattern: &str| {
+        s.len() == pattern.len()
+            && s.bytes().zip(pattern.bytes()).all(|(b, p)| if p == b'0' { b.is_ascii_digit() } else { b == p })
+    };
+    (shaped(date, "0000-00-00") && shaped(time, "00:00:00")).then(|| format!("{} {}", date, time))
+}
+
+fn find_embeds(content: &str) -> Vec<Embed> {
+    let mut found = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence {
+            scan_embeds(line, offset, &mut found);
+        }
+        offset += line.len();
+    }
+    found
+}
+
+fn scan_embeds(line: &str, offset: usize, found: &mut Vec<Embed>) {
+    let bytes = line.as_bytes();
+    let mut in_code = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'`' {
+            in_code = !in_code;
+        } else if !in_code && line[i..].starts_with("![[") {
+            if let Some(len) = line[i + 3..].find("]]") {
+                // `|300` sizes and `#heading` parts don't name the file
+                let inner = &line[i + 3..i + 3 + len];
+                let target = inner.split(['|', '#']).next().unwrap_or_default().trim();
+                let end = i + 3 + len + 2;
+                if !target.is_empty() {
+                    found.push(Embed { range: offset + i..offset + end, target: target.to_string() });
+                }
+                i = end;
+                continue;
+            }
+        } else if !in_code && line[i..].starts_with("![") {
+            let link = line[i + 2..]
+                .find("](")
+                .and_then(|alt| line[i + 2 + alt + 2..].find(')').map(|len| (alt, len)));
+            if let Some((alt, len)) = link {
+                let start = i + 2 + alt + 2;
+                let target = line[start..start + len].split(" \"").next().unwrap_or_default().trim();
+                let target = target.trim_start_matches('<').trim_end_matches('>');
+                let end = start + len + 1;
+                let external = target.contains("://") || target.starts_with("data:") || target.starts_with('#');
+                if !target.is_empty() && !external {
+                    found.push(Embed { range: offset + i..offset + end, target: target.replace("%20", " ") });
+                }
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+}
+
+/// Import a folder of Markdown notes, such as an Obsidian vault, under
+/// `folder_id`. Subdirectories become folders, embedded files become
+/// attachments and wiki-links are pointed at the imported titles. Importing the
+/// same folder again only updates notes whose files changed.
+#[tauri::command]
+pub async fn import_markdown_folder(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    dir: String,
+    folder_id: Option<i64>,
+) -> Result<ImportReport, String> {
+    let root = std::fs::canonicalize(&dir).map_err(|e| format!("Failed to open {}: {}", dir, e))?;
+    println!("Backend: import_markdown_folder: {}", root.display());
+
+    let mut report = ImportReport::default();
+    let mut markdown = Vec::new();
+    // Obsidian resolves `![[image.png]]` by file name anywhere in the vault
+    let mut files_by_name: HashMap<String, PathBuf> = HashMap::new();
+
+    let walker = WalkDir::new(&root)
+        .sort_by_file_name()
+        .into_iter()
+        // .obsidian, .trash, .git and the like
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker.filter_map(Result::ok).filter(|e| e.file_type().is_file()) {
+        let path = entry.into_path();
+        if is_markdown(&path) {
+            markdown.push(path);
+        } else if let Some(name) = path.file_name() {
+            files_by_name.entry(name.to_string_lossy().to_lowercase()).or_insert(path);
+        }
+    }
+
+    // Read everything first: links can only be resolved once all titles are known
+    let mut notes = Vec::new();
+    for path in &markdown {
+        let rel = relative(&root, path);
+        let raw = match tokio::fs::read(path).await {
+            Ok(raw) => raw,
+            Err(e) => {
+                report.skipped.push(SkippedFile { path: rel, reason: e.to_string() });
+                continue;
+            }
+        };
+        let hash = sha256_hex(&raw);
+        let Ok(text) = String::from_utf8(raw) else {
+            report.skipped.push(SkippedFile { path: rel, reason: "Not UTF-8 text".to_string() });
+            continue;
+        };
+
+        let (front_matter, body) = split_front_matter(&text);
+        let field = |key: &str| front_matter.as_ref().and_then(|f| f.get(key)).and_then(|v| v.as_str());
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+
+        notes.push(SourceNote {
+            title: field("title").map(str::trim).filter(|t| !t.is_empty()).unwrap_or(&stem).to_string(),
+            local_uuid: field("local_uuid").filter(|u| uuid::Uuid::parse_str(u).is_ok()).map(str::to_string),
+            created: field("created").and_then(sqlite_timestamp),
+            tags: front_matter.as_ref().map(front_matter_tags).unwrap_or_default(),
+            body: body.to_string(),
+            path: rel,
+            hash,
+        });
+    }
+
+    // `[[Note]]`, `[[sub/Note]]` and `[[Note.md]]` all name a file; Onyx links name a title
+    let mut titles: HashMap<String, String> = HashMap::new();
+    for note in &notes {
+        let path = strip_md(&note.path).to_lowercase();
+        let stem = path.rsplit('/').next().unwrap_or_default().to_string();
+        titles.entry(stem).or_insert_with(|| note.title.clone());
+        titles.insert(path, note.title.clone());
+    }
+
+    let attachments_dir = attachments::attachments_dir(&app_handle)?;
+    let mut stored = StoredFiles::default();
+    let mut folders: HashMap<String, Option<i64>> = HashMap::from([(String::new(), folder_id)]);
+    let total = notes.len();
+
+    for (done, note) in notes.into_iter().enumerate() {
+        let _ = app_handle.emit(
+            "import-progress",
+            ImportProgress { done, total, path: note.path.clone() },
+        );
+
+        // Folders created for a note that's then skipped are rolled back
+        // with it, so it works on a copy that's only kept once committed
+        let mut note_folders = folders.clone();
+        let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+        let result = import_one(
+            &mut tx,
+            &root,
+            &note,
+            &titles,
+            &files_by_name,
+            &attachments_dir,
+            &mut stored,
+            &mut note_folders,
+            &mut report,
+        )
+        .await;
+        match result {
+            Ok(()) => {
+                tx.commit().await.map_err(|e| e.to_string())?;
+                report.attachments += stored.commit();
+                folders = note_folders;
+            }
+            Err(reason) => {
+                stored.rollback();
+                report.skipped.push(SkippedFile { path: note.path, reason });
+            }
+        }
+    }
+    let _ = app_handle.emit("import-progress", ImportProgress { done: total, total, path: String::new() });
+
+    println!(
+        "Import: {} created, {} updated, {} unchanged, {} skipped",
+        report.created,
+        report.updated,
+        report.unchanged,
+        report.skipped.len()
+    );
+    Ok(report)
+}
+
+/// Attachment URIs of the files stored so far, so a file embedded by several
+/// notes is stored once. The current note's stay pending until its
+/// transaction commits; a rollback takes the attachments with it.
+#[derive(Default)]
+struct StoredFiles {
+    committed: HashMap<PathBuf, String>,
+    pending: HashMap<PathBuf, String>,
+}
+
+impl StoredFiles {
+    fn get(&self, file: &Path) -> Option<&String> {
+        self.pending.get(file).or_else(|| self.committed.get(file))
+    }
+
+    /// Keep the pending ones; returns how many there were.
+    fn commit(&mut self) -> usize {
+        let count = self.pending.len();
+        self.committed.extend(self.pending.drain());
+        count
+    }
+
+    fn rollback(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn import_one(
+    conn: &mut SqliteConnection,
+    root: &Path,
+    note: &SourceNote,
+    titles: &HashMap<String, String>,
+    files_by_name: &HashMap<String, PathBuf>,
+    attachments_dir: &Path,
+    stored: &mut StoredFiles,
+    folders: &mut HashMap<String, Option<i64>>,
+    report: &mut ImportReport,
+) -> Result<(), String> {
+    let source_key = root.join(&note.path).to_string_lossy().to_string();
+    let previous: Option<(String, String)> =
+        sqlx::query_as("SELECT note_uuid, hash FROM import_sources WHERE path = $1")
+            .bind(&source_key)
+            .fetch_optional(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+
+    // The note this file became last time, or the one it was exported from
+    let uuid = previous.as_ref().map(|(uuid, _)| uuid.clone()).or_else(|| note.local_uuid.clone());
+    let existing: Option<(i64, bool, bool)> = match &uuid {
+        Some(uuid) => sqlx::query_as(
+            "SELECT id, deleted_at IS NOT NULL, locked_ciphertext IS NOT NULL FROM notes WHERE local_uuid = $1",
+        )
+        .bind(uuid)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?,
+        None => None,
+    };
+
+    match existing {
+        Some((_, true, _)) => return Err("Note is in the trash".to_string()),
+        Some((_, _, true)) => return Err("Note is locked".to_string()),
+        Some(_) if previous.as_ref().is_some_and(|(_, hash)| *hash == note.hash) => {
+            report.unchanged += 1;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let parent_dir = note.path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or_default();
+    let folder_id = ensure_folders(conn, parent_dir, folders).await?;
+    let content = convert_content(conn, root, note, titles, files_by_name, attachments_dir, stored, report).await?;
+
+    let (id, uuid) = match existing {
+        Some((id, _, _)) if same_note(conn, id, &note.title, &content, folder_id).await? => {
+            // E.g. a vault exported from this workspace
+            report.unchanged += 1;
+            (id, uuid.unwrap_or_default())
+        }
+        Some((id, _, _)) => {
+            revisions::snapshot(&mut *conn, id, "import", false)
+                .await
+                .map_err(|e| e.to_string())?;
+            sqlx::query("UPDATE notes SET title = $1, content = $2, folder_id = $3 WHERE id = $4")
+                .bind(&note.title)
+                .bind(&content)
+                .bind(folder_id)
+                .bind(id)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            report.updated += 1;
+            (id, uuid.unwrap_or_default())
+        }
+        None => {
+            // A purged note comes back as a new one
+            let uuid = note
+                .local_uuid
+                .clone()
+                .filter(|_| previous.is_none())
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            let result = sqlx::query(
+                "INSERT INTO notes (title, content, folder_id, local_uuid, created_at)
+                 VALUES ($1, $2, $3, $4, COALESCE($5, CURRENT_TIMESTAMP))",
+            )
+            .bind(&note.title)
+            .bind(&content)
+            .bind(folder_id)
+            .bind(&uuid)
+            .bind(&note.created)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+            report.created += 1;
+            (result.last_insert_rowid(), uuid)
+        }
+    };
+
+    commands::reindex(&mut *conn, id, &content).await?;
+    for tag in &note.tags {
+        // Front-matter tags become manual tags; ones Onyx can't represent are dropped
+        let _ = tags::add_manual(&mut *conn, id, tag).await;
+    }
+
+    sqlx::query(
+        "INSERT INTO import_sources (path, note_uuid, hash) VALUES ($1, $2, $3)
+         ON CONFLICT(path) DO UPDATE SET note_uuid = excluded.note_uuid, hash = excluded.hash,
+         imported_at = CURRENT_TIMESTAMP",
+    )
+    .bind(&source_key)
+    .bind(&uuid)
+    .bind(&note.hash)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+async fn same_note(
+    conn: &mut SqliteConnection,
+    id: i64,
+    title: &str,
+    content: &str,
+    folder_id: Option<i64>,
+) -> Result<bool, String> {
+    let same: (bool,) = sqlx::query_as(
+        "SELECT title = $1 AND content IS $2 AND folder_id IS $3 FROM notes WHERE id = $4",
+    )
+    .bind(title)
+    .bind(content)
+    .bind(folder_id)
+    .bind(id)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(same.0)
+}
+
+pub(crate) async fn ensure_folders(
+    conn: &mut SqliteConnection,
+    dir: &str,
+    folders: &mut HashMap<String, Option<i64>>,
+) -> Result<Option<i64>, String> {
+    if let Some(id) = folders.get(dir) {
+        return Ok(*id);
+    }
+    let (parent_dir, name) = dir.rsplit_once('/').unwrap_or(("", dir));
+    let parent_id = Box::pin(ensure_folders(conn, parent_dir, folders)).await?;
+    let id = folders::ensure_folder(conn, name, parent_id).await?;
+    folders.insert(dir.to_string(), Some(id));
+    Ok(Some(id))
+}
+
+/// Embedded files become attachments, embedded notes become links, and
+/// wiki-links are pointed at the titles the files were imported under.
+#[allow(clippy::too_many_arguments)]
+async fn convert_content(
+    conn: &mut SqliteConnection,
+    root: &Path,
+    note: &SourceNote,
+    titles: &HashMap<String, String>,
+    files_by_name: &HashMap<String, PathBuf>,
+    attachments_dir: &Path,
+    stored: &mut StoredFiles,
+    report: &mut ImportReport,
+) -> Result<String, String> {
+    let note_dir = root.join(note.path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or_default());
+    let mut content = note.body.clone();
+
+    // Back to front so earlier ranges stay valid
+    for embed in find_embeds(&note.body).into_iter().rev() {
+        let lower = strip_md(&embed.target).to_lowercase();
+        if let Some(title) = titles.get(&lower) {
+            content.replace_range(embed.range, &format!("[[{}]]", title));
+            continue;
+        }
+
+        let Some(file) = resolve_file(root, &note_dir, &embed.target, files_by_name) else {
+            report.missing_embeds.push(format!("{}: {}", note.path, embed.target));
+            continue;
+        };
+        let uri = match stored.get(&file) {
+            Some(uri) => uri.clone(),
+            None => {
+                let data = tokio::fs::read(&file).await.map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+                let name = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                let attachment = attachments::store(&mut *conn, attachments_dir, &name, &data).await?;
+                stored.pending.insert(file.clone(), attachment.uri.clone());
+                attachment.uri
+            }
+        };
+        let alt = file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        content.replace_range(embed.range, &format!("![{}]({})", alt, uri));
+    }
+
+    let converted = content.clone();
+    for link in links::parse_links(&converted).into_iter().rev() {
+        let Some(title) = titles.get(&strip_md(&link.target).to_lowercase()) else {
+            continue;
+        };
+        if *title == link.target {
+            continue;
+        }
+        let mut replacement = format!("[[{}", title);
+        if let Some(heading) = &link.heading {
+            replacement.push('#');
+            replacement.push_str(heading);
+        }
+        if let Some(alias) = &link.alias {
+            replacement.push('|');
+            replacement.push_str(alias);
+        }
+        replacement.push_str("]]");
+        content.replace_range(link.range, &replacement);
+    }
+
+    Ok(content)
+}
+
+// Relative to the note, then to the vault, then by name; never outside the vault
+fn resolve_file(root: &Path, note_dir: &Path, target: &str, files_by_name: &HashMap<String, PathBuf>) -> Option<PathBuf> {
+    let candidates = [note_dir.join(target), root.join(target)];
+    for candidate in candidates {
+        if let Ok(path) = std::fs::canonicalize(&candidate) {
+            if path.starts_with(root) && path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+    let name = Path::new(target).file_name()?.to_string_lossy().to_lowercase();
+    files_by_name.get(&name).cloned()
+}
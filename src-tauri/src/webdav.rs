@@ -0,0 +1,456 @@
+use crate::cloud_sync::{self, CloudSyncReport, Object, ObjectStore};
+use crate::{attachments, settings};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH};
+use reqwest::{Method, StatusCode, Url};
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::time::Duration;
+use tauri::{AppHandle, State};
+use zeroize::Zeroizing;
+
+// Keys sync_objects rows; there's one WebDAV remote at a time
+const PROVIDER: &str = "webdav";
+
+const URL_KEY: &str = "webdav_url";
+const USERNAME_KEY: &str = "webdav_username";
+const PASSWORD_KEY: &str = "webdav_password";
+// The derived sync key, so the passphrase isn't needed on every sync
+const SYNC_KEY_KEY: &str = "webdav_key";
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:getetag/></d:prop></d:propfind>"#;
+
+/// Held while a sync runs, so two can't interleave.
+#[derive(Default)]
+pub struct WebDavState(tokio::sync::Mutex<()>);
+
+/// WebDAV settings as shown in the app. The password is write-only.
+#[derive(Serialize)]
+pub struct WebDavConfig {
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub has_password: bool,
+    /// Whether a passphrase was entered and checked against the remote
+    pub has_key: bool,
+}
+
+/// A collection on a WebDAV server, e.g. a Nextcloud folder or `rclone serve webdav`.
+pub struct WebDav {
+    client: reqwest::Client,
+    // Always ends with `/`
+    base: Url,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl WebDav {
+    pub fn new(url: &str, username: Option<String>, password: Option<String>) -> Result<Self, String> {
+        let mut url = url.trim().to_string();
+        if !url.ends_with('/') {
+            url.push('/');
+        }
+        let base = Url::parse(&url).map_err(|e| format!("Invalid URL: {}", e))?;
+        if !matches!(base.scheme(), "http" | "https") {
+            return Err("The URL must start with http:// or https://".to_string());
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(WebDav {
+            client,
+            base,
+            username,
+            password,
+        })
+    }
+
+    fn request(&self, method: Method, name: &str) -> Result<reqwest::RequestBuilder, String> {
+        let url = self.base.join(name).map_err(|e| e.to_string())?;
+        let request = self.client.request(method, url);
+        Ok(match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        })
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, String> {
+        let response = request.send().await.map_err(|e| format!("WebDAV server unreachable: {}", e))?;
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err("The WebDAV server rejected the username or password".to_string())
+            }
+            _ => Ok(response),
+        }
+    }
+
+    /// Create the collections objects go in. Existing ones are left alone.
+    pub async fn prepare(&self) -> Result<(), String> {
+        for dir in [cloud_sync::NOTES_PREFIX, cloud_sync::ATTACHMENTS_PREFIX] {
+            let method = Method::from_bytes(b"MKCOL").map_err(|e| e.to_string())?;
+            let response = self.send(self.request(method, dir)?).await?;
+            // 405: already there
+            if !response.status().is_success() && response.status() != StatusCode::METHOD_NOT_ALLOWED {
+                return Err(format!("Failed to create {} on the server: {}", dir, response.status()));
+            }
+        }
+        Ok(())
+    }
+
+    async fn etag(&self, name: &str) -> Result<String, String> {
+        let response = self.send(self.request(Method::HEAD, name)?).await?;
+        header_etag(&response).ok_or_else(|| "The WebDAV server doesn't report ETags".to_string())
+    }
+
+    // Hrefs may be full URLs or absolute paths, and are percent-encoded
+    fn name_of(&self, href: &str) -> Option<String> {
+        let path = match Url::parse(href) {
+            Ok(url) => url.path().to_string(),
+            Err(_) => href.to_string(),
+        };
+        let path = percent_decode(&path);
+        let base = percent_decode(self.base.path());
+        path.strip_prefix(&base)
+            .filter(|name| !name.is_empty() && !name.ends_with('/'))
+            .map(str::to_string)
+    }
+}
+
+fn header_etag(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = value.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Text carried by an XML event, with entities resolved.
+pub(crate) fn event_text(event: &Event) -> Result<Option<String>, String> {
+    Ok(Some(match event {
+        Event::Text(t) => t.decode().map_err(|e| e.to_string())?.to_string(),
+        Event::CData(t) => t.decode().map_err(|e| e.to_string())?.to_string(),
+        // ETags are quoted, so servers often write them as &quot;...&quot;
+        Event::GeneralRef(r) => match r.resolve_char_ref().map_err(|e| e.to_string())? {
+            Some(c) => c.to_string(),
+            None => match r.as_ref() {
+                b"amp" => "&",
+                b"lt" => "<",
+                b"gt" => ">",
+                b"quot" => "\"",
+                b"apos" => "'",
+                _ => "",
+            }
+            .to_string(),
+        },
+        _ => return Ok(None),
+    }))
+}
+
+/// (href, ETag) of each `response` in a PROPFIND multistatus.
+fn parse_multistatus(xml: &str) -> Result<Vec<(String, String)>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut entries = Vec::new();
+    let mut href = String::new();
+    let mut etag = String::new();
+    // Local name of the element whose text is being read
+    let mut field: Option<Vec<u8>> = None;
+
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Invalid PROPFIND response: {}", e))?;
+        let text = match &event {
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                if name == b"response" {
+                    href.clear();
+                    etag.clear();
+                }
+                field = Some(name);
+                continue;
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"response" && !href.is_empty() {
+                    entries.push((href.trim().to_string(), etag.trim().to_string()));
+                }
+                field = None;
+                continue;
+            }
+            Event::Eof => break,
+            event => match event_text(event)? {
+                Some(text) => text,
+                None => continue,
+            },
+        };
+        match field.as_deref() {
+            Some(b"href") => href.push_str(&text),
+            Some(b"getetag") => etag.push_str(&text),
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+impl ObjectStore for WebDav {
+    async fn list(&self, prefix: &str) -> Result<HashMap<String, String>, String> {
+        let method = Method::from_bytes(b"PROPFIND").map_err(|e| e.to_string())?;
+        let request = self
+            .request(method, prefix)?
+            .header("Depth", "1")
+            .header(CONTENT_TYPE, "application/xml")
+            .body(PROPFIND_BODY);
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(HashMap::new());
+        }
+        if response.status() != StatusCode::MULTI_STATUS {
+            return Err(format!("Failed to list {}: {}", prefix, response.status()));
+        }
+        let xml = response.text().await.map_err(|e| e.to_string())?;
+
+        let mut objects = HashMap::new();
+        for (href, etag) in parse_multistatus(&xml)? {
+            let Some(name) = self.name_of(&href) else {
+                continue;
+            };
+            if etag.is_empty() {
+                return Err("The WebDAV server doesn't report ETags".to_string());
+            }
+            objects.insert(name, etag);
+        }
+        Ok(objects)
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<Object>, String> {
+        let response = self.send(self.request(Method::GET, name)?).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("Failed to download {}: {}", name, response.status()));
+        }
+        let etag = match header_etag(&response) {
+            Some(etag) => etag,
+            None => self.etag(name).await?,
+        };
+        let data = response.bytes().await.map_err(|e| e.to_string())?.to_vec();
+        Ok(Some(Object { data, etag }))
+    }
+
+    async fn put(&self, name: &str, data: Vec<u8>, expected: Option<&str>) -> Result<Option<String>, String> {
+        let request = self.request(Method::PUT, name)?.body(data);
+        let request = match expected {
+            Some(etag) => request.header(IF_MATCH, etag),
+            None => request.header(IF_NONE_MATCH, "*"),
+        };
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::PRECONDITION_FAILED {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("Failed to upload {}: {}", name, response.status()));
+        }
+        match header_etag(&response) {
+            Some(etag) => Ok(Some(etag)),
+            None => self.etag(name).await.map(Some),
+        }
+    }
+
+    async fn delete(&self, name: &str, expected: &str) -> Result<bool, String> {
+        let request = self.request(Method::DELETE, name)?.header(IF_MATCH, expected);
+        let response = self.send(request).await?;
+        match response.status() {
+            StatusCode::PRECONDITION_FAILED => Ok(false),
+            // Already gone is as good as deleted
+            StatusCode::NOT_FOUND => Ok(true),
+            status if status.is_success() => Ok(true),
+            status => Err(format!("Failed to delete {}: {}", name, status)),
+        }
+    }
+}
+
+async fn connect(pool: &SqlitePool) -> Result<WebDav, String> {
+    let url = settings::get(pool, URL_KEY)
+        .await?
+        .ok_or("Set up WebDAV sync first")?;
+    WebDav::new(
+        &url,
+        settings::get(pool, USERNAME_KEY).await?,
+        settings::get(pool, PASSWORD_KEY).await?,
+    )
+}
+
+// Empty clears the setting
+async fn store(pool: &SqlitePool, key: &str, value: Option<String>) -> Result<(), String> {
+    match value.filter(|v| !v.is_empty()) {
+        Some(value) => settings::set(pool, key, &value).await,
+        None => settings::remove(pool, key).await,
+    }
+}
+
+// --- Commands ---
+
+#[tauri::command]
+pub async fn get_webdav_config(pool: State<'_, SqlitePool>) -> Result<WebDavConfig, String> {
+    Ok(WebDavConfig {
+        url: settings::get(&pool, URL_KEY).await?,
+        username: settings::get(&pool, USERNAME_KEY).await?,
+        has_password: settings::get(&pool, PASSWORD_KEY).await?.is_some(),
+        has_key: settings::get(&pool, SYNC_KEY_KEY).await?.is_some(),
+    })
+}
+
+/// Connect to a WebDAV collection. Notes are encrypted with a key derived from
+/// `passphrase` before upload; every device syncing with the collection needs
+/// the same passphrase, and the server never sees it.
+#[tauri::command]
+pub async fn set_webdav_config(
+    pool: State<'_, SqlitePool>,
+    sync: State<'_, WebDavState>,
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    passphrase: String,
+) -> Result<(), String> {
+    println!("Backend: set_webdav_config: {}", url);
+    let _running = sync.0.try_lock().map_err(|_| "A sync is already running".to_string())?;
+    let passphrase = Zeroizing::new(passphrase);
+    if passphrase.is_empty() {
+        return Err("A sync passphrase is required".to_string());
+    }
+    let username = username.map(|u| u.trim().to_string()).filter(|u| !u.is_empty());
+    let webdav = WebDav::new(&url, username.clone(), password.clone())?;
+    webdav.prepare().await?;
+    let key = cloud_sync::unlock(&webdav, &passphrase).await?;
+
+    // What was synced with another collection says nothing about this one
+    let url = webdav.base.to_string();
+    if settings::get(&pool, URL_KEY).await?.as_deref() != Some(url.as_str()) {
+        let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+        cloud_sync::reset(&mut conn, PROVIDER).await?;
+    }
+    settings::set(&pool, URL_KEY, &url).await?;
+    store(&pool, USERNAME_KEY, username).await?;
+    store(&pool, PASSWORD_KEY, password).await?;
+    settings::set(&pool, SYNC_KEY_KEY, &cloud_sync::to_hex(&*key)).await
+}
+
+/// Stop syncing. Nothing is removed from the server or from this device.
+#[tauri::command]
+pub async fn disable_webdav_sync(pool: State<'_, SqlitePool>, sync: State<'_, WebDavState>) -> Result<(), String> {
+    println!("Backend: disable_webdav_sync");
+    let _running = sync.0.try_lock().map_err(|_| "A sync is already running".to_string())?;
+    for key in [URL_KEY, USERNAME_KEY, PASSWORD_KEY, SYNC_KEY_KEY] {
+        settings::remove(&pool, key).await?;
+    }
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    cloud_sync::reset(&mut conn, PROVIDER).await
+}
+
+/// Upload notes and attachments changed here, download the ones changed on
+/// the server, and follow deletes both ways.
+#[tauri::command]
+pub async fn webdav_sync(
+    app_handle: AppHandle,
+    pool: State<'_, SqlitePool>,
+    sync: State<'_, WebDavState>,
+) -> Result<CloudSyncReport, String> {
+    let _running = sync.0.try_lock().map_err(|_| "A sync is already running".to_string())?;
+    let webdav = connect(&pool).await?;
+    println!("Backend: webdav_sync: {}", webdav.base);
+    let key: Zeroizing<Vec<u8>> = Zeroizing::new(cloud_sync::from_hex(
+        &settings::get(&pool, SYNC_KEY_KEY)
+            .await?
+            .ok_or("Enter the sync passphrase first")?,
+    )?);
+    let key: &[u8; 32] = key.as_slice().try_into().map_err(|_| "Invalid sync key".to_string())?;
+
+    webdav.prepare().await?;
+    let dir = attachments::attachments_dir(&app_handle)?;
+    let mut conn = pool.acquire().await.map_err(|e| e.to_string())?;
+    let report = cloud_sync::sync(&mut conn, &webdav, key, PROVIDER, &dir).await?;
+    println!(
+        "Backend: webdav_sync: {} uploaded, {} downloaded, {} deleted, {} conflicts",
+        report.uploaded,
+        report.downloaded,
+        report.deleted,
+        report.conflicts.len()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_multistatus_reads_hrefs_and_etags() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/dav/onyx/notes/</d:href>
+    <d:propstat><d:prop><d:getetag/></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/onyx/notes/a%20b</d:href>
+    <d:propstat><d:prop><d:getetag>&quot;abc&quot;</d:getetag></d:prop></d:propstat>
+  </d:response>
+  <D:response xmlns:D="DAV:">
+    <D:href>https://example.com/dav/onyx/notes/c</D:href>
+    <D:propstat><D:prop><D:getetag><![CDATA["def"]]></D:getetag></D:prop></D:propstat>
+  </D:response>
+</d:multistatus>"#;
+        assert_eq!(
+            parse_multistatus(xml).unwrap(),
+            [
+                ("/dav/onyx/notes/".to_string(), String::new()),
+                ("/dav/onyx/notes/a%20b".to_string(), "\"abc\"".to_string()),
+                ("https://example.com/dav/onyx/notes/c".to_string(), "\"def\"".to_string()),
+            ]
+        );
+        assert!(parse_multistatus("<d:multistatus><d:response>").is_ok());
+        assert!(parse_multistatus("<a></b>").is_err());
+    }
+
+    #[test]
+    fn name_of_strips_the_base() {
+        let webdav = WebDav::new("https://example.com/dav/my%20notes", None, None).unwrap();
+        assert_eq!(webdav.name_of("/dav/my%20notes/notes/a%20b").as_deref(), Some("notes/a b"));
+        assert_eq!(
+            webdav.name_of("https://example.com/dav/my%20notes/notes/c").as_deref(),
+            Some("notes/c")
+        );
+        // The collection itself and anything outside it
+        assert_eq!(webdav.name_of("/dav/my%20notes/notes/"), None);
+        assert_eq!(webdav.name_of("/dav/my%20notes/"), None);
+        assert_eq!(webdav.name_of("/other/notes/a"), None);
+    }
+
+    #[test]
+    fn percent_decode_leaves_malformed_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%e2%82%ac"), "%zz€");
+    }
+}
@@ -0,0 +1,424 @@
+use crate::{commands, locked, revisions};
+use sqlx::{SqliteConnection, SqlitePool};
+use tauri::State;
+use yrs::block::ClientID;
+use yrs::updates::decoder::Decode;
+use yrs::updates::encoder::Encode;
+use yrs::{Doc, GetString, Map, OffsetKind, Options, ReadTxn, StateVector, Text, TextRef, Transact, TransactionMut, Update};
+
+// Shared types of a note's document, as the editor names them
+const TEXT: &str = "codemirror";
+const META: &str = "meta";
+const TITLE_KEY: &str = "title";
+// Updates kept in the log before they're folded into the snapshot
+const MAX_LOGGED_UPDATES: i64 = 100;
+
+// Yjs in the editor decodes client ids into JS numbers
+const MAX_CLIENT_ID: ClientID = (1 << 53) - 1;
+
+// Matches Yjs in the editor, which counts text positions in UTF-16 units
+fn new_doc(client_id: ClientID) -> Doc {
+    Doc::with_options(Options {
+        client_id,
+        offset_kind: OffsetKind::Utf16,
+        ..Options::default()
+    })
+}
+
+/// The client id this device writes under, taken from its clock's device id.
+/// A fresh one per load would add an entry to every note's state vector each
+/// time content changed outside the editor.
+async fn client_id(conn: &mut SqliteConnection) -> Result<ClientID, String> {
+    let device: String = sqlx::query_scalar("SELECT device FROM hlc_clock WHERE id = 1")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let id = ClientID::from_str_radix(&device, 16).map_err(|_| format!("Invalid device id: {}", device))?;
+    Ok(id & MAX_CLIENT_ID)
+}
+
+fn decode_update(data: &[u8]) -> Result<Update, String> {
+    Update::decode_v1(data).map_err(|e| format!("Invalid Yjs update: {}", e))
+}
+
+fn utf16_len(s: &str) -> u32 {
+    s.encode_utf16().count() as u32
+}
+
+// Replace only the part that differs, so concurrent edits elsewhere in the
+// text still merge
+fn replace_text(text: &TextRef, txn: &mut TransactionMut, content: &str) {
+    let current = text.get_string(txn);
+    if current == content {
+        return;
+    }
+    let prefix: String = current
+        .chars()
+        .zip(content.chars())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a)
+        .collect();
+    let (rest_current, rest_content) = (&current[prefix.len()..], &content[prefix.len()..]);
+    let suffix: String = rest_current
+        .chars()
+        .rev()
+        .zip(rest_content.chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a)
+        .collect();
+    let removed = &rest_current[..rest_current.len() - suffix.len()];
+    let inserted = &rest_content[..rest_content.len() - suffix.len()];
+    let at = utf16_len(&prefix);
+    if !removed.is_empty() {
+        text.remove_range(txn, at, utf16_len(removed));
+    }
+    if !inserted.is_empty() {
+        text.insert(txn, at, inserted);
+    }
+}
+
+/// Content a sync wrote into the note that the document hasn't caught up with.
+async fn pending(conn: &mut SqliteConnection, note_id: i64) -> Result<Option<String>, String> {
+    sqlx::query_scalar("SELECT content FROM note_yjs_pending WHERE note_id = $1")
+        .bind(note_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn settle(conn: &mut SqliteConnection, note_id: i64) -> Result<(), String> {
+    sqlx::query("DELETE FROM note_yjs_pending WHERE note_id = $1")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Record that a sync wrote `content` into the note. The device the edit
+/// came from sends it as Yjs updates too, so `load` leaves it to those rather
+/// than diffing it in under this device's id and getting the text twice.
+pub(crate) async fn synced(conn: &mut SqliteConnection, note_id: i64, content: &str) -> Result<(), String> {
+    sqlx::query(
+        "INSERT INTO note_yjs_pending (note_id, content) VALUES ($1, $2)
+         ON CONFLICT(note_id) DO UPDATE SET content = excluded.content",
+    )
+    .bind(note_id)
+    .bind(content)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Load a note's document: its snapshot with the logged updates on top.
+/// Content changed outside the editor (renames, tag edits, plain saves) is
+/// brought in as a further update, so the document matches the note. The
+/// exception is content a sync wrote (see `synced`), which the document only
+/// matches once the updates for it arrive.
+async fn load(conn: &mut SqliteConnection, note_id: i64) -> Result<Doc, String> {
+    // The plaintext of a locked note mustn't end up in its document
+    if locked::is_locked(&mut *conn, note_id).await.map_err(|e| e.to_string())? {
+        return Err("Note is locked".to_string());
+    }
+    let note: Option<(String, Option<String>)> = sqlx::query_as("SELECT title, content FROM notes WHERE id = $1")
+        .bind(note_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (title, content) = note.ok_or("Note not found")?;
+    let content = content.unwrap_or_default();
+    let pending = pending(&mut *conn, note_id).await?;
+
+    let snapshot: Option<Vec<u8>> = sqlx::query_scalar("SELECT state FROM note_snapshots WHERE note_id = $1")
+        .bind(note_id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    let updates: Vec<Vec<u8>> = sqlx::query_scalar("SELECT data FROM note_updates WHERE note_id = $1 ORDER BY id")
+        .bind(note_id)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let doc = new_doc(client_id(&mut *conn).await?);
+    let (catch_up, waiting) = {
+        let text = doc.get_or_insert_text(TEXT);
+        let meta = doc.get_or_insert_map(META);
+        let mut txn = doc.transact_mut();
+        for data in snapshot.iter().chain(&updates) {
+            txn.apply_update(decode_update(data)?).map_err(|e| e.to_string())?;
+        }
+        let before = txn.state_vector();
+
+        // Still what the sync wrote, and the updates for it haven't come
+        let waiting = pending.as_deref() == Some(content.as_str()) && text.get_string(&txn) != content;
+        if !waiting {
+            replace_text(&text, &mut txn, &content);
+        }
+        let stored_title = meta.get(&txn, TITLE_KEY).map(|v| v.to_string(&txn));
+        if stored_title.as_deref() != Some(title.as_str()) {
+            meta.insert(&mut txn, TITLE_KEY, title);
+        }
+
+        ((txn.state_vector() != before).then(|| txn.encode_state_as_update_v1(&before)), waiting)
+    };
+    if let Some(data) = catch_up {
+        append(conn, note_id, &data).await?;
+    }
+    if pending.is_some() && !waiting {
+        settle(conn, note_id).await?;
+    }
+    Ok(doc)
+}
+
+async fn append(conn: &mut SqliteConnection, note_id: i64, data: &[u8]) -> Result<(), String> {
+    sqlx::query("INSERT INTO note_updates (note_id, data) VALUES ($1, $2)")
+        .bind(note_id)
+        .bind(data)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Fold the log into the snapshot once it grows long
+async fn compact(conn: &mut SqliteConnection, note_id: i64, doc: &Doc) -> Result<(), String> {
+    let logged: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM note_updates WHERE note_id = $1")
+        .bind(note_id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    if logged <= MAX_LOGGED_UPDATES {
+        return Ok(());
+    }
+
+    let state = doc.transact().encode_state_as_update_v1(&StateVector::default());
+    sqlx::query(
+        "INSERT INTO note_snapshots (note_id, state) VALUES ($1, $2)
+         ON CONFLICT(note_id) DO UPDATE SET state = excluded.state, updated_at = CURRENT_TIMESTAMP",
+    )
+    .bind(note_id)
+    .bind(&state)
+    .execute(&mut *conn)
+    .await
+    .map_err(|e| e.to_string())?;
+    sqlx::query("DELETE FROM note_updates WHERE note_id = $1")
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    println!("Backend: Compacted {} Yjs updates of note {}", logged, note_id);
+    Ok(())
+}
+
+/// Drop a note's document, e.g. when it is locked and its plaintext has to go.
+pub(crate) async fn forget(conn: &mut SqliteConnection, note_id: i64) -> Result<(), String> {
+    for sql in [
+        "DELETE FROM note_updates WHERE note_id = $1",
+        "DELETE FROM note_snapshots WHERE note_id = $1",
+        "DELETE FROM note_yjs_pending WHERE note_id = $1",
+    ] {
+        sqlx::query(sql)
+            .bind(note_id)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Apply an update to the note's document and rewrite the note from it.
+async fn apply(conn: &mut SqliteConnection, note_id: i64, update: &[u8]) -> Result<(), String> {
+    let doc = load(&mut *conn, note_id).await?;
+    // Left after `load` only while the document is behind the note
+    let pending = pending(&mut *conn, note_id).await?;
+    let (title, content, catch_up) = {
+        let text = doc.get_or_insert_text(TEXT);
+        let meta = doc.get_or_insert_map(META);
+        let mut txn = doc.transact_mut();
+        let before = text.get_string(&txn);
+        txn.apply_update(decode_update(update)?).map_err(|e| e.to_string())?;
+        let applied = txn.state_vector();
+
+        // An update that doesn't bring what the sync wrote, e.g. a keystroke
+        // in an editor that hasn't seen it: merge the two rather than let
+        // the document's older text win. On overlap the document wins; the
+        // synced text stays in the note's history.
+        if let Some(synced) = &pending {
+            let current = text.get_string(&txn);
+            if let Ok(merged) = diffy::merge(&before, &current, synced) {
+                replace_text(&text, &mut txn, &merged);
+            }
+        }
+        let title = meta.get(&txn, TITLE_KEY).map(|v| v.to_string(&txn));
+        let catch_up = (txn.state_vector() != applied).then(|| txn.encode_state_as_update_v1(&applied));
+        (title, text.get_string(&txn), catch_up)
+    };
+    append(conn, note_id, update).await?;
+    if let Some(data) = catch_up {
+        append(conn, note_id, &data).await?;
+    }
+    if pending.is_some() {
+        settle(conn, note_id).await?;
+    }
+
+    revisions::snapshot(conn, note_id, "edit", true)
+        .await
+        .map_err(|e| e.to_string())?;
+    sqlx::query("UPDATE notes SET title = COALESCE($1, title), content = $2 WHERE id = $3")
+        .bind(title)
+        .bind(&content)
+        .bind(note_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| e.to_string())?;
+    commands::reindex(conn, note_id, &content).await?;
+    compact(conn, note_id, &doc).await
+}
+
+// --- Commands ---
+
+/// Apply a Yjs update (v1 encoding) from the editor or another device. It is
+/// logged, and the note's title and content are rewritten from the document
+/// for search, export and the other sync methods.
+#[tauri::command]
+pub async fn apply_yjs_update(pool: State<'_, SqlitePool>, note_id: i64, update: Vec<u8>) -> Result<(), String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    apply(&mut tx, note_id, &update).await?;
+    tx.commit().await.map_err(|e| e.to_string())
+}
+
+/// The note's Yjs state vector (v1 encoding), to send to a peer so it can
+/// answer with only what this device is missing.
+#[tauri::command]
+pub async fn get_yjs_state_vector(pool: State<'_, SqlitePool>, note_id: i64) -> Result<Vec<u8>, String> {
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let doc = load(&mut tx, note_id).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    let state_vector = doc.transact().state_vector().encode_v1();
+    Ok(state_vector)
+}
+
+/// What a peer with `state_vector` is missing, as one Yjs update (v1
+/// encoding). Without a state vector, the whole document.
+#[tauri::command]
+pub async fn get_yjs_diff(
+    pool: State<'_, SqlitePool>,
+    note_id: i64,
+    state_vector: Option<Vec<u8>>,
+) -> Result<Vec<u8>, String> {
+    let state_vector = match state_vector {
+        Some(data) => StateVector::decode_v1(&data).map_err(|e| format!("Invalid state vector: {}", e))?,
+        None => StateVector::default(),
+    };
+    let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+    let doc = load(&mut tx, note_id).await?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    let diff = doc.transact().encode_state_as_update_v1(&state_vector);
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    async fn text_of(conn: &mut SqliteConnection, note_id: i64) -> String {
+        let doc = load(conn, note_id).await.unwrap();
+        let text = doc.get_or_insert_text(TEXT);
+        let txn = doc.transact();
+        text.get_string(&txn)
+    }
+
+    async fn content_of(conn: &mut SqliteConnection, note_id: i64) -> String {
+        sqlx::query_scalar("SELECT content FROM notes WHERE id = $1")
+            .bind(note_id)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+    }
+
+    /// Another device's copy of the note's document, as this one has it now.
+    async fn peer(conn: &mut SqliteConnection, note_id: i64, client_id: ClientID) -> Doc {
+        let state = load(conn, note_id)
+            .await
+            .unwrap()
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default());
+        let doc = new_doc(client_id);
+        doc.get_or_insert_text(TEXT);
+        doc.transact_mut().apply_update(decode_update(&state).unwrap()).unwrap();
+        doc
+    }
+
+    // The update for inserting `chunk` at `at`
+    fn insert(doc: &Doc, at: u32, chunk: &str) -> Vec<u8> {
+        let text = doc.get_or_insert_text(TEXT);
+        let before = doc.transact().state_vector();
+        text.insert(&mut doc.transact_mut(), at, chunk);
+        doc.transact().encode_state_as_update_v1(&before)
+    }
+
+    // What `import_note_from_pb` and the other syncs do with a note's content
+    async fn sync_write(conn: &mut SqliteConnection, note_id: i64, content: &str) {
+        sqlx::query("UPDATE notes SET content = $1 WHERE id = $2")
+            .bind(content)
+            .bind(note_id)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        synced(conn, note_id, content).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn an_edit_from_sync_and_from_yjs_lands_once() {
+        let db = test_support::database().await;
+        let id = test_support::add_note(&db.pool, "Groceries", "milk").await;
+        let mut conn = db.pool.acquire().await.unwrap();
+        let other = peer(&mut conn, id, 42).await;
+        let update = insert(&other, 4, ", eggs");
+
+        // The plain copy comes first; the document waits for the update
+        sync_write(&mut conn, id, "milk, eggs").await;
+        assert_eq!(text_of(&mut conn, id).await, "milk");
+        apply(&mut conn, id, &update).await.unwrap();
+        assert_eq!(text_of(&mut conn, id).await, "milk, eggs");
+        assert_eq!(content_of(&mut conn, id).await, "milk, eggs");
+
+        // And the other way round
+        let update = insert(&other, 10, ", bread");
+        apply(&mut conn, id, &update).await.unwrap();
+        sync_write(&mut conn, id, "milk, eggs, bread").await;
+        assert_eq!(text_of(&mut conn, id).await, "milk, eggs, bread");
+        assert_eq!(pending(&mut conn, id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn an_update_without_the_synced_edit_keeps_both() {
+        let db = test_support::database().await;
+        let id = test_support::add_note(&db.pool, "Groceries", "milk\neggs\n").await;
+        let mut conn = db.pool.acquire().await.unwrap();
+        let editor = peer(&mut conn, id, 7).await;
+
+        sync_write(&mut conn, id, "milk\neggs\nbread\n").await;
+        apply(&mut conn, id, &insert(&editor, 0, "butter\n")).await.unwrap();
+        assert_eq!(content_of(&mut conn, id).await, "butter\nmilk\neggs\nbread\n");
+        assert_eq!(text_of(&mut conn, id).await, "butter\nmilk\neggs\nbread\n");
+    }
+
+    #[tokio::test]
+    async fn plain_saves_are_caught_up() {
+        let db = test_support::database().await;
+        let id = test_support::add_note(&db.pool, "Groceries", "milk").await;
+        let mut conn = db.pool.acquire().await.unwrap();
+        assert_eq!(text_of(&mut conn, id).await, "milk");
+
+        sqlx::query("UPDATE notes SET content = 'oat milk' WHERE id = $1")
+            .bind(id)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        assert_eq!(text_of(&mut conn, id).await, "oat milk");
+    }
+}